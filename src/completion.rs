@@ -0,0 +1,347 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Code completion without type inference: the keywords valid at a position, and the names visible there.
+//!
+
+use source_model::*;
+use source_lexer::{ lex_all_tokens, file_offset };
+use name_resolution::{ visible_names, DefinitionKind, VisibleNames };
+use fragment::ParseTarget;
+use parse_describe::{ analyse_source_recovering, source_line_index };
+
+use syntex_syntax::parse::token::{ self, Token, DelimToken };
+use syntex_syntax::parse::token::keywords;
+use syntex_syntax::parse::lexer::TokenAndSpan;
+use syntex_syntax::codemap::CodeMap;
+
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Keyword,
+    Definition(DefinitionKind),
+}
+
+impl CompletionKind {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            CompletionKind::Keyword => "Keyword",
+            CompletionKind::Definition(kind) => kind.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionItem {
+    pub label : String,
+    pub kind : CompletionKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completions {
+    /// The range of the name being typed at the position, which a completion replaces. It's empty if no name
+    /// is being typed.
+    pub sourcerange : SourceRange,
+    /// The visible names, from the innermost scope, then the keywords.
+    pub items : Vec<CompletionItem>,
+}
+
+const ITEM_KEYWORDS : &'static [&'static str] = &[
+    "const", "enum", "extern", "fn", "impl", "mod", "pub", "static", "struct", "trait", "type", "unsafe", "use",
+];
+/// The keywords that can follow `pub`: an `impl` has no visibility.
+const VISIBLE_ITEM_KEYWORDS : &'static [&'static str] = &[
+    "const", "enum", "extern", "fn", "mod", "static", "struct", "trait", "type", "unsafe", "use",
+];
+const STATEMENT_KEYWORDS : &'static [&'static str] = &[
+    "let", "const", "enum", "fn", "impl", "mod", "static", "struct", "trait", "type", "use",
+];
+const EXPRESSION_KEYWORDS : &'static [&'static str] = &[
+    "break", "continue", "false", "for", "if", "loop", "match", "move", "return", "self", "true", "unsafe", "while",
+];
+const TYPE_KEYWORDS : &'static [&'static str] = &["Self", "extern", "fn", "unsafe"];
+const BINDING_KEYWORDS : &'static [&'static str] = &["mut", "ref"];
+const LIFETIME_KEYWORDS : &'static [&'static str] = &["'static"];
+
+/// What can be written at a position, as guessed from the tokens before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompletionContext {
+    /// The start of an item, outside of a function body.
+    Item,
+    /// An item after its visibility, `pub`.
+    VisibleItem,
+    /// The start of a statement in a function body.
+    Statement,
+    Expression,
+    Type,
+    /// The pattern of a `let` statement.
+    Binding,
+    Lifetime,
+    /// The label of a `break` or `continue` expression.
+    Label,
+    /// The name of a declaration, a field or method name, a path member, or inside a comment or a literal.
+    /// Nothing is offered, since these can't be known without type inference.
+    Nothing,
+}
+
+/// The completions at given position. The source doesn't need to parse: the incomplete statement at the
+/// position is skipped, as with `analyse_source_recovering`.
+pub fn completions(source : &str, target : ParseTarget, pos : &LineColumnPosition)
+    -> (Vec<SourceMessage>, Completions)
+{
+    let line_index = source_line_index(source);
+    let offset = line_index.offset_at(pos);
+
+    let position = *pos;
+    let (messages, names) = analyse_source_recovering(source, target, pos, move |parsed| {
+        visible_names(parsed, &position)
+    });
+    let names = names.unwrap_or(VisibleNames { definitions : vec![], in_body : false });
+
+    let offset = match offset {
+        Some(offset) => offset,
+        None => {
+            let sourcerange = SourceRange { start_pos : *pos, end_pos : *pos };
+            return (messages, Completions { sourcerange : sourcerange, items : vec![] });
+        }
+    };
+    let codemap = Rc::new(CodeMap::new());
+    let tokens = lex_all_tokens(codemap.clone(), line_index.source());
+    let (prefix_start, context) = completion_context(&codemap, &tokens, offset, names.in_body);
+    let prefix = &line_index.source()[prefix_start..offset];
+
+    let mut items : Vec<CompletionItem> = names.definitions.iter()
+        .filter(|definition| offers_definition(context, definition.kind))
+        .map(|definition| CompletionItem {
+            label : definition.name.clone(), kind : CompletionKind::Definition(definition.kind)
+        })
+        .collect();
+    for keyword in context_keywords(context) {
+        // The `self` parameter is already offered
+        if !items.iter().any(|item| item.label == keyword) {
+            items.push(CompletionItem { label : String::from(keyword), kind : CompletionKind::Keyword });
+        }
+    }
+    items.retain(|item| item.label.starts_with(prefix));
+
+    (messages, Completions { sourcerange : line_index.range_at(prefix_start, offset), items : items })
+}
+
+fn context_keywords(context : CompletionContext) -> Vec<&'static str> {
+    match context {
+        CompletionContext::Item => ITEM_KEYWORDS.to_vec(),
+        CompletionContext::VisibleItem => VISIBLE_ITEM_KEYWORDS.to_vec(),
+        CompletionContext::Statement => STATEMENT_KEYWORDS.iter().chain(EXPRESSION_KEYWORDS).cloned().collect(),
+        CompletionContext::Expression => EXPRESSION_KEYWORDS.to_vec(),
+        CompletionContext::Type => TYPE_KEYWORDS.to_vec(),
+        CompletionContext::Binding => BINDING_KEYWORDS.to_vec(),
+        CompletionContext::Lifetime => LIFETIME_KEYWORDS.to_vec(),
+        CompletionContext::Label | CompletionContext::Nothing => vec![],
+    }
+}
+
+fn offers_definition(context : CompletionContext, kind : DefinitionKind) -> bool {
+    use source_model::StructureElementKind::*;
+
+    match context {
+        CompletionContext::Statement | CompletionContext::Expression => {
+            kind != DefinitionKind::Lifetime && kind != DefinitionKind::Label
+        }
+        CompletionContext::Type => match kind {
+            DefinitionKind::GenericParam => true,
+            DefinitionKind::Item(kind) => match kind {
+                Struct | Union | Enum | Trait | TypeAlias | Mod | ExternCrate | Use => true,
+                _ => false,
+            },
+            _ => false,
+        },
+        CompletionContext::Lifetime => kind == DefinitionKind::Lifetime,
+        CompletionContext::Label => kind == DefinitionKind::Label,
+        CompletionContext::Item | CompletionContext::VisibleItem | CompletionContext::Binding |
+        CompletionContext::Nothing => false,
+    }
+}
+
+/// The start offset of the name being typed at given offset, and the completion context there.
+fn completion_context(codemap : &CodeMap, tokens : &[TokenAndSpan], offset : usize, in_body : bool)
+    -> (usize, CompletionContext)
+{
+    let offsets = |token : &TokenAndSpan| (file_offset(codemap, token.sp.lo), file_offset(codemap, token.sp.hi));
+
+    let mut prefix_start = offset;
+    let mut prefix_tok = None;
+    for token in tokens {
+        let (start, end) = offsets(token);
+        match token.tok {
+            token::Comment | token::DocComment(_) if start < offset && offset < end => {
+                return (offset, CompletionContext::Nothing);
+            }
+            // A line comment ends before the line break
+            token::Comment | token::DocComment(_) if end == offset && is_line_comment(codemap, token) => {
+                return (offset, CompletionContext::Nothing);
+            }
+            token::Literal(..) if start < offset && offset <= end => {
+                return (offset, CompletionContext::Nothing);
+            }
+            token::Ident(_) | token::Lifetime(_) if start < offset && offset <= end => {
+                prefix_start = start;
+                prefix_tok = Some(token.tok.clone());
+            }
+            _ => { }
+        }
+    }
+
+    let previous : Vec<&TokenAndSpan> = tokens.iter()
+        .filter(|token| match token.tok {
+            token::Whitespace | token::Comment | token::DocComment(_) | token::Shebang(_) => false,
+            _ => offsets(*token).1 <= prefix_start,
+        })
+        .collect();
+    let previous_tok = previous.last().map(|token| &token.tok);
+
+    if let Some(token::Lifetime(_)) = prefix_tok {
+        let after_jump = previous_tok.map_or(false, |tok| {
+            tok.is_keyword(keywords::Break) || tok.is_keyword(keywords::Continue)
+        });
+        return (prefix_start, if after_jump { CompletionContext::Label } else { CompletionContext::Lifetime });
+    }
+    let context = match previous_tok {
+        None | Some(&token::Semi) | Some(&token::OpenDelim(DelimToken::Brace)) |
+        Some(&token::CloseDelim(DelimToken::Brace)) => {
+            if in_body { CompletionContext::Statement } else { CompletionContext::Item }
+        }
+        Some(&token::CloseDelim(DelimToken::Bracket)) if !in_body => CompletionContext::Item,
+        Some(&token::Dot) | Some(&token::ModSep) => CompletionContext::Nothing,
+        Some(&token::RArrow) => CompletionContext::Type,
+        Some(&token::Colon) => {
+            // In a function body, a colon is also that of a field in a struct expression
+            let (statement_start, enclosing_delim) = enclosing_statement(&previous);
+            let in_let = statement_start.map_or(false, |tok| tok.is_keyword(keywords::Let));
+            if !in_body || in_let || enclosing_delim != Some(DelimToken::Brace) {
+                CompletionContext::Type
+            } else {
+                CompletionContext::Expression
+            }
+        }
+        Some(&token::Eq) if !in_body => CompletionContext::Expression,
+        Some(tok) if is_any_keyword(tok, &[keywords::Fn, keywords::Struct, keywords::Enum, keywords::Trait,
+            keywords::Type, keywords::Mod, keywords::Crate]) => CompletionContext::Nothing,
+        Some(tok) if tok.is_keyword(keywords::Let) => CompletionContext::Binding,
+        Some(tok) if tok.is_keyword(keywords::Pub) && !in_body => CompletionContext::VisibleItem,
+        Some(tok) if is_any_keyword(tok, &[keywords::As, keywords::Impl]) => CompletionContext::Type,
+        _ => if in_body { CompletionContext::Expression } else { CompletionContext::Type },
+    };
+    (prefix_start, context)
+}
+
+fn is_line_comment(codemap : &CodeMap, token : &TokenAndSpan) -> bool {
+    codemap.span_to_snippet(token.sp).map_or(false, |snippet| snippet.starts_with("//"))
+}
+
+fn is_any_keyword(tok : &Token, candidates : &[keywords::Keyword]) -> bool {
+    candidates.iter().any(|&keyword| tok.is_keyword(keyword))
+}
+
+/// The first token of the statement that ends with given tokens, and the innermost delimiter enclosing it.
+fn enclosing_statement<'a>(tokens : &[&'a TokenAndSpan]) -> (Option<&'a Token>, Option<DelimToken>) {
+    let mut depth = 0;
+    let mut enclosing_delim = None;
+    let mut statement_start = None;
+
+    for &token in tokens.iter().rev() {
+        match token.tok {
+            token::CloseDelim(_) => depth += 1,
+            token::OpenDelim(_) if depth > 0 => depth -= 1,
+            token::OpenDelim(DelimToken::Brace) | token::Semi if depth == 0 => {
+                enclosing_delim = enclosing_delim.or(Some(DelimToken::Brace));
+                break;
+            }
+            token::OpenDelim(delim) if enclosing_delim.is_none() => enclosing_delim = Some(delim),
+            _ => { }
+        }
+        statement_start = Some(&token.tok);
+    }
+    (statement_start, enclosing_delim)
+}
+
+
+#[test]
+fn test_completions() {
+
+    // The position is marked with `$`
+    fn complete(source : &str) -> Vec<String> {
+        let offset = source.find('$').unwrap();
+        let source = source.replace("$", "");
+        let pos = LineIndex::new(&source).position_at(offset);
+
+        completions(&source, ParseTarget::Crate, &pos).1.items.into_iter().map(|item| {
+            format!("{} {}", item.kind.to_string(), item.label)
+        }).collect()
+    }
+    fn labels(source : &str) -> Vec<String> {
+        let offset = source.find('$').unwrap();
+        let source = source.replace("$", "");
+        let pos = LineIndex::new(&source).position_at(offset);
+
+        completions(&source, ParseTarget::Crate, &pos).1.items.into_iter().map(|item| item.label).collect()
+    }
+    fn strings(strings : &[&str]) -> Vec<String> {
+        strings.iter().map(|string| string.to_string()).collect()
+    }
+
+    let source = "use std::fmt;\nstruct Point;\nfn main<T>(count : u32) {\n  let total = 1;\n  { let inner = 2; }\n  \
+        to$\n}";
+    assert_eq!(complete(source), strings(&["Local total"]));
+    let source = "use std::fmt;\nstruct Point;\nfn main<T>(count : u32) {\n  let total = 1;\n  { let inner = 2; }\n  $\n}";
+    assert_eq!(labels(source)[..6].to_vec(), strings(&["total", "count", "T", "main", "Point", "fmt"]));
+    assert_eq!(complete(source)[2..6].to_vec(), strings(&["GenericParam T", "Function main", "Struct Point", "Use fmt"]));
+    assert!(labels(source).contains(&String::from("let")));
+    assert!(!labels(source).contains(&String::from("inner")));
+
+    // Incomplete statements
+    let source = "fn main(count : u32) {\n  let total = count;\n  let x = co$\n}\nfn other() {}";
+    assert_eq!(complete(source), strings(&["Param count", "Keyword continue"]));
+    let source = "fn main(count : u32) {\n  let total = count;\n  other(t$\n}\nfn other(x : u32) {}";
+    assert_eq!(complete(source), strings(&["Local total", "Keyword true"]));
+    // The local is not visible in its own initializer
+    assert_eq!(labels("fn main() { let total = to$; }"), strings(&[]));
+
+    // Imports have the kind of the imported item
+    let source = "mod shapes { pub struct Circle; }\nuse shapes::Circle;\nfn main() { let c = Ci$ }";
+    assert_eq!(complete(source), strings(&["Struct Circle"]));
+
+    // Types, lifetimes and labels
+    let source = "struct Point<'a, T> { x : &'a T, y : $ }\nfn point() {}";
+    assert_eq!(labels(source), strings(&["T", "Point", "Self", "extern", "fn", "unsafe"]));
+    assert_eq!(labels("struct Point<'a, T> { x : &'$a T }"), strings(&["'a", "'static"]));
+    assert_eq!(labels("fn main() { 'outer: loop { 'inner: loop { break 'o$ } } }"), strings(&["'outer"]));
+    assert_eq!(labels("fn main() { let x : $ }\nstruct Point;\nconst ORIGIN : u32 = 0;"),
+        strings(&["Point", "Self", "extern", "fn", "unsafe"]));
+
+    // Items
+    assert_eq!(labels("struct Point;\nst$"), strings(&["static", "struct"]));
+    assert_eq!(labels("pub $"), strings(&VISIBLE_ITEM_KEYWORDS));
+    assert!(!labels("pub $").contains(&String::from("pub")));
+    assert_eq!(labels("fn main() { let $ }"), strings(&["mut", "ref"]));
+
+    // Nothing is known about fields, methods and members, nor offered in comments and literals
+    assert_eq!(labels("struct Point;\nfn main(p : Point) { p.$ }"), strings(&[]));
+    assert_eq!(labels("struct Point;\nfn main() { Point::$ }"), strings(&[]));
+    assert_eq!(labels("struct Point;\nfn main() { let s = \"P$\"; }"), strings(&[]));
+    assert_eq!(labels("struct Point;\nfn main() { /* P$ */ }"), strings(&[]));
+    assert_eq!(labels("struct Point;\nfn main() {\n  // P$\n}"), strings(&[]));
+    assert_eq!(labels("struct Point;\nfn ma$() { }"), strings(&[]));
+}
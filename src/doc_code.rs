@@ -0,0 +1,318 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Validation of the Rust code blocks in doc comments and Markdown files.
+//!
+
+use source_model::*;
+use fragment::ParseTarget;
+use parse_describe::{ analyse_source_as, source_line_index };
+
+use syntex_syntax::codemap::CharPos;
+
+use std::cmp::min;
+
+/// The kind of document the code blocks are extracted from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocSourceKind {
+    /// A Rust source file: the code blocks are in its `///` and `//!` doc comments.
+    Rust,
+    Markdown,
+}
+
+impl DocSourceKind {
+    pub fn from_string(string : &str) -> Option<DocSourceKind> {
+        match string {
+            "rust" => Some(DocSourceKind::Rust),
+            "markdown" => Some(DocSourceKind::Markdown),
+            _ => None,
+        }
+    }
+}
+
+/// A fenced Rust code block.
+#[derive(Debug, Clone)]
+pub struct DocCodeBlock {
+    /// The range of the whole block, including the fences.
+    pub sourcerange : SourceRange,
+    /// The info string after the opening fence, such as `rust,no_run`.
+    pub info : String,
+    /// Whether the block was parsed. `ignore` and `compile_fail` blocks are not.
+    pub checked : bool,
+}
+
+/// A line of the document, and the offset of its text in the source.
+#[derive(Debug, Clone, Copy)]
+struct DocLine<'a> {
+    text : &'a str,
+    offset : usize,
+}
+
+/// Parse the Rust code blocks of given source, and report their parse messages, with ranges in the source.
+/// Hidden lines (starting with `# `) are parsed as well.
+pub fn check_doc_code_blocks(line_index : &LineIndex, kind : DocSourceKind) 
+    -> (Vec<SourceMessage>, Vec<DocCodeBlock>) 
+{
+    let mut messages = vec![];
+    let mut blocks = vec![];
+
+    for doc_lines in doc_line_runs(line_index, kind) {
+        let mut ix = 0;
+        while ix < doc_lines.len() {
+            let (fence, info) = match fence_start(doc_lines[ix].text) {
+                Some(fence_and_info) => fence_and_info,
+                None => {
+                    ix += 1;
+                    continue;
+                }
+            };
+
+            // An unclosed block extends to the end of the doc comment
+            let (code_end_ix, end_ix) = 
+                match (ix + 1 .. doc_lines.len()).find(|&end_ix| is_fence_end(doc_lines[end_ix].text, fence)) {
+                    Some(end_ix) => (end_ix, end_ix),
+                    None => (doc_lines.len(), doc_lines.len() - 1),
+                };
+            let code_lines = &doc_lines[ix + 1 .. code_end_ix];
+            let last_line = doc_lines[end_ix];
+
+            if let Some(checked) = rust_block_checked(info, kind) {
+                let sourcerange = line_index.range_at(doc_lines[ix].offset, last_line.offset + last_line.text.len());
+                if checked {
+                    messages.extend(check_code_block(line_index, code_lines, &sourcerange));
+                }
+                blocks.push(DocCodeBlock { sourcerange : sourcerange, info : info.to_string(), checked : checked });
+            }
+
+            ix = end_ix + 1;
+        }
+    }
+
+    (messages, blocks)
+}
+
+/// The runs of consecutive doc comment lines of a Rust source (with the comment prefix removed),
+/// or all the lines of a Markdown source.
+fn doc_line_runs<'a>(line_index : &'a LineIndex, kind : DocSourceKind) -> Vec<Vec<DocLine<'a>>> {
+    let mut runs = vec![];
+    let mut run : Vec<DocLine> = vec![];
+    let mut run_prefix = "";
+
+    for line_ix in 0..line_index.line_count() {
+        let text = line_index.line_text(line_ix);
+        let offset = line_index.offset_at(&LineColumnPosition { line : line_ix + 1, col : CharPos(0) }).unwrap();
+
+        if kind == DocSourceKind::Markdown {
+            run.push(DocLine { text : text, offset : offset });
+            continue;
+        }
+
+        let trimmed = text.trim_left();
+        let prefix = if trimmed.starts_with("///") && !trimmed.starts_with("////") {
+            "///"
+        } else if trimmed.starts_with("//!") {
+            "//!"
+        } else {
+            ""
+        };
+
+        if prefix != run_prefix && !run.is_empty() {
+            runs.push(run);
+            run = vec![];
+        }
+        run_prefix = prefix;
+
+        if prefix != "" {
+            let mut content_offset = text.len() - trimmed.len() + prefix.len();
+            if text[content_offset..].starts_with(" ") {
+                content_offset += 1;
+            }
+            run.push(DocLine { text : &text[content_offset..], offset : offset + content_offset });
+        }
+    }
+    if !run.is_empty() {
+        runs.push(run);
+    }
+    runs
+}
+
+/// If given line opens a code fence, the fence (for example "```") and the info string.
+fn fence_start(text : &str) -> Option<(&str, &str)> {
+    let trimmed = text.trim_left_matches(' ');
+    if text.len() - trimmed.len() > 3 {
+        return None;
+    }
+
+    for fence_char in &['`', '~'] {
+        let fence_len = trimmed.len() - trimmed.trim_left_matches(*fence_char).len();
+        if fence_len >= 3 {
+            let info = trimmed[fence_len..].trim();
+            if *fence_char == '`' && info.contains('`') {
+                return None;
+            }
+            return Some((&trimmed[..fence_len], info));
+        }
+    }
+    None
+}
+
+fn is_fence_end(text : &str, fence : &str) -> bool {
+    let trimmed = text.trim_left_matches(' ');
+    if text.len() - trimmed.len() > 3 {
+        return false;
+    }
+    let fence_char = fence.chars().next().unwrap();
+    let rest = trimmed.trim_left_matches(fence_char);
+    trimmed.len() - rest.len() >= fence.len() && rest.trim().is_empty()
+}
+
+/// None if given code block info string is not of a Rust code block, otherwise whether the block should be
+/// checked. A block marked `rust` is Rust code, whatever its other attributes. In doc comments, so is a block 
+/// with no other language tag (any unknown attribute, such as `text`), while in Markdown it must be marked `rust`.
+fn rust_block_checked(info : &str, kind : DocSourceKind) -> Option<bool> {
+    let mut has_rust_tag = false;
+    let mut has_other_tag = false;
+    let mut checked = true;
+
+    for attr in info.split(|ch : char| ch == ',' || ch.is_whitespace()).filter(|attr| !attr.is_empty()) {
+        match attr {
+            "rust" => has_rust_tag = true,
+            "ignore" | "compile_fail" => checked = false,
+            "no_run" | "should_panic" | "test_harness" | "allow_fail" => { }
+            _ if attr.starts_with("ignore-") => checked = false,
+            _ if attr.starts_with("edition") => { }
+            _ => has_other_tag = true,
+        }
+    }
+
+    let is_rust = has_rust_tag || (kind == DocSourceKind::Rust && !has_other_tag);
+    if is_rust { Some(checked) } else { None }
+}
+
+/// Parse the code of given lines, and map the messages back to the source.
+fn check_code_block(line_index : &LineIndex, code_lines : &[DocLine], block_range : &SourceRange)
+    -> Vec<SourceMessage>
+{
+    let mut code = String::new();
+    // The offset in the code and in the source, and the length, of each code line
+    let mut line_offsets = vec![];
+
+    for line in code_lines {
+        let (text, offset) = unhide_line(line);
+        line_offsets.push((code.len(), offset, text.len()));
+        code.push_str(text);
+        code.push_str("\n");
+    }
+
+    // rustdoc wraps the code in a main function unless it declares one
+    let target = if code.contains("fn main") { ParseTarget::Crate } else { ParseTarget::Statements };
+    let (messages, _) = analyse_source_as(&code, target, |_| ());
+    let code_line_index = source_line_index(&code);
+
+    let source_offset = |code_offset : usize| -> usize {
+        let line_ix = match line_offsets.binary_search_by(|&(code_start, _, _)| code_start.cmp(&code_offset)) {
+            Ok(line_ix) => line_ix,
+            Err(next_line_ix) => next_line_ix - 1,
+        };
+        let (code_start, source_start, len) = line_offsets[line_ix];
+        source_start + min(code_offset - code_start, len)
+    };
+
+    messages.into_iter().map(|message| {
+        let code_offsets = message.sourcerange.and_then(|range| code_line_index.range_offsets(&range));
+        let sourcerange = match code_offsets {
+            Some((start, end)) if !line_offsets.is_empty() => {
+                line_index.range_at(source_offset(start), source_offset(end))
+            }
+            _ => *block_range,
+        };
+        SourceMessage { sourcerange : Some(sourcerange), .. message }
+    }).collect()
+}
+
+/// The code text of given code line, and its offset in the source. A hidden line `# code` is `code`,
+/// and `##` is an escaped `#`.
+fn unhide_line<'a>(line : &DocLine<'a>) -> (&'a str, usize) {
+    let text = line.text;
+    let trimmed = text.trim_left();
+    let indent = text.len() - trimmed.len();
+
+    let hidden_prefix_len = if trimmed == "#" {
+        1
+    } else if trimmed.starts_with("##") {
+        1
+    } else if trimmed.starts_with("# ") || trimmed.starts_with("#\t") {
+        2
+    } else {
+        return (text, line.offset);
+    };
+
+    let code_start = indent + hidden_prefix_len;
+    (&text[code_start..], line.offset + code_start)
+}
+
+
+#[test]
+fn test_doc_code_blocks() {
+
+    fn check(source : &str, kind : DocSourceKind) -> (Vec<(Option<SourceRange>, String)>, Vec<(SourceRange, bool)>) {
+        let line_index = LineIndex::new(source);
+        let (messages, blocks) = check_doc_code_blocks(&line_index, kind);
+        (
+            messages.into_iter().map(|msg| (msg.sourcerange, msg.message)).collect(),
+            blocks.into_iter().map(|block| (block.sourcerange, block.checked)).collect(),
+        )
+    }
+
+    let source = r#"
+/// Foo.
+///
+/// ```
+/// # let x = 1;
+/// let y = x +;
+/// ```
+///
+/// ```text
+/// not rust
+/// ```
+/// ```ignore
+/// not rust
+/// ```
+fn foo() {}
+//! ```rust,no_run
+//! fn main() { }
+//! ```
+//! ```rust,custom
+//! let z = 2;
+//! ```
+"#;
+    let (messages, blocks) = check(source, DocSourceKind::Rust);
+    assert_eq!(messages, vec![
+        (Some(source_range(6, 15, 6, 16)), "expected expression, found `;`".to_string()),
+    ]);
+    assert_eq!(blocks, vec![
+        (source_range(4, 4, 7, 7), true),
+        (source_range(12, 4, 14, 7), false),
+        (source_range(16, 4, 18, 7), true),
+        (source_range(19, 4, 21, 7), true),
+    ]);
+
+    let source = "Text\n\n```\nnot rust\n```\n\n  ~~~~ rust\n  let x = ;\n  ~~~~\n";
+    let (messages, blocks) = check(source, DocSourceKind::Markdown);
+    assert_eq!(messages, vec![
+        (Some(source_range(8, 10, 8, 11)), "expected expression, found `;`".to_string()),
+    ]);
+    assert_eq!(blocks, vec![(source_range(7, 0, 9, 6), true)]);
+}
@@ -0,0 +1,72 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Queries over a structure element tree.
+//!
+
+use source_model::*;
+
+/// Find the elements enclosing given position, from the top-level element down to the innermost one.
+/// Returns an empty path if no element encloses the position.
+pub fn find_element_path<'a>(elements : &'a [StructureElement], pos : &LineColumnPosition) 
+    -> Vec<&'a StructureElement> 
+{
+    let mut path = vec![];
+    let mut elements = elements;
+    
+    while let Some(element) = elements.iter().find(|element| element.sourcerange.contains(pos)) {
+        path.push(element);
+        elements = &element.children;
+    }
+    
+    path
+}
+
+/// Find the innermost element enclosing given position.
+pub fn find_element_at<'a>(elements : &'a [StructureElement], pos : &LineColumnPosition) 
+    -> Option<&'a StructureElement> 
+{
+    find_element_path(elements, pos).pop()
+}
+
+
+#[test]
+fn test_find_element_path() {
+    use parse_describe::parse_crate_with_messages;
+    use syntex_syntax::codemap::CharPos;
+    
+    fn check_path(source : &str, line : usize, col : usize, expected : &[&str]) {
+        let (_, elements) = parse_crate_with_messages(source);
+        let pos = LineColumnPosition { line : line + 1, col : CharPos(col) };
+        
+        let path : Vec<&str> = find_element_path(&elements, &pos).iter()
+            .map(|element| &element.name as &str).collect();
+        assert_eq!(path, expected);
+    }
+    
+    let source = "fn foo() { }\nmod bar {\n  struct Xpto { field : u32 }\n\n}\n";
+    check_path(source, 0, 0, &["foo"]);
+    check_path(source, 0, 5, &["foo"]);
+    check_path(source, 0, 12, &["foo"]);
+    check_path(source, 1, 2, &["bar"]);
+    check_path(source, 2, 2, &["bar", "Xpto"]);
+    check_path(source, 2, 18, &["bar", "Xpto", "field"]);
+    check_path(source, 3, 0, &["bar"]);
+    check_path(source, 5, 0, &[]);
+    
+    let (_, elements) = parse_crate_with_messages(source);
+    let pos = LineColumnPosition { line : 3, col : CharPos(2) };
+    assert_eq!(find_element_at(&elements, &pos).map(|element| element.kind.to_string()), Some("Struct"));
+}
@@ -0,0 +1,283 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Folding ranges analysis: item bodies, match blocks, `use` runs, comment blocks and region markers.
+//!
+
+use source_model::*;
+use source_lexer::{ lex_all_tokens, file_offset, matching_brace_starts };
+use parse_describe::ParsedSource;
+
+use syntex_syntax::visit::*;
+use syntex_syntax::ast::*;
+use syntex_syntax::parse::token;
+use syntex_syntax::parse::lexer::TokenAndSpan;
+use syntex_syntax::codemap::{ Span, CodeMap };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldingRangeKind {
+    /// A run of consecutive `use` items.
+    Imports,
+    /// A block comment, or a run of line comments.
+    Comment,
+    /// A region delimited by `// region` and `// endregion` comments.
+    Region,
+    /// A braced body of an item, or of a `match`. The range starts at the opening brace.
+    Code,
+}
+
+impl FoldingRangeKind {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            FoldingRangeKind::Imports => "Imports",
+            FoldingRangeKind::Comment => "Comment",
+            FoldingRangeKind::Region => "Region",
+            FoldingRangeKind::Code => "Code",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FoldingRange {
+    pub kind : FoldingRangeKind,
+    pub sourcerange : SourceRange,
+}
+
+/// Compute the folding ranges of given source, sorted by range. Only ranges spanning more than one line
+/// are reported.
+pub fn folding_ranges(parsed : &ParsedSource) -> Vec<FoldingRange> {
+    let codemap : &CodeMap = &parsed.codemap;
+    let line_index : &LineIndex = &parsed.line_index;
+    let tokens = lex_all_tokens(parsed.codemap.clone(), &parsed.source);
+
+    let mut folds = FoldingRanges { line_index : line_index, folds : vec![] };
+
+    if parsed.is_parsed() {
+        let brace_starts = matching_brace_starts(codemap, &tokens);
+
+        let mut visitor = FoldingVisitor { braced_spans : vec![], use_runs : vec![] };
+        parsed.walk(&mut visitor);
+
+        for span in visitor.braced_spans {
+            let end = file_offset(codemap, span.hi);
+            if let Some(&start) = brace_starts.get(&end) {
+                folds.add(FoldingRangeKind::Code, start, end);
+            }
+        }
+        for (first, last) in visitor.use_runs {
+            folds.add(FoldingRangeKind::Imports, file_offset(codemap, first.lo), file_offset(codemap, last.hi));
+        }
+    }
+
+    add_comment_folds(codemap, &parsed.source, &tokens, &mut folds);
+
+    let mut folds = folds.folds;
+    folds.sort_by(|a, b| a.sourcerange.cmp(&b.sourcerange));
+    folds
+}
+
+struct FoldingRanges<'a> {
+    line_index : &'a LineIndex,
+    folds : Vec<FoldingRange>,
+}
+
+impl<'a> FoldingRanges<'a> {
+    fn add(&mut self, kind : FoldingRangeKind, start_offset : usize, end_offset : usize) {
+        let sourcerange = self.line_index.range_at(start_offset, end_offset);
+        if sourcerange.start_pos.line < sourcerange.end_pos.line {
+            self.folds.push(FoldingRange { kind : kind, sourcerange : sourcerange });
+        }
+    }
+}
+
+/// Collects the spans of AST nodes that may end with a braced body, and the runs of `use` items.
+struct FoldingVisitor {
+    braced_spans : Vec<Span>,
+    use_runs : Vec<(Span, Span)>,
+}
+
+impl Visitor for FoldingVisitor {
+
+    fn visit_mod(&mut self, m: &Mod, _span: Span, _nodeid: NodeId) {
+        let mut use_run : Option<(Span, Span)> = None;
+
+        for item in &m.items {
+            if let ItemKind::Use(_) = item.node {
+                use_run = Some(match use_run {
+                    Some((first, _)) => (first, item.span),
+                    None => (item.span, item.span),
+                });
+            } else if let Some(use_run) = use_run.take() {
+                self.use_runs.push(use_run);
+            }
+        }
+        if let Some(use_run) = use_run {
+            self.use_runs.push(use_run);
+        }
+
+        walk_mod(self, m);
+    }
+
+    fn visit_item(&mut self, item: &Item) {
+        self.braced_spans.push(item.span);
+        walk_item(self, item);
+    }
+
+    fn visit_trait_item(&mut self, ti: &TraitItem) {
+        self.braced_spans.push(ti.span);
+        walk_trait_item(self, ti);
+    }
+
+    fn visit_impl_item(&mut self, ii: &ImplItem) {
+        self.braced_spans.push(ii.span);
+        walk_impl_item(self, ii);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::Match(..) = expr.node {
+            self.braced_spans.push(expr.span);
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_mac(&mut self, _mac: &Mac) {
+        // Macro invocations are not expanded, nothing to visit
+    }
+}
+
+/* ----------------- Comments ----------------- */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineCommentKind {
+    Plain,
+    Doc,
+}
+
+/// Add the folds of comment blocks and regions.
+fn add_comment_folds(codemap : &CodeMap, source : &str, tokens : &[TokenAndSpan], folds : &mut FoldingRanges) {
+    // The current run of line comments: its kind, start offset and end offset
+    let mut comment_run : Option<(LineCommentKind, usize, usize)> = None;
+    let mut region_starts = vec![];
+
+    for token in tokens {
+        let start = file_offset(codemap, token.sp.lo);
+        let end = file_offset(codemap, token.sp.hi);
+        let text = &source[start..end];
+
+        let comment_kind = match token.tok {
+            token::Whitespace => {
+                if line_break_count(text) > 1 {
+                    end_comment_run(&mut comment_run, folds);
+                }
+                continue;
+            }
+            token::Comment => LineCommentKind::Plain,
+            token::DocComment(_) => LineCommentKind::Doc,
+            _ => {
+                end_comment_run(&mut comment_run, folds);
+                continue;
+            }
+        };
+
+        if !text.starts_with("//") {
+            // Block comment
+            end_comment_run(&mut comment_run, folds);
+            folds.add(FoldingRangeKind::Comment, start, end);
+            continue;
+        }
+
+        if comment_kind == LineCommentKind::Plain {
+            let comment_body = text.trim_left_matches('/').trim();
+            if is_region_marker(comment_body, "endregion") {
+                end_comment_run(&mut comment_run, folds);
+                if let Some(region_start) = region_starts.pop() {
+                    folds.add(FoldingRangeKind::Region, region_start, end);
+                }
+                continue;
+            } else if is_region_marker(comment_body, "region") {
+                end_comment_run(&mut comment_run, folds);
+                region_starts.push(start);
+                continue;
+            }
+        }
+
+        comment_run = match comment_run {
+            Some((run_kind, run_start, _)) if run_kind == comment_kind => Some((run_kind, run_start, end)),
+            _ => {
+                end_comment_run(&mut comment_run, folds);
+                Some((comment_kind, start, end))
+            }
+        };
+    }
+    end_comment_run(&mut comment_run, folds);
+}
+
+/// Whether given comment text starts with given region marker, as a whole word (optionally followed by `:`).
+fn is_region_marker(comment_body : &str, marker : &str) -> bool {
+    comment_body.starts_with(marker) && 
+        comment_body[marker.len()..].chars().next().map_or(true, |ch| ch.is_whitespace() || ch == ':')
+}
+
+fn end_comment_run(comment_run : &mut Option<(LineCommentKind, usize, usize)>, folds : &mut FoldingRanges) {
+    if let Some((_, start, end)) = comment_run.take() {
+        folds.add(FoldingRangeKind::Comment, start, end);
+    }
+}
+
+fn line_break_count(text : &str) -> usize {
+    text.matches('\n').count() + text.matches('\r').count() - text.matches("\r\n").count()
+}
+
+
+#[test]
+fn test_folding_ranges() {
+    use parse_describe::analyse_source;
+
+    fn test_folds(source : &str, expected : &[(FoldingRangeKind, SourceRange)]) {
+        let (_, folds) = analyse_source(source, |parsed| folding_ranges(parsed));
+        let folds : Vec<(FoldingRangeKind, SourceRange)> = folds.unwrap().into_iter()
+            .map(|fold| (fold.kind, fold.sourcerange)).collect();
+        assert_eq!(folds, expected);
+    }
+
+    test_folds("fn foo() { }", &[]);
+    test_folds("fn foo() {\n}", &[(FoldingRangeKind::Code, source_range(1, 9, 2, 1))]);
+    test_folds("struct Foo<T>\n  where T : Clone {\n  a : u32,\n}\nstruct Bar;",
+        &[(FoldingRangeKind::Code, source_range(2, 18, 4, 1))]);
+    test_folds("impl Foo {\n  fn foo() {\n    match x {\n      _ => {}\n    }\n  }\n}", &[
+        (FoldingRangeKind::Code, source_range(1, 9, 7, 1)),
+        (FoldingRangeKind::Code, source_range(2, 11, 6, 3)),
+        (FoldingRangeKind::Code, source_range(3, 12, 5, 5)),
+    ]);
+
+    test_folds("use a;\nuse b::{c,\n d};\n\nfn foo() {}\nuse e;",
+        &[(FoldingRangeKind::Imports, source_range(1, 0, 3, 4))]);
+
+    test_folds("// a\n// b\n\n// c\n/// d\n/// e\nfn foo() {}\n/* f \n */", &[
+        (FoldingRangeKind::Comment, source_range(1, 0, 2, 4)),
+        (FoldingRangeKind::Comment, source_range(5, 0, 6, 5)),
+        (FoldingRangeKind::Comment, source_range(8, 0, 9, 3)),
+    ]);
+
+    test_folds("// region: Foo\n// a\nfn foo() {}\n// endregion\n", &[
+        (FoldingRangeKind::Region, source_range(1, 0, 4, 12)),
+    ]);
+    test_folds("// region Foo\n// a\nfn foo() {}\n// endregion\n", &[
+        (FoldingRangeKind::Region, source_range(1, 0, 4, 12)),
+    ]);
+    test_folds("// regional settings\n// a\nfn foo() {}\n// endregions\n", &[
+        (FoldingRangeKind::Comment, source_range(1, 0, 2, 4)),
+    ]);
+}
@@ -0,0 +1,206 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Parsing of source fragments, such as a single expression or type, instead of a whole crate.
+//!
+
+use syntex_syntax::ast;
+use syntex_syntax::codemap::mk_sp;
+use syntex_syntax::parse::{ self, token };
+use syntex_syntax::parse::parser::Parser;
+use syntex_syntax::ptr::P;
+use syntex_syntax::visit::Visitor;
+
+/// The syntactic construct a source is parsed as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseTarget {
+    /// A crate root module, with inner attributes and items. This is the default.
+    Crate,
+    /// A list of items, without inner attributes.
+    Items,
+    /// The statements of a block, without the braces.
+    Statements,
+    Expression,
+    Type,
+    Pattern,
+    /// A single outer or inner attribute.
+    Attribute,
+}
+
+impl ParseTarget {
+    pub fn from_string(string : &str) -> Option<ParseTarget> {
+        match string {
+            "crate" => Some(ParseTarget::Crate),
+            "items" => Some(ParseTarget::Items),
+            "statements" => Some(ParseTarget::Statements),
+            "expr" => Some(ParseTarget::Expression),
+            "type" => Some(ParseTarget::Type),
+            "pattern" => Some(ParseTarget::Pattern),
+            "attribute" => Some(ParseTarget::Attribute),
+            _ => None,
+        }
+    }
+
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            ParseTarget::Crate => "crate",
+            ParseTarget::Items => "items",
+            ParseTarget::Statements => "statements",
+            ParseTarget::Expression => "expr",
+            ParseTarget::Type => "type",
+            ParseTarget::Pattern => "pattern",
+            ParseTarget::Attribute => "attribute",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            ParseTarget::Crate => "crate",
+            ParseTarget::Items => "item list",
+            ParseTarget::Statements => "statements",
+            ParseTarget::Expression => "expression",
+            ParseTarget::Type => "type",
+            ParseTarget::Pattern => "pattern",
+            ParseTarget::Attribute => "attribute",
+        }
+    }
+}
+
+/// A parsed source fragment, for the targets that are not parsed as a crate.
+pub enum ParsedFragment {
+    Statements(Vec<ast::Stmt>),
+    Expression(P<ast::Expr>),
+    Type(P<ast::Ty>),
+    Pattern(P<ast::Pat>),
+    Attribute(ast::Attribute),
+}
+
+impl ParsedFragment {
+
+    pub fn target(&self) -> ParseTarget {
+        match *self {
+            ParsedFragment::Statements(_) => ParseTarget::Statements,
+            ParsedFragment::Expression(_) => ParseTarget::Expression,
+            ParsedFragment::Type(_) => ParseTarget::Type,
+            ParsedFragment::Pattern(_) => ParseTarget::Pattern,
+            ParsedFragment::Attribute(_) => ParseTarget::Attribute,
+        }
+    }
+
+    /// Visit the top-level nodes of this fragment.
+    pub fn walk<V : Visitor>(&self, visitor : &mut V) {
+        match *self {
+            ParsedFragment::Statements(ref stmts) => {
+                for stmt in stmts {
+                    visitor.visit_stmt(stmt);
+                }
+            }
+            ParsedFragment::Expression(ref expr) => visitor.visit_expr(expr),
+            ParsedFragment::Type(ref ty) => visitor.visit_ty(ty),
+            ParsedFragment::Pattern(ref pat) => visitor.visit_pat(pat),
+            ParsedFragment::Attribute(ref attr) => visitor.visit_attribute(attr),
+        }
+    }
+}
+
+/// The result of parsing a source with some target: either a crate (for the crate and item list targets),
+/// or a fragment.
+pub enum ParsedTarget {
+    Crate(ast::Crate),
+    Fragment(ParsedFragment),
+}
+
+/// Parse the whole token stream of given parser as given target.
+pub fn parse_target<'a>(parser : &mut Parser<'a>, target : ParseTarget) -> parse::PResult<'a, ParsedTarget> {
+    let fragment = match target {
+        ParseTarget::Crate => return Ok(ParsedTarget::Crate(try!(parser.parse_crate_mod()))),
+        ParseTarget::Items => return Ok(ParsedTarget::Crate(try!(parse_items(parser)))),
+        ParseTarget::Statements => ParsedFragment::Statements(try!(parse_statements(parser))),
+        ParseTarget::Expression => ParsedFragment::Expression(try!(parser.parse_expr())),
+        ParseTarget::Type => ParsedFragment::Type(try!(parser.parse_ty_sum())),
+        ParseTarget::Pattern => ParsedFragment::Pattern(try!(parser.parse_pat())),
+        ParseTarget::Attribute => ParsedFragment::Attribute(try!(parser.parse_attribute(true))),
+    };
+
+    if parser.token != token::Eof {
+        let token_str = parser.this_token_to_string();
+        return Err(parser.fatal(&format!("expected end of {}, found `{}`", target.description(), token_str)));
+    }
+    Ok(ParsedTarget::Fragment(fragment))
+}
+
+/// Parse a list of items as a crate without inner attributes.
+fn parse_items<'a>(parser : &mut Parser<'a>) -> parse::PResult<'a, ast::Crate> {
+    let lo = parser.span.lo;
+
+    let mut items = vec![];
+    while let Some(item) = try!(parser.parse_item()) {
+        items.push(item);
+    }
+
+    if parser.token != token::Eof {
+        let token_str = parser.this_token_to_string();
+        return Err(parser.fatal(&format!("expected item, found `{}`", token_str)));
+    }
+
+    let span = mk_sp(lo, parser.span.lo);
+    Ok(ast::Crate {
+        attrs : vec![],
+        module : ast::Mod { inner : span, items : items },
+        config : parser.cfg.clone(),
+        span : span,
+        exported_macros : vec![],
+    })
+}
+
+fn parse_statements<'a>(parser : &mut Parser<'a>) -> parse::PResult<'a, Vec<ast::Stmt>> {
+    let mut stmts = vec![];
+
+    while parser.token != token::Eof {
+        // None is an empty statement (a lone `;`)
+        if let Some(stmt) = try!(parser.parse_full_stmt(false)) {
+            stmts.push(stmt);
+        }
+    }
+    Ok(stmts)
+}
+
+
+
+#[test]
+fn test_parse_target() {
+    use parse_describe::analyse_source_as;
+
+    fn parsed_fragment_target(source : &str, target : ParseTarget) -> Option<ParseTarget> {
+        let (_, result) = analyse_source_as(source, target, |parsed| parsed.fragment.as_ref().map(|f| f.target()));
+        result.unwrap()
+    }
+
+    for target in &[ParseTarget::Crate, ParseTarget::Items, ParseTarget::Statements, ParseTarget::Expression,
+        ParseTarget::Type, ParseTarget::Pattern, ParseTarget::Attribute]
+    {
+        assert_eq!(ParseTarget::from_string(target.to_string()), Some(*target));
+    }
+
+    assert_eq!(parsed_fragment_target("Vec<u8> + Send", ParseTarget::Type), Some(ParseTarget::Type));
+    assert_eq!(parsed_fragment_target("Some(ref x) | None", ParseTarget::Pattern), None);
+    assert_eq!(parsed_fragment_target("(a, _)", ParseTarget::Pattern), Some(ParseTarget::Pattern));
+    assert_eq!(parsed_fragment_target("#![allow(x)]", ParseTarget::Attribute), Some(ParseTarget::Attribute));
+    assert_eq!(parsed_fragment_target("let x = 1; x", ParseTarget::Statements), Some(ParseTarget::Statements));
+    assert_eq!(parsed_fragment_target("let x = 1;", ParseTarget::Expression), None);
+
+    let (_, is_crate) = analyse_source_as("fn foo() {}", ParseTarget::Items, |parsed| parsed.krate.is_some());
+    assert_eq!(is_crate, Some(true));
+}
@@ -0,0 +1,227 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Lexical highlighting: classification of the source tokens, as lexed by the parser's own lexer.
+//!
+
+use source_model::*;
+use source_lexer::{ lex_all_tokens, file_offset };
+use parse_describe::ParsedSource;
+
+use syntex_syntax::parse::token::{ self, Token };
+use syntex_syntax::parse::lexer::TokenAndSpan;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LexicalTokenKind {
+    Keyword,
+    Identifier,
+    Lifetime,
+    BoolLiteral,
+    CharLiteral,
+    ByteLiteral,
+    IntegerLiteral,
+    FloatLiteral,
+    StringLiteral,
+    RawStringLiteral,
+    ByteStringLiteral,
+    RawByteStringLiteral,
+    /// A line or block comment (nested block comments are a single token), or a shebang line.
+    Comment,
+    DocComment,
+    /// A whole `#[...]` or `#![...]` attribute.
+    Attribute,
+    /// The name and `!` of a macro invocation, or of `macro_rules!`.
+    MacroBang,
+    /// Any punctuation that is not a delimiter.
+    Operator,
+    /// A parenthesis, bracket or brace, or a `,` or `;` separator.
+    Delimiter,
+}
+
+impl LexicalTokenKind {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            LexicalTokenKind::Keyword => "Keyword",
+            LexicalTokenKind::Identifier => "Identifier",
+            LexicalTokenKind::Lifetime => "Lifetime",
+            LexicalTokenKind::BoolLiteral => "BoolLiteral",
+            LexicalTokenKind::CharLiteral => "CharLiteral",
+            LexicalTokenKind::ByteLiteral => "ByteLiteral",
+            LexicalTokenKind::IntegerLiteral => "IntegerLiteral",
+            LexicalTokenKind::FloatLiteral => "FloatLiteral",
+            LexicalTokenKind::StringLiteral => "StringLiteral",
+            LexicalTokenKind::RawStringLiteral => "RawStringLiteral",
+            LexicalTokenKind::ByteStringLiteral => "ByteStringLiteral",
+            LexicalTokenKind::RawByteStringLiteral => "RawByteStringLiteral",
+            LexicalTokenKind::Comment => "Comment",
+            LexicalTokenKind::DocComment => "DocComment",
+            LexicalTokenKind::Attribute => "Attribute",
+            LexicalTokenKind::MacroBang => "MacroBang",
+            LexicalTokenKind::Operator => "Operator",
+            LexicalTokenKind::Delimiter => "Delimiter",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LexicalToken {
+    pub kind : LexicalTokenKind,
+    pub sourcerange : SourceRange,
+}
+
+/// Classify the tokens of given source, in source order. Whitespace is not reported.
+/// If the lexer fails fatally, only the tokens before the error are reported.
+pub fn lexical_tokens(parsed : &ParsedSource) -> Vec<LexicalToken> {
+    let codemap = &parsed.codemap;
+    let tokens : Vec<TokenAndSpan> = lex_all_tokens(parsed.codemap.clone(), &parsed.source).into_iter()
+        .filter(|token| token.tok != token::Whitespace)
+        .collect();
+
+    let mut result = vec![];
+    let mut ix = 0;
+
+    while ix < tokens.len() {
+        let start = file_offset(codemap, tokens[ix].sp.lo);
+
+        let (kind, last_ix) = match tokens[ix].tok {
+            token::Pound => {
+                match attribute_end(&tokens, ix) {
+                    Some(last_ix) => (LexicalTokenKind::Attribute, last_ix),
+                    None => (LexicalTokenKind::Operator, ix),
+                }
+            }
+            token::Ident(_) if !tokens[ix].tok.is_any_keyword() &&
+                tokens.get(ix + 1).map_or(false, |next| next.tok == token::Not) => {
+                (LexicalTokenKind::MacroBang, ix + 1)
+            }
+            ref tok => (token_kind(tok), ix),
+        };
+
+        let end = file_offset(codemap, tokens[last_ix].sp.hi);
+        result.push(LexicalToken { kind : kind, sourcerange : parsed.line_index.range_at(start, end) });
+        ix = last_ix + 1;
+    }
+    result
+}
+
+/// The index of the closing `]` of the attribute starting with the `#` token at given index, if it is one.
+fn attribute_end(tokens : &[TokenAndSpan], pound_ix : usize) -> Option<usize> {
+    let mut ix = pound_ix + 1;
+    if tokens.get(ix).map(|token| &token.tok) == Some(&token::Not) {
+        ix += 1;
+    }
+    if tokens.get(ix).map(|token| &token.tok) != Some(&token::OpenDelim(token::Bracket)) {
+        return None;
+    }
+
+    let mut depth = 0;
+    for (ix, token) in tokens.iter().enumerate().skip(ix) {
+        match token.tok {
+            token::OpenDelim(token::Bracket) => depth += 1,
+            token::CloseDelim(token::Bracket) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(ix);
+                }
+            }
+            _ => { }
+        }
+    }
+    None
+}
+
+fn token_kind(tok : &Token) -> LexicalTokenKind {
+    match *tok {
+        Token::Ident(ident) if &*ident.name.as_str() == "true" || &*ident.name.as_str() == "false" => {
+            LexicalTokenKind::BoolLiteral
+        }
+        Token::Ident(_) if tok.is_any_keyword() => LexicalTokenKind::Keyword,
+        Token::Ident(_) | Token::Underscore => LexicalTokenKind::Identifier,
+        Token::Lifetime(_) => LexicalTokenKind::Lifetime,
+        Token::Literal(ref lit, _) => match *lit {
+            token::Lit::Byte(_) => LexicalTokenKind::ByteLiteral,
+            token::Lit::Char(_) => LexicalTokenKind::CharLiteral,
+            token::Lit::Integer(_) => LexicalTokenKind::IntegerLiteral,
+            token::Lit::Float(_) => LexicalTokenKind::FloatLiteral,
+            token::Lit::Str_(_) => LexicalTokenKind::StringLiteral,
+            token::Lit::StrRaw(..) => LexicalTokenKind::RawStringLiteral,
+            token::Lit::ByteStr(_) => LexicalTokenKind::ByteStringLiteral,
+            token::Lit::ByteStrRaw(..) => LexicalTokenKind::RawByteStringLiteral,
+        },
+        Token::Comment | Token::Shebang(_) => LexicalTokenKind::Comment,
+        Token::DocComment(_) => LexicalTokenKind::DocComment,
+        Token::OpenDelim(_) | Token::CloseDelim(_) | Token::Comma | Token::Semi => LexicalTokenKind::Delimiter,
+        _ => LexicalTokenKind::Operator,
+    }
+}
+
+
+#[test]
+fn test_lexical_tokens() {
+    use parse_describe::analyse_source;
+    use self::LexicalTokenKind::*;
+
+    fn test_tokens(source : &str, expected : &[(LexicalTokenKind, SourceRange)]) {
+        let (_, tokens) = analyse_source(source, |parsed| lexical_tokens(parsed));
+        let tokens : Vec<(LexicalTokenKind, SourceRange)> = tokens.unwrap().into_iter()
+            .map(|token| (token.kind, token.sourcerange)).collect();
+        assert_eq!(tokens, expected);
+    }
+
+    test_tokens("fn foo<'a>() -> bool { true }", &[
+        (Keyword, source_range(1, 0, 1, 2)),
+        (Identifier, source_range(1, 3, 1, 6)),
+        (Operator, source_range(1, 6, 1, 7)),
+        (Lifetime, source_range(1, 7, 1, 9)),
+        (Operator, source_range(1, 9, 1, 10)),
+        (Delimiter, source_range(1, 10, 1, 11)),
+        (Delimiter, source_range(1, 11, 1, 12)),
+        (Operator, source_range(1, 13, 1, 15)),
+        (Identifier, source_range(1, 16, 1, 20)),
+        (Delimiter, source_range(1, 21, 1, 22)),
+        (BoolLiteral, source_range(1, 23, 1, 27)),
+        (Delimiter, source_range(1, 28, 1, 29)),
+    ]);
+
+    test_tokens("#![x(y)] /* a /* b */ */\n/// doc\nconst A: u8 = b'a';", &[
+        (Attribute, source_range(1, 0, 1, 8)),
+        (Comment, source_range(1, 9, 1, 24)),
+        (DocComment, source_range(2, 0, 2, 7)),
+        (Keyword, source_range(3, 0, 3, 5)),
+        (Identifier, source_range(3, 6, 3, 7)),
+        (Operator, source_range(3, 7, 3, 8)),
+        (Identifier, source_range(3, 9, 3, 11)),
+        (Operator, source_range(3, 12, 3, 13)),
+        (ByteLiteral, source_range(3, 14, 3, 18)),
+        (Delimiter, source_range(3, 18, 3, 19)),
+    ]);
+
+    test_tokens(r##"println!(r#"a"#, 1.5, 'c', 2u8, b"x", br"y")"##, &[
+        (MacroBang, source_range(1, 0, 1, 8)),
+        (Delimiter, source_range(1, 8, 1, 9)),
+        (RawStringLiteral, source_range(1, 9, 1, 15)),
+        (Delimiter, source_range(1, 15, 1, 16)),
+        (FloatLiteral, source_range(1, 17, 1, 20)),
+        (Delimiter, source_range(1, 20, 1, 21)),
+        (CharLiteral, source_range(1, 22, 1, 25)),
+        (Delimiter, source_range(1, 25, 1, 26)),
+        (IntegerLiteral, source_range(1, 27, 1, 30)),
+        (Delimiter, source_range(1, 30, 1, 31)),
+        (ByteStringLiteral, source_range(1, 32, 1, 36)),
+        (Delimiter, source_range(1, 36, 1, 37)),
+        (RawByteStringLiteral, source_range(1, 38, 1, 43)),
+        (Delimiter, source_range(1, 43, 1, 44)),
+    ]);
+}
@@ -0,0 +1,227 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Hover information: the signature and the doc comment of the definition of a name.
+//!
+
+use source_model::*;
+use source_lexer::file_offset;
+use name_resolution::{ resolve_names, DefinitionKind };
+use element_query::find_element_path;
+use public_api::normalize_whitespace;
+use parse_describe::{ ParsedSource, structure_elements };
+
+use syntex_syntax::visit::*;
+use syntex_syntax::ast::*;
+use syntex_syntax::codemap::{ Span, CodeMap };
+use syntex_syntax::parse::lexer::comments::strip_doc_comment_decoration;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Hover {
+    pub name : String,
+    pub kind : DefinitionKind,
+    /// The range of the name at the position.
+    pub sourcerange : SourceRange,
+    /// The declaration of an item without its body, such as `pub fn foo(x: u32) -> u32`. For a local variable
+    /// or a parameter, its name and its type if the type is written, such as `x: u32`.
+    pub signature : String,
+    /// The type description of an item, as in its structure element, or the written type of a local variable
+    /// or a parameter.
+    pub type_desc : String,
+    /// The doc comment of an item, without the comment markers.
+    pub docs : String,
+}
+
+/// The hover information of the name at given position, if it refers to a definition of the source.
+pub fn hover_at(parsed : &ParsedSource, pos : &LineColumnPosition) -> Option<Hover> {
+    let resolution = resolve_names(parsed);
+    let reference = match resolution.reference_at(pos) {
+        Some(reference) => reference,
+        None => return None,
+    };
+    let definition = &resolution.definitions[reference.definition];
+
+    let mut declarations = Declarations { codemap : &parsed.codemap, docs : HashMap::new(),
+        declared_types : HashMap::new() };
+    parsed.walk(&mut declarations);
+
+    let mut hover = Hover {
+        name : definition.name.clone(), kind : definition.kind, sourcerange : reference.sourcerange,
+        signature : definition.name.clone(), type_desc : String::new(), docs : String::new(),
+    };
+    match definition.kind {
+        DefinitionKind::Item(_) => {
+            let elements = structure_elements(parsed);
+            let element = find_element_path(&elements, &definition.decl_range.start_pos).into_iter()
+                .find(|element| element.sourcerange == definition.decl_range);
+            if let Some(element) = element {
+                if let Some((start, end)) = parsed.line_index.range_offsets(&element.header_range) {
+                    hover.signature = normalize_whitespace(&parsed.source[start..end]);
+                }
+                hover.type_desc = element.type_desc.clone();
+            }
+            if let Some(offsets) = parsed.line_index.range_offsets(&definition.decl_range) {
+                hover.docs = declarations.docs.remove(&offsets).unwrap_or(String::new());
+            }
+        }
+        DefinitionKind::Local | DefinitionKind::Param => {
+            let name_start = parsed.line_index.range_offsets(&definition.name_range).map(|(start, _)| start);
+            if let Some(declared_type) = name_start.and_then(|start| declarations.declared_types.remove(&start)) {
+                hover.signature = format!("{}: {}", definition.name, declared_type);
+                hover.type_desc = declared_type;
+            }
+        }
+        DefinitionKind::GenericParam | DefinitionKind::Lifetime | DefinitionKind::Label => { }
+    }
+    Some(hover)
+}
+
+/// The doc comment in given attributes, without the comment markers.
+pub fn doc_comment(attrs : &[Attribute]) -> String {
+    let mut lines = vec![];
+    for attr in attrs.iter().filter(|attr| attr.node.style == AttrStyle::Outer && attr.check_name("doc")) {
+        let doc = match attr.value_str() {
+            Some(doc) => doc,
+            None => continue,
+        };
+        let doc = if attr.node.is_sugared_doc { strip_doc_comment_decoration(&doc) } else { doc.to_string() };
+        for line in doc.lines() {
+            // The space after `///` is not part of the comment text
+            lines.push(if line.starts_with(' ') { String::from(&line[1..]) } else { String::from(line) });
+        }
+    }
+    lines.join("\n").trim().to_string()
+}
+
+/// Collects the doc comments of the items, by the offsets of their span, and the written types of the
+/// local variables and parameters bound to a single name, by the offset of the name.
+struct Declarations<'a> {
+    codemap : &'a CodeMap,
+    docs : HashMap<(usize, usize), String>,
+    declared_types : HashMap<usize, String>,
+}
+
+impl<'a> Declarations<'a> {
+
+    fn add_docs(&mut self, span : Span, attrs : &[Attribute]) {
+        let docs = doc_comment(attrs);
+        if !docs.is_empty() {
+            self.docs.insert((file_offset(self.codemap, span.lo), file_offset(self.codemap, span.hi)), docs);
+        }
+    }
+
+    fn add_declared_type(&mut self, pat : &Pat, ty : &Ty) {
+        if let TyKind::Infer = ty.node {
+            // A closure parameter without a type
+            return;
+        }
+        if let PatKind::Ident(_, ref ident, None) = pat.node {
+            if let Ok(snippet) = self.codemap.span_to_snippet(ty.span) {
+                self.declared_types.insert(file_offset(self.codemap, ident.span.lo), snippet);
+            }
+        }
+    }
+
+    fn add_params(&mut self, decl : &FnDecl) {
+        for arg in decl.inputs.iter().filter(|arg| arg.to_self().is_none()) {
+            self.add_declared_type(&arg.pat, &arg.ty);
+        }
+    }
+
+}
+
+impl<'a> Visitor for Declarations<'a> {
+
+    fn visit_item(&mut self, item : &Item) {
+        self.add_docs(item.span, &item.attrs);
+        walk_item(self, item);
+    }
+
+    fn visit_foreign_item(&mut self, foreign_item : &ForeignItem) {
+        self.add_docs(foreign_item.span, &foreign_item.attrs);
+        walk_foreign_item(self, foreign_item);
+    }
+
+    fn visit_trait_item(&mut self, trait_item : &TraitItem) {
+        self.add_docs(trait_item.span, &trait_item.attrs);
+        if let TraitItemKind::Method(ref sig, None) = trait_item.node {
+            self.add_params(&sig.decl);
+        }
+        walk_trait_item(self, trait_item);
+    }
+
+    fn visit_impl_item(&mut self, impl_item : &ImplItem) {
+        self.add_docs(impl_item.span, &impl_item.attrs);
+        walk_impl_item(self, impl_item);
+    }
+
+    fn visit_variant(&mut self, variant : &Variant, generics : &Generics, item_id : NodeId) {
+        self.add_docs(variant.span, &variant.node.attrs);
+        walk_variant(self, variant, generics, item_id);
+    }
+
+    fn visit_fn(&mut self, kind : FnKind, decl : &FnDecl, body : &Block, span : Span, _id : NodeId) {
+        self.add_params(decl);
+        walk_fn(self, kind, decl, body, span);
+    }
+
+    fn visit_local(&mut self, local : &Local) {
+        if let Some(ref ty) = local.ty {
+            self.add_declared_type(&local.pat, ty);
+        }
+        walk_local(self, local);
+    }
+
+    fn visit_mac(&mut self, _mac : &Mac) {
+        // Macro invocations are not expanded, nothing to visit
+    }
+}
+
+
+#[test]
+fn test_hover() {
+    use parse_describe::analyse_source;
+
+    fn hover(source : &str, offset : usize) -> Option<(String, String, String, String)> {
+        let pos = LineIndex::new(source).position_at(offset);
+        analyse_source(source, move |parsed| {
+            hover_at(parsed, &pos).map(|hover| {
+                (String::from(hover.kind.to_string()), hover.signature, hover.type_desc, hover.docs)
+            })
+        }).1.unwrap()
+    }
+    fn info(kind : &str, signature : &str, type_desc : &str, docs : &str) -> Option<(String, String, String, String)> {
+        Some((String::from(kind), String::from(signature), String::from(type_desc), String::from(docs)))
+    }
+
+    let source = "/// Adds one.\n/// Wraps around.\npub fn inc(x : u32,\n    y : u32) -> u32 { let z : u32 = x; z + 1 }\n\
+        fn main() { let w = inc(1, 2); let f = |a| a; w }";
+    assert_eq!(hover(source, 39), info("Function", "pub fn inc(x : u32, y : u32) -> u32", "(u32, u32) -> u32",
+        "Adds one.\nWraps around."));
+    assert_eq!(hover(source, 119), hover(source, 39));
+    assert_eq!(hover(source, 43), info("Param", "x: u32", "u32", ""));
+    assert_eq!(hover(source, 91), info("Local", "z: u32", "u32", ""));
+    assert_eq!(hover(source, 145), info("Local", "w", "", ""));
+    assert_eq!(hover(source, 139), info("Param", "a", "", ""));
+    assert_eq!(hover(source, 0), None);
+
+    let source = "enum E {\n  /** The first. */\n  A(u32),\n  B }\nstruct S<T> { t : T }\nconst C : E = E::A(1);";
+    assert_eq!(hover(source, 84), info("EnumVariant", "A(u32)", "", "The first."));
+    assert_eq!(hover(source, 5), info("Enum", "enum E", "", ""));
+    assert_eq!(hover(source, 52), info("Struct", "struct S<T>", "", ""));
+    assert_eq!(hover(source, 54), info("GenericParam", "T", "", ""));
+}
@@ -0,0 +1,116 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! A minimal JSON document model and serializer, for the JSON output format.
+//!
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// An object, with its members in insertion order.
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+
+    pub fn string(string : &str) -> JsonValue {
+        JsonValue::String(String::from(string))
+    }
+
+    pub fn int(value : usize) -> JsonValue {
+        JsonValue::Int(value as i64)
+    }
+
+    pub fn object(members : Vec<(&str, JsonValue)>) -> JsonValue {
+        JsonValue::Object(members.into_iter().map(|(name, value)| (String::from(name), value)).collect())
+    }
+
+    pub fn write<OUT : ?Sized + fmt::Write>(&self, out : &mut OUT) -> fmt::Result {
+        match *self {
+            JsonValue::Null => out.write_str("null"),
+            JsonValue::Bool(value) => out.write_str(if value { "true" } else { "false" }),
+            JsonValue::Int(value) => out.write_fmt(format_args!("{}", value)),
+            JsonValue::String(ref string) => write_json_string(string, out),
+            JsonValue::Array(ref elements) => {
+                try!(out.write_char('['));
+                for (ix, element) in elements.iter().enumerate() {
+                    if ix > 0 {
+                        try!(out.write_char(','));
+                    }
+                    try!(element.write(out));
+                }
+                out.write_char(']')
+            }
+            JsonValue::Object(ref members) => {
+                try!(out.write_char('{'));
+                for (ix, &(ref name, ref value)) in members.iter().enumerate() {
+                    if ix > 0 {
+                        try!(out.write_char(','));
+                    }
+                    try!(write_json_string(name, out));
+                    try!(out.write_char(':'));
+                    try!(value.write(out));
+                }
+                out.write_char('}')
+            }
+        }
+    }
+
+    pub fn to_json_string(&self) -> String {
+        let mut string = String::new();
+        self.write(&mut string).ok();
+        string
+    }
+
+}
+
+pub fn write_json_string<OUT : ?Sized + fmt::Write>(string : &str, out : &mut OUT) -> fmt::Result {
+    try!(out.write_char('"'));
+
+    for ch in string.chars() {
+        match ch {
+            '"' => try!(out.write_str("\\\"")),
+            '\\' => try!(out.write_str("\\\\")),
+            '\n' => try!(out.write_str("\\n")),
+            '\r' => try!(out.write_str("\\r")),
+            '\t' => try!(out.write_str("\\t")),
+            ch if (ch as u32) < 0x20 => try!(out.write_fmt(format_args!("\\u{:04x}", ch as u32))),
+            ch => try!(out.write_char(ch)),
+        }
+    }
+
+    out.write_char('"')
+}
+
+
+#[test]
+fn test_JsonValue() {
+    assert_eq!(JsonValue::Null.to_json_string(), "null");
+    assert_eq!(JsonValue::Bool(true).to_json_string(), "true");
+    assert_eq!(JsonValue::int(42).to_json_string(), "42");
+    assert_eq!(JsonValue::string("a\"b\\c\nd\u{1}").to_json_string(), r#""a\"b\\c\nd\u0001""#);
+    assert_eq!(JsonValue::Array(vec![]).to_json_string(), "[]");
+    assert_eq!(JsonValue::Array(vec![JsonValue::int(1), JsonValue::Null]).to_json_string(), "[1,null]");
+    assert_eq!(
+        JsonValue::object(vec![("b", JsonValue::int(1)), ("a", JsonValue::Array(vec![]))]).to_json_string(),
+        r#"{"b":1,"a":[]}"#
+    );
+}
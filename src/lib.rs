@@ -6,6 +6,29 @@ extern crate rustdt_util as util;
 
 pub mod token_writer;
 pub mod source_model;
+pub mod source_input;
 pub mod parse_describe;
 pub mod structure_visitor;
+pub mod element_query;
+pub mod json_writer;
+pub mod source_lexer;
+pub mod folding;
+pub mod highlighting;
+pub mod semantic_tokens;
+pub mod syntax_tree;
+pub mod fragment;
+pub mod doc_code;
+pub mod module_loader;
+pub mod module_tree;
+pub mod symbol_index;
+pub mod outline_diff;
+pub mod public_api;
+pub mod name_resolution;
+pub mod rename;
+pub mod hover;
+pub mod signature_help;
+pub mod completion;
+
+#[cfg(test)]
+pub mod test_util;
 
@@ -1,17 +1,29 @@
 extern crate rainicorn;
 
 use rainicorn::*;
+use rainicorn::parse_describe::ParseAnalysisOptions;
 
+use std::env;
 use std::io::{self, Read};
 
 fn main() {
     
-    let mut buffer = String::new();
-    let result = io::stdin().read_to_string(&mut buffer);
+    let options = match ParseAnalysisOptions::from_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            parse_describe::input_error_analysis_for_Stdout(&format!("Error: {}", err), 
+                &ParseAnalysisOptions::default());
+            return;
+        }
+    };
+    
+    let mut buffer = Vec::new();
+    let result = if options.command.reads_source() { io::stdin().read_to_end(&mut buffer) } else { Ok(0) };
     
     match result {
-        Err(err) => println!("Error: {}.", err),
-        Ok(_) => parse_describe::parse_analysis_for_Stdout(&buffer),
+        Err(err) => parse_describe::input_error_analysis_for_Stdout(&format!("Error reading input: {}.", err), 
+            &options),
+        Ok(_) => parse_describe::parse_analysis_bytes_for_Stdout(&buffer, &options),
     }
     
-}
\ No newline at end of file
+}
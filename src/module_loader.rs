@@ -0,0 +1,378 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Loading of out-of-line modules (`mod foo;`) from the filesystem.
+//!
+//! The parser itself never loads module files (see `DummyFileLoader`): each file is parsed on its own,
+//! so that a missing module file is just a warning.
+//!
+
+use source_model::*;
+use source_input::{ DecodedSource, decode_source };
+use parse_describe::{ ParsedSource, analyse_source, structure_elements, apply_column_encoding, source_line_index };
+use syntax_tree::visibility_to_string;
+
+use syntex_syntax::ast::{ self, ItemKind };
+use syntex_syntax::attr;
+use syntex_syntax::print::pprust;
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{ self, Read };
+use std::path::{ Path, PathBuf };
+
+/// A module declaration: an out-of-line module `mod foo;`, or an inline module `mod foo { ... }`.
+#[derive(Debug, Clone)]
+pub struct ModuleDecl {
+    pub name : String,
+    /// The range of the declaration item.
+    pub sourcerange : SourceRange,
+    /// The visibility, such as `pub`, or None if private.
+    pub vis : Option<String>,
+    /// The conditions of its `#[cfg]` attributes, such as `test`.
+    pub cfgs : Vec<String>,
+    /// The value of its `#[path]` attribute, if any.
+    pub path_attr : Option<String>,
+    /// The directories of the inline modules enclosing the declaration: their `#[path]`, or their name.
+    pub inline_path : Vec<String>,
+    /// For an inline module, the module declarations inside it. None for an out-of-line module.
+    pub inline_decls : Option<Vec<ModuleDecl>>,
+}
+
+/// The module declarations of given source, outside of functions. The declarations inside an inline module
+/// are in its `inline_decls`.
+pub fn module_declarations(parsed : &ParsedSource) -> Vec<ModuleDecl> {
+    match parsed.krate {
+        Some(ref krate) => collect_module_declarations(parsed, &krate.module, &mut vec![]),
+        None => vec![],
+    }
+}
+
+fn collect_module_declarations(parsed : &ParsedSource, module : &ast::Mod, inline_path : &mut Vec<String>)
+    -> Vec<ModuleDecl>
+{
+    let mut decls = vec![];
+    for item in &module.items {
+        let submodule = match item.node {
+            ItemKind::Mod(ref submodule) => submodule,
+            _ => continue,
+        };
+        let name = item.ident.name.as_str().to_string();
+        let path_attr = attr::first_attr_value_str_by_name(&item.attrs, "path").map(|path| path.to_string());
+
+        let is_out_of_line = parsed.codemap.span_to_snippet(item.span)
+            .map_or(false, |snippet| snippet.trim_right().ends_with(';'));
+
+        let inline_decls = if is_out_of_line {
+            None
+        } else {
+            inline_path.push(path_attr.clone().unwrap_or(name.clone()));
+            let inline_decls = collect_module_declarations(parsed, submodule, inline_path);
+            inline_path.pop();
+            Some(inline_decls)
+        };
+
+        decls.push(ModuleDecl {
+            name : name,
+            sourcerange : SourceRange::from_span(&parsed.codemap, &parsed.line_index, item.span),
+            vis : visibility_to_string(&item.vis),
+            cfgs : cfg_conditions(&item.attrs),
+            path_attr : path_attr,
+            inline_path : inline_path.clone(),
+            inline_decls : inline_decls,
+        });
+    }
+    decls
+}
+
+/// The conditions of the `#[cfg]` attributes in given attributes.
+pub fn cfg_conditions(attrs : &[ast::Attribute]) -> Vec<String> {
+    attrs.iter()
+        .filter(|attr| attr.check_name("cfg"))
+        .filter_map(|attr| attr.meta_item_list())
+        .flat_map(|conditions| conditions.iter().map(pprust::meta_list_item_to_string))
+        .collect()
+}
+
+/// The out-of-line module declarations in given declarations, including those inside inline modules.
+pub fn out_of_line_declarations(decls : Vec<ModuleDecl>) -> Vec<ModuleDecl> {
+    let mut out_of_line_decls = vec![];
+    for mut decl in decls {
+        match decl.inline_decls.take() {
+            Some(inline_decls) => out_of_line_decls.extend(out_of_line_declarations(inline_decls)),
+            None => out_of_line_decls.push(decl),
+        }
+    }
+    out_of_line_decls
+}
+
+/// The file of a module declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleFile {
+    Found(PathBuf),
+    /// No file was found at any of the candidate paths.
+    NotFound(Vec<PathBuf>),
+    /// Both `foo.rs` and `foo/mod.rs` exist.
+    Ambiguous(PathBuf, PathBuf),
+}
+
+/// The directories the module declarations of a file are resolved from.
+#[derive(Debug, Clone)]
+pub struct ModuleDirs {
+    /// The directory of the file, that `#[path]` attributes are relative to.
+    pub file_dir : PathBuf,
+    /// The directory of the submodule files.
+    pub module_dir : PathBuf,
+}
+
+impl ModuleDirs {
+
+    /// The directories of the module file at given path. A crate root, a `mod.rs` file, or a file loaded with
+    /// `#[path]` owns its directory: its submodules are next to it. The submodules of another file `foo.rs`
+    /// are in `foo/`.
+    pub fn new(file_path : &Path, owns_directory : bool) -> ModuleDirs {
+        let file_dir = file_path.parent().map_or(PathBuf::new(), |dir| dir.to_path_buf());
+        let module_dir = if owns_directory {
+            file_dir.clone()
+        } else {
+            file_dir.join(file_path.file_stem().unwrap_or(OsStr::new("")))
+        };
+        ModuleDirs { file_dir : file_dir, module_dir : module_dir }
+    }
+
+    pub fn resolve(&self, decl : &ModuleDecl) -> ModuleFile {
+        if let Some(ref path_attr) = decl.path_attr {
+            let path = join_all(&self.file_dir, &decl.inline_path).join(path_attr);
+            return if path.is_file() { ModuleFile::Found(path) } else { ModuleFile::NotFound(vec![path]) };
+        }
+
+        let dir = join_all(&self.module_dir, &decl.inline_path);
+        let file_path = dir.join(format!("{}.rs", decl.name));
+        let mod_rs_path = dir.join(&decl.name).join("mod.rs");
+
+        match (file_path.is_file(), mod_rs_path.is_file()) {
+            (true, false) => ModuleFile::Found(file_path),
+            (false, true) => ModuleFile::Found(mod_rs_path),
+            (true, true) => ModuleFile::Ambiguous(file_path, mod_rs_path),
+            (false, false) => ModuleFile::NotFound(vec![file_path, mod_rs_path]),
+        }
+    }
+
+}
+
+fn join_all(dir : &Path, names : &[String]) -> PathBuf {
+    let mut path = dir.to_path_buf();
+    for name in names {
+        path.push(name);
+    }
+    path
+}
+
+/// Whether the module file loaded for given declaration owns its directory.
+pub fn owns_directory(decl : &ModuleDecl, file_path : &Path) -> bool {
+    decl.path_attr.is_some() || file_path.file_name() == Some(OsStr::new("mod.rs"))
+}
+
+/// Read the source of given file, decoded with `decode_source`: invalid UTF-8 sequences are replaced, and
+/// reported as warnings with a range in the file.
+pub fn read_source_file(path : &Path) -> io::Result<DecodedSource> {
+    let mut bytes = vec![];
+    try!(try!(File::open(path)).read_to_end(&mut bytes));
+    Ok(decode_source(&bytes))
+}
+
+/// Describe the structure of the crate root file at given path, with given source, and of the module files
+/// it loads, recursively. The `Mod` element of a loaded module has the module file as `source_file`, and
+/// the structure of that file as children (with columns already in given encoding).
+/// Module files that are not found are reported as warnings, as are the messages of the loaded files
+/// (without a source range).
+pub fn describe_module_tree(root_path : &Path, source : &str, encoding : ColumnEncoding)
+    -> (Vec<SourceMessage>, Vec<StructureElement>)
+{
+    let mut loader = ModuleLoader { encoding : encoding, loading : vec![] };
+    loader.describe_file(root_path, source, true)
+}
+
+struct ModuleLoader {
+    encoding : ColumnEncoding,
+    /// The files being loaded, to detect circular modules.
+    loading : Vec<PathBuf>,
+}
+
+impl ModuleLoader {
+
+    fn describe_file(&mut self, path : &Path, source : &str, owns_directory : bool)
+        -> (Vec<SourceMessage>, Vec<StructureElement>)
+    {
+        let (mut messages, result) = analyse_source(source,
+            |parsed| (structure_elements(parsed), out_of_line_declarations(module_declarations(parsed))));
+        let (mut elements, decls) = result.unwrap_or((vec![], vec![]));
+
+        let dirs = ModuleDirs::new(path, owns_directory);
+        self.loading.push(path.to_path_buf());
+
+        for decl in decls {
+            if let Some(file_path) = find_module_file(&dirs, &decl, &mut messages) {
+                self.load_module(&decl, &file_path, &mut messages, &mut elements);
+            }
+        }
+
+        self.loading.pop();
+        (messages, elements)
+    }
+
+    fn load_module(&mut self, decl : &ModuleDecl, file_path : &Path, messages : &mut Vec<SourceMessage>,
+        elements : &mut Vec<StructureElement>)
+    {
+        if self.loading.iter().any(|path| path == file_path) {
+            messages.push(warning(Some(decl.sourcerange),
+                format!("circular modules: `{}` is already being loaded", file_path.display())));
+            return;
+        }
+
+        let source = match read_source_file(file_path) {
+            Ok(source) => source,
+            Err(error) => {
+                messages.push(warning(Some(decl.sourcerange),
+                    format!("could not read file `{}`: {}", file_path.display(), error)));
+                return;
+            }
+        };
+
+        let file_name = file_path.display().to_string();
+        let mut file_messages = source.messages;
+        let (parse_messages, mut file_elements) =
+            self.describe_file(file_path, &source.text, owns_directory(decl, file_path));
+        file_messages.extend(parse_messages);
+        apply_column_encoding(&source_line_index(&source.text), self.encoding, &mut file_messages,
+            &mut file_elements);
+
+        messages.extend(file_messages.into_iter().map(|message| located_message(&file_name, message)));
+
+        if let Some(element) = find_module_element(elements, &decl.sourcerange) {
+            prefix_qualified_paths(&mut file_elements, &element.qualified_path, module_id_path(&element.id));
+            element.source_file = Some(file_name);
+            element.children = file_elements;
+        }
+    }
+
+}
+
+/// The file of given module declaration, or None if it is not found (which is reported as a warning).
+pub fn find_module_file(dirs : &ModuleDirs, decl : &ModuleDecl, messages : &mut Vec<SourceMessage>)
+    -> Option<PathBuf>
+{
+    match dirs.resolve(decl) {
+        ModuleFile::Found(file_path) => Some(file_path),
+        ModuleFile::NotFound(paths) => {
+            let paths : Vec<String> = paths.iter().map(|path| format!("`{}`", path.display())).collect();
+            messages.push(warning(Some(decl.sourcerange),
+                format!("file not found for module `{}`: {}", decl.name, paths.join(" or "))));
+            None
+        }
+        ModuleFile::Ambiguous(file_path, mod_rs_path) => {
+            messages.push(warning(Some(decl.sourcerange), format!("file for module `{}` found at both `{}` \
+                and `{}`", decl.name, file_path.display(), mod_rs_path.display())));
+            None
+        }
+    }
+}
+
+pub fn warning(sourcerange : Option<SourceRange>, message : String) -> SourceMessage {
+    SourceMessage { severity : Severity::WARNING, sourcerange : sourcerange, message : message }
+}
+
+/// A message of another file, without range: the file and position are in the message text.
+pub fn located_message(file_name : &str, message : SourceMessage) -> SourceMessage {
+    let text = match message.sourcerange {
+        Some(range) => {
+            format!("{}:{}:{}: {}", file_name, range.start_pos.line - 1, range.start_pos.col.0, message.message)
+        }
+        None => format!("{}: {}", file_name, message.message),
+    };
+    SourceMessage { sourcerange : None, message : text, .. message }
+}
+
+/// Prefix the qualified paths and IDs of given elements of a module file with the path of the module.
+fn prefix_qualified_paths(elements : &mut Vec<StructureElement>, module_path : &str, module_id_path : &str) {
+    for element in elements.iter_mut() {
+        element.qualified_path = format!("{}::{}", module_path, element.qualified_path);
+        element.id = match element.id.find(' ') {
+            Some(ix) => format!("{} {}::{}", &element.id[..ix], module_id_path, &element.id[ix+1..]),
+            None => element.id.clone(),
+        };
+        prefix_qualified_paths(&mut element.children, module_path, module_id_path);
+    }
+}
+
+/// The path part of the ID of a module element (the ID without the kind).
+fn module_id_path(id : &str) -> &str {
+    id.find(' ').map_or(id, |ix| &id[ix+1..])
+}
+
+/// The `Mod` element with given range.
+fn find_module_element<'a>(elements : &'a mut Vec<StructureElement>, sourcerange : &SourceRange)
+    -> Option<&'a mut StructureElement>
+{
+    for element in elements.iter_mut() {
+        if element.kind == StructureElementKind::Mod && element.sourcerange == *sourcerange {
+            return Some(element);
+        }
+        if element.source_file.is_none() && element.sourcerange.contains_range(sourcerange) {
+            return find_module_element(&mut element.children, sourcerange);
+        }
+    }
+    None
+}
+
+
+#[test]
+fn test_describe_module_tree() {
+    use test_util::TempDir;
+
+    let dir = TempDir::new("rainicorn_module_loader_tests");
+    dir.write_file("a.rs", "mod b;\nfn in_a() {}");
+    dir.write_file("a/b.rs", "fn in_b() {}");
+    dir.write_file("c/mod.rs", "mod d;\nstruct InC;");
+    dir.write_file("c/d.rs", "");
+    dir.write_file("other.rs", "fn in_other() {} 1");
+
+    let source = "mod a;\nmod c;\n#[path = \"other.rs\"]\nmod e;\nmod inline { mod missing; }";
+    let (messages, elements) = describe_module_tree(&dir.join("lib.rs"), source, ColumnEncoding::Chars);
+
+    fn describe(elements : &[StructureElement], out : &mut String) {
+        for element in elements {
+            out.push_str(&element.name);
+            if let Some(ref source_file) = element.source_file {
+                out.push_str(&format!("@{}", Path::new(source_file).file_name().unwrap().to_str().unwrap()));
+            }
+            out.push_str("(");
+            describe(&element.children, out);
+            out.push_str(")");
+        }
+    }
+    let mut tree = String::new();
+    describe(&elements, &mut tree);
+    assert_eq!(tree, "a@a.rs(b@b.rs(in_b())in_a())c@mod.rs(d@d.rs()InC())e@other.rs()inline(missing())");
+    assert_eq!(elements[0].children[0].children[0].qualified_path, "a::b::in_b");
+    assert_eq!(elements[0].children[0].children[0].id, "Function a::b::in_b");
+
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].message.ends_with("other.rs:0:17: expected item, found `1`"));
+    assert_eq!(messages[0].sourcerange, None);
+    assert_eq!(messages[1].severity, Severity::WARNING);
+    assert!(messages[1].message.starts_with("file not found for module `missing`: `"));
+    assert_eq!(messages[1].sourcerange, Some(source_range(5, 13, 5, 25)));
+}
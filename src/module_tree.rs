@@ -0,0 +1,332 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! The module hierarchy of a whole crate, read from the filesystem.
+//!
+
+use util::core::*;
+use source_model::*;
+use parse_describe::{ analyse_source, apply_column_encoding, source_line_index };
+use module_loader::{ ModuleDecl, ModuleDirs, ModuleFile, module_declarations, owns_directory, read_source_file,
+    warning, located_message };
+
+use std::ffi::OsStr;
+use std::path::{ Path, PathBuf };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleKind {
+    /// The root module of a library crate.
+    Lib,
+    /// The root module of a binary crate.
+    Bin,
+    /// An out-of-line module, `mod foo;`.
+    File,
+    /// An inline module, `mod foo { ... }`.
+    Inline,
+}
+
+impl ModuleKind {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            ModuleKind::Lib => "Lib",
+            ModuleKind::Bin => "Bin",
+            ModuleKind::File => "File",
+            ModuleKind::Inline => "Inline",
+        }
+    }
+}
+
+/// A module of the module tree.
+#[derive(Debug, Clone)]
+pub struct ModuleNode {
+    pub kind : ModuleKind,
+    /// The module name, or the crate name for a crate root.
+    pub name : String,
+    /// The file the module is in, or None if it was not found.
+    pub file : Option<String>,
+    /// The visibility, such as `pub`, or None if private.
+    pub vis : Option<String>,
+    /// The conditions of its `#[cfg]` attributes.
+    pub cfgs : Vec<String>,
+    /// For an out-of-line module whose file was not found, the paths where it was looked for.
+    pub missing_files : Vec<String>,
+    pub children : Vec<ModuleNode>,
+}
+
+impl ModuleNode {
+    fn new(kind : ModuleKind, name : String) -> ModuleNode {
+        ModuleNode { kind : kind, name : name, file : None, vis : None, cfgs : vec![], missing_files : vec![],
+            children : vec![] }
+    }
+}
+
+/// A crate root file, of a library or a binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrateRoot {
+    pub kind : ModuleKind,
+    pub name : String,
+    pub path : PathBuf,
+}
+
+/// The crate roots of given path: a `Cargo.toml` manifest, or a crate root source file.
+pub fn crate_roots(path : &Path) -> GResult<Vec<CrateRoot>> {
+    if path.file_name() == Some(OsStr::new("Cargo.toml")) {
+        let manifest = try!(read_source_file(path)).text;
+        let roots = manifest_crate_roots(path.parent().unwrap_or(Path::new("")), &manifest);
+        if roots.is_empty() {
+            return Err(format!("No library or binary crate root in `{}`.", path.display()).into());
+        }
+        return Ok(roots);
+    }
+
+    let kind = if path.file_name() == Some(OsStr::new("main.rs")) { ModuleKind::Bin } else { ModuleKind::Lib };
+    let name = path.file_stem().unwrap_or(OsStr::new("")).to_string_lossy().into_owned();
+    Ok(vec![CrateRoot { kind : kind, name : name, path : path.to_path_buf() }])
+}
+
+/// The crate roots of the `[lib]` and `[[bin]]` targets of given manifest, or the Cargo default roots
+/// (`src/lib.rs` and `src/main.rs`) if it declares none and they exist.
+/// Only the `name` and `path` keys of the targets are read, this is not a full TOML parser.
+fn manifest_crate_roots(dir : &Path, manifest : &str) -> Vec<CrateRoot> {
+    let mut package_name = None;
+    // The kind, name and path of each target
+    let mut targets : Vec<(ModuleKind, Option<String>, Option<String>)> = vec![];
+    let mut section = "";
+
+    for line in manifest.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line;
+            match line {
+                "[lib]" => targets.push((ModuleKind::Lib, None, None)),
+                "[[bin]]" => targets.push((ModuleKind::Bin, None, None)),
+                _ => { }
+            }
+            continue;
+        }
+
+        let (key, value) = match manifest_key_value(line) {
+            Some(key_value) => key_value,
+            None => continue,
+        };
+        match section {
+            "[package]" if key == "name" => package_name = Some(value),
+            "[lib]" | "[[bin]]" => {
+                let target = targets.last_mut().unwrap();
+                match key {
+                    "name" => target.1 = Some(value),
+                    "path" => target.2 = Some(value),
+                    _ => { }
+                }
+            }
+            _ => { }
+        }
+    }
+
+    let package_name = package_name.unwrap_or(String::new());
+    if !targets.iter().any(|target| target.0 == ModuleKind::Lib) && dir.join("src/lib.rs").is_file() {
+        targets.insert(0, (ModuleKind::Lib, None, None));
+    }
+    if !targets.iter().any(|target| target.0 == ModuleKind::Bin) && dir.join("src/main.rs").is_file() {
+        targets.push((ModuleKind::Bin, None, None));
+    }
+
+    targets.into_iter().map(|(kind, name, path)| {
+        let name = name.unwrap_or(package_name.replace("-", "_"));
+        let path = match path {
+            Some(path) => dir.join(path),
+            None if kind == ModuleKind::Lib => dir.join("src/lib.rs"),
+            None => {
+                let bin_path = dir.join("src/bin").join(format!("{}.rs", name));
+                if bin_path.is_file() { bin_path } else { dir.join("src/main.rs") }
+            }
+        };
+        CrateRoot { kind : kind, name : name, path : path }
+    }).collect()
+}
+
+/// The key and string value of a manifest line such as `name = "foo"`.
+fn manifest_key_value(line : &str) -> Option<(&str, String)> {
+    let equals_ix = match line.find('=') {
+        Some(equals_ix) => equals_ix,
+        None => return None,
+    };
+    let value = line[equals_ix + 1 ..].trim();
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return None;
+    }
+    Some((line[..equals_ix].trim(), value[1 .. value.len() - 1].to_string()))
+}
+
+/// The module tree of each given crate root. Parse errors of the module files, and module files that are not
+/// found, are reported as messages without range: the file and position are in the message text, with the
+/// column in given encoding.
+pub fn module_tree(roots : &[CrateRoot], encoding : ColumnEncoding) -> (Vec<SourceMessage>, Vec<ModuleNode>) {
+    let mut builder = ModuleTreeBuilder { messages : vec![], loading : vec![], encoding : encoding };
+
+    let nodes = roots.iter().map(|root| {
+        let mut node = ModuleNode::new(root.kind, root.name.clone());
+        builder.load_file(&mut node, &root.path, true);
+        node
+    }).collect();
+
+    (builder.messages, nodes)
+}
+
+struct ModuleTreeBuilder {
+    messages : Vec<SourceMessage>,
+    /// The files being loaded, to detect circular modules.
+    loading : Vec<PathBuf>,
+    encoding : ColumnEncoding,
+}
+
+impl ModuleTreeBuilder {
+
+    /// Set given node as the module of given file, and load its submodules.
+    fn load_file(&mut self, node : &mut ModuleNode, path : &Path, owns_directory : bool) {
+        let file_name = path.display().to_string();
+        node.file = Some(file_name.clone());
+
+        let source = match read_source_file(path) {
+            Ok(source) => source,
+            Err(error) => {
+                self.messages.push(warning(None, format!("could not read file `{}`: {}", file_name, error)));
+                return;
+            }
+        };
+
+        let line_index = source_line_index(&source.text);
+        let mut messages = source.messages;
+        let (parse_messages, decls) = analyse_source(&source.text, |parsed| module_declarations(parsed));
+        messages.extend(parse_messages);
+        apply_column_encoding(&line_index, self.encoding, &mut messages, &mut vec![]);
+        self.messages.extend(messages.into_iter().map(|message| located_message(&file_name, message)));
+
+        let dirs = ModuleDirs::new(path, owns_directory);
+        self.loading.push(path.to_path_buf());
+        node.children = self.module_nodes(&dirs, &file_name, &line_index, decls.unwrap_or(vec![]));
+        self.loading.pop();
+    }
+
+    fn module_nodes(&mut self, dirs : &ModuleDirs, file_name : &str, line_index : &LineIndex, decls : Vec<ModuleDecl>)
+        -> Vec<ModuleNode>
+    {
+        let mut nodes = vec![];
+        for mut decl in decls {
+            let inline_decls = decl.inline_decls.take();
+            let kind = if inline_decls.is_some() { ModuleKind::Inline } else { ModuleKind::File };
+
+            let mut node = ModuleNode::new(kind, decl.name.clone());
+            node.vis = decl.vis.clone();
+            node.cfgs = decl.cfgs.clone();
+
+            match inline_decls {
+                Some(inline_decls) => {
+                    node.file = Some(file_name.to_string());
+                    node.children = self.module_nodes(dirs, file_name, line_index, inline_decls);
+                }
+                None => self.load_module(&mut node, dirs, file_name, line_index, &decl),
+            }
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    fn load_module(&mut self, node : &mut ModuleNode, dirs : &ModuleDirs, file_name : &str, line_index : &LineIndex,
+        decl : &ModuleDecl)
+    {
+        let message = match dirs.resolve(decl) {
+            ModuleFile::Found(path) => {
+                if !self.loading.iter().any(|loading_path| *loading_path == path) {
+                    self.load_file(node, &path, owns_directory(decl, &path));
+                    return;
+                }
+                format!("circular modules: `{}` is already being loaded", path.display())
+            }
+            ModuleFile::NotFound(paths) => {
+                node.missing_files = paths.iter().map(|path| path.display().to_string()).collect();
+                let paths : Vec<String> = node.missing_files.iter().map(|path| format!("`{}`", path)).collect();
+                format!("file not found for module `{}`: {}", decl.name, paths.join(" or "))
+            }
+            ModuleFile::Ambiguous(file_path, mod_rs_path) => {
+                format!("file for module `{}` found at both `{}` and `{}`", decl.name, file_path.display(),
+                    mod_rs_path.display())
+            }
+        };
+        let sourcerange = decl.sourcerange.with_column_encoding(line_index, self.encoding);
+        self.messages.push(located_message(file_name, warning(Some(sourcerange), message)));
+    }
+
+}
+
+
+#[test]
+fn test_module_tree() {
+    use test_util::TempDir;
+
+    let dir = TempDir::new("rainicorn_module_tree_tests");
+    dir.write_file("Cargo.toml", "[package]\nname = \"my-crate\"\n\n[dependencies]\nname = \"x\"\n\n\
+        [[bin]]\nname = \"tool\"\n# comment\n");
+    dir.write_file("src/lib.rs", "pub mod a;\n#[cfg(test)]\nmod tests { mod missing; }\n\
+        #[cfg(all(unix, feature = \"x\"))]\npub(crate) mod b;");
+    dir.write_file("src/a/mod.rs", "fn in_a() {} /* ä */ 1");
+    dir.write_file("src/b.rs", "");
+    dir.write_file("src/bin/tool.rs", "");
+
+    let roots = crate_roots(&dir.join("Cargo.toml")).unwrap();
+    assert_eq!(roots, vec![
+        CrateRoot { kind : ModuleKind::Lib, name : "my_crate".to_string(), path : dir.join("src/lib.rs") },
+        CrateRoot { kind : ModuleKind::Bin, name : "tool".to_string(), path : dir.join("src/bin/tool.rs") },
+    ]);
+    assert_eq!(crate_roots(&dir.join("src/main.rs")).unwrap()[0].kind, ModuleKind::Bin);
+
+    let (messages, nodes) = module_tree(&roots, ColumnEncoding::Chars);
+
+    fn describe(nodes : &[ModuleNode], out : &mut String) {
+        for node in nodes {
+            out.push_str(&format!("{} {}", node.kind.to_string(), node.name));
+            if let Some(ref vis) = node.vis {
+                out.push_str(&format!(" {}", vis));
+            }
+            for cfg in &node.cfgs {
+                out.push_str(&format!(" cfg({})", cfg));
+            }
+            if let Some(ref file) = node.file {
+                out.push_str(&format!(" @{}", Path::new(file).file_name().unwrap().to_str().unwrap()));
+            }
+            out.push_str(&format!(" missing:{} (", node.missing_files.len()));
+            describe(&node.children, out);
+            out.push_str(")");
+        }
+    }
+    let mut tree = String::new();
+    describe(&nodes, &mut tree);
+    assert_eq!(tree, "Lib my_crate @lib.rs missing:0 (\
+        File a pub @mod.rs missing:0 ()\
+        Inline tests cfg(test) @lib.rs missing:0 (File missing missing:2 ())\
+        File b pub(crate) cfg(all(unix, feature = \"x\")) @b.rs missing:0 ())\
+        Bin tool @tool.rs missing:0 ()");
+
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].message.ends_with("mod.rs:0:21: expected item, found `1`"));
+    assert!(messages[1].message.contains("lib.rs:2:12: file not found for module `missing`: `"));
+    assert_eq!(messages[1].severity, Severity::WARNING);
+    assert_eq!(messages[1].sourcerange, None);
+
+    // The columns of each file are converted with the line index of that file
+    let (messages, _) = module_tree(&roots, ColumnEncoding::Bytes);
+    assert!(messages[0].message.ends_with("mod.rs:0:22: expected item, found `1`"));
+    assert!(messages[1].message.contains("lib.rs:2:12: file not found for module `missing`: `"));
+}
@@ -0,0 +1,1317 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Name resolution within a single source, without type checking: the definitions of local variables,
+//! parameters, items, generic parameters, lifetimes and labels, and the references to them.
+//!
+
+use source_model::*;
+use source_lexer::file_offset;
+use structure_visitor::find_identifier;
+use parse_describe::ParsedSource;
+
+use syntex_syntax::visit::*;
+use syntex_syntax::ast::*;
+use syntex_syntax::codemap::{ Span, CodeMap };
+use syntex_syntax::parse::token;
+use syntex_syntax::tokenstream::TokenTree;
+
+use std::collections::HashMap;
+use std::mem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    /// A variable bound by a `let`, `match`, `if let`, `while let` or `for` pattern.
+    Local,
+    /// A parameter of a function, method or closure.
+    Param,
+    /// An item of given kind, or a name imported by a `use` item (of kind `Use`).
+    Item(StructureElementKind),
+    GenericParam,
+    Lifetime,
+    Label,
+}
+
+impl DefinitionKind {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            DefinitionKind::Local => "Local",
+            DefinitionKind::Param => "Param",
+            DefinitionKind::Item(kind) => kind.to_string(),
+            DefinitionKind::GenericParam => "GenericParam",
+            DefinitionKind::Lifetime => "Lifetime",
+            DefinitionKind::Label => "Label",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name : String,
+    pub kind : DefinitionKind,
+    /// The range of the name in the declaration.
+    pub name_range : SourceRange,
+    /// The range of the whole declaration, such as the `let` statement, the parameter or the item.
+    pub decl_range : SourceRange,
+    /// The definition of the same name that this local, parameter, generic parameter, lifetime or label
+    /// hides, if any.
+    pub shadowed : Option<usize>,
+}
+
+/// How a reference uses its definition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The name in the declaration.
+    Declaration,
+    Read,
+    /// The target of an assignment, such as `x` in `x = 1` or `x += 1`.
+    Write,
+}
+
+impl Access {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            Access::Declaration => "Declaration",
+            Access::Read => "Read",
+            Access::Write => "Write",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NameReference {
+    pub sourcerange : SourceRange,
+    /// The index of the referenced definition.
+    pub definition : usize,
+    pub access : Access,
+}
+
+#[derive(Debug, Clone)]
+pub struct NameResolution {
+    pub definitions : Vec<Definition>,
+    /// The references to the definitions, sorted by range. The name of each declaration is a reference
+    /// to its own definition, with `Declaration` access.
+    pub references : Vec<NameReference>,
+}
+
+impl NameResolution {
+
+    /// The reference at given position. If both a declaration and a reference to another definition
+    /// are there (as in the path of a `use` item), the latter is preferred.
+    pub fn reference_at(&self, pos : &LineColumnPosition) -> Option<&NameReference> {
+        let mut found = None;
+        for reference in self.references.iter().filter(|reference| reference.sourcerange.contains(pos)) {
+            if found.is_none() || reference.access != Access::Declaration {
+                found = Some(reference);
+            }
+        }
+        found
+    }
+
+    /// The definition of the name at given position.
+    pub fn definition_at(&self, pos : &LineColumnPosition) -> Option<&Definition> {
+        self.reference_at(pos).map(|reference| &self.definitions[reference.definition])
+    }
+
+    /// All the references to given definition, including its declaration.
+    pub fn references_to(&self, definition : usize) -> Vec<&NameReference> {
+        self.references.iter().filter(|reference| reference.definition == definition).collect()
+    }
+
+    /// All the references to the definition of the name at given position, for occurrence highlighting.
+    pub fn references_at(&self, pos : &LineColumnPosition) -> Vec<&NameReference> {
+        self.reference_at(pos).map_or(vec![], |reference| self.references_to(reference.definition))
+    }
+
+}
+
+/// The names visible at a position, for code completion.
+#[derive(Debug, Clone)]
+pub struct VisibleNames {
+    /// The visible definitions, from the innermost scope, without the shadowed ones. The names imported by
+    /// a `use` item have the kind of the imported definition, if it's found in the source.
+    pub definitions : Vec<Definition>,
+    /// Whether the position is in the body of a function or closure.
+    pub in_body : bool,
+}
+
+/// Resolve the names of given source.
+pub fn resolve_names(parsed : &ParsedSource) -> NameResolution {
+    let resolver = run_resolver(parsed, None);
+
+    let mut references = resolver.references;
+    references.sort_by(|a, b| (a.sourcerange, a.definition).cmp(&(b.sourcerange, b.definition)));
+
+    // Drop the references found twice
+    let mut result : Vec<NameReference> = vec![];
+    for reference in references {
+        if result.last().map_or(true, |last| {
+            (last.sourcerange, last.definition) != (reference.sourcerange, reference.definition)
+        }) {
+            result.push(reference);
+        }
+    }
+    NameResolution { definitions : resolver.definitions, references : result }
+}
+
+/// The names visible at given position of given source.
+pub fn visible_names(parsed : &ParsedSource, pos : &LineColumnPosition) -> VisibleNames {
+    let resolver = run_resolver(parsed, parsed.line_index.offset_at(pos));
+
+    // Outside of any item, the items of the root module are visible
+    let (visible, in_body) = match resolver.visible_at_position {
+        Some(ref visible_at_position) => visible_at_position.clone(),
+        None => (resolver.visible_definitions(), false),
+    };
+    let definitions = visible.into_iter().map(|def| {
+        let mut definition = resolver.definitions[def].clone();
+        if let Some(imported) = resolver.resolve_import(def, 0) {
+            definition.kind = resolver.definitions[imported].kind;
+        }
+        definition
+    }).collect();
+    VisibleNames { definitions : definitions, in_body : in_body }
+}
+
+/// Resolve the names of given source, collecting the definitions visible at given offset, if any.
+fn run_resolver<'a>(parsed : &'a ParsedSource, position : Option<usize>) -> NameResolver<'a> {
+    let mut resolver = NameResolver {
+        codemap : &parsed.codemap, line_index : &parsed.line_index,
+        definitions : vec![], references : vec![],
+        scopes : vec![Scope { kind : ScopeKind::Module, definitions : vec![] }],
+        module_path : vec![], members : HashMap::new(), item_definitions : HashMap::new(),
+        imports : HashMap::new(), methods : vec![],
+        binding_kind : DefinitionKind::Local, binding_decl : None, in_alternative : false, in_assignee : false,
+        position : position, visible_at_position : None,
+    };
+
+    if let Some(ref krate) = parsed.krate {
+        let items : Vec<&Item> = krate.module.items.iter().map(|item| &**item).collect();
+        resolver.scopes[0].definitions = resolver.declare_items(&items);
+        for item in items {
+            resolver.visit_item(item);
+        }
+    }
+    if let Some(ref fragment) = parsed.fragment {
+        fragment.walk(&mut resolver);
+    }
+    resolver
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace {
+    Value,
+    Type,
+    Lifetime,
+    Label,
+    Macro,
+}
+
+const NAMESPACES : [Namespace; 5] =
+    [Namespace::Value, Namespace::Type, Namespace::Lifetime, Namespace::Label, Namespace::Macro];
+
+impl Definition {
+    fn in_namespace(&self, namespace : Namespace) -> bool {
+        use source_model::StructureElementKind::*;
+
+        match self.kind {
+            DefinitionKind::Local | DefinitionKind::Param => namespace == Namespace::Value,
+            DefinitionKind::GenericParam => namespace == Namespace::Type,
+            DefinitionKind::Lifetime => namespace == Namespace::Lifetime,
+            DefinitionKind::Label => namespace == Namespace::Label,
+            DefinitionKind::Item(kind) => match kind {
+                Var | Function => namespace == Namespace::Value,
+                // Unit and tuple structs are also values
+                Struct | EnumVariant => namespace == Namespace::Value || namespace == Namespace::Type,
+                Union | Enum | Trait | TypeAlias | Mod | ExternCrate => namespace == Namespace::Type,
+                MacroDef => namespace == Namespace::Macro,
+                Use => namespace != Namespace::Lifetime && namespace != Namespace::Label,
+                Impl => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScopeKind {
+    /// A module: names of enclosing scopes are not visible.
+    Module,
+    /// An item: only the items of enclosing scopes are visible.
+    Item,
+    /// A method or closure: the labels of enclosing scopes are not visible.
+    Function,
+    Block,
+}
+
+struct Scope {
+    kind : ScopeKind,
+    definitions : Vec<usize>,
+}
+
+/// Where the members of a path are looked up.
+#[derive(Debug, Clone, Copy)]
+enum Container {
+    /// The root module.
+    Root,
+    Definition(usize),
+}
+
+/// The path of a name imported by a `use` item, resolved when the name is used.
+struct Import {
+    /// The definitions of the modules enclosing the `use` item, from the outermost one.
+    module_path : Vec<usize>,
+    segments : Vec<String>,
+    /// Whether the name is imported under another name (with `as`).
+    renamed : bool,
+}
+
+/// The maximum number of imports followed to resolve an import.
+const MAX_IMPORT_DEPTH : usize = 8;
+
+struct NameResolver<'a> {
+    codemap : &'a CodeMap,
+    line_index : &'a LineIndex,
+    definitions : Vec<Definition>,
+    references : Vec<NameReference>,
+    scopes : Vec<Scope>,
+    /// The definitions of the modules enclosing the current position, from the outermost one.
+    module_path : Vec<usize>,
+    /// The items of a module, the variants of an enum, the items of a trait, and the items of
+    /// the impls of a type, which are only reachable through paths.
+    members : HashMap<usize, Vec<usize>>,
+    /// The definition of each item, by the offsets of the item span.
+    item_definitions : HashMap<(u32, u32), usize>,
+    imports : HashMap<usize, Import>,
+    /// The methods of all traits and impls.
+    methods : Vec<usize>,
+    /// The kind of the definitions of the pattern bindings being visited.
+    binding_kind : DefinitionKind,
+    /// The declaration of the pattern bindings being visited, if other than the pattern itself.
+    binding_decl : Option<Span>,
+    /// Whether visiting an alternative of a match arm other than the first one, whose bindings are
+    /// references to those of the first one.
+    in_alternative : bool,
+    /// Whether the expression being visited is the target of an assignment.
+    in_assignee : bool,
+    /// The offset at which to collect the visible definitions, if any.
+    position : Option<usize>,
+    /// The definitions visible at the position, and whether it's in the body of a function or closure.
+    /// These are collected in the innermost node containing the position.
+    visible_at_position : Option<(Vec<usize>, bool)>,
+}
+
+fn span_key(span : Span) -> (u32, u32) {
+    (span.lo.0, span.hi.0)
+}
+
+impl<'a> NameResolver<'a> {
+
+    fn span_offsets(&self, span : Span) -> (usize, usize) {
+        (file_offset(self.codemap, span.lo), file_offset(self.codemap, span.hi))
+    }
+
+    fn span_range(&self, span : Span) -> SourceRange {
+        SourceRange::from_span(self.codemap, self.line_index, span)
+    }
+
+    /// The offsets of the first occurrence of given name in the source of given span, starting at `from`.
+    fn find_name(&self, name : &str, span : Span, from : usize) -> Option<(usize, usize)> {
+        let (start, end) = self.span_offsets(span);
+        if from < start || from > end {
+            return None;
+        }
+        find_identifier(&self.line_index.source()[from..end], name).map(|ix| (from + ix, from + ix + name.len()))
+    }
+
+    fn find_name_in(&self, name : &str, span : Span) -> Option<(usize, usize)> {
+        self.find_name(name, span, file_offset(self.codemap, span.lo))
+    }
+
+    /// The offsets of each segment of given path, if found.
+    fn segment_offsets(&self, path : &Path) -> Vec<Option<(usize, usize)>> {
+        let mut from = file_offset(self.codemap, path.span.lo);
+        let mut offsets = vec![];
+
+        for segment in &path.segments {
+            let offset = self.find_name(&segment.identifier.name.as_str(), path.span, from);
+            if let Some((_, end)) = offset {
+                from = end;
+            }
+            offsets.push(offset);
+        }
+        offsets
+    }
+
+    fn define(&mut self, name : &str, kind : DefinitionKind, name_offsets : Option<(usize, usize)>,
+        decl_span : Span) -> usize
+    {
+        let decl_range = self.span_range(decl_span);
+        let name_range = name_offsets.map_or(decl_range, |(start, end)| self.line_index.range_at(start, end));
+        let namespace = match kind {
+            DefinitionKind::Local | DefinitionKind::Param => Some(Namespace::Value),
+            DefinitionKind::GenericParam => Some(Namespace::Type),
+            DefinitionKind::Lifetime => Some(Namespace::Lifetime),
+            DefinitionKind::Label => Some(Namespace::Label),
+            DefinitionKind::Item(_) => None,
+        };
+        let shadowed = namespace.and_then(|namespace| self.lookup(name, namespace));
+
+        self.definitions.push(Definition {
+            name : String::from(name), kind : kind, name_range : name_range, decl_range : decl_range,
+            shadowed : shadowed,
+        });
+        let definition = self.definitions.len() - 1;
+
+        if name_offsets.is_some() {
+            self.references.push(NameReference { 
+                sourcerange : name_range, definition : definition, access : Access::Declaration
+            });
+        }
+        definition
+    }
+
+    fn add_reference(&mut self, offsets : (usize, usize), definition : usize) {
+        self.add_access(offsets, definition, Access::Read);
+    }
+
+    fn add_access(&mut self, offsets : (usize, usize), definition : usize, access : Access) {
+        let sourcerange = self.line_index.range_at(offsets.0, offsets.1);
+        self.references.push(NameReference { sourcerange : sourcerange, definition : definition, access : access });
+    }
+
+    fn push_scope(&mut self, kind : ScopeKind) {
+        self.scopes.push(Scope { kind : kind, definitions : vec![] });
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn add_to_scope(&mut self, definition : usize) {
+        self.scopes.last_mut().unwrap().definitions.push(definition);
+    }
+
+    /// Whether the position is within given span (inclusive of its ends).
+    fn at_position(&self, span : Span) -> bool {
+        let (start, end) = self.span_offsets(span);
+        self.position.map_or(false, |position| start <= position && position <= end)
+    }
+
+    fn collect_visible_at_position(&mut self) {
+        let in_body = self.scopes.iter().rev()
+            .find(|scope| scope.kind != ScopeKind::Function)
+            .map_or(false, |scope| scope.kind == ScopeKind::Block);
+        self.visible_at_position = Some((self.visible_definitions(), in_body));
+    }
+
+    /* ----------------- declarations ----------------- */
+
+    /// Define given items, and the members reachable through them. Returns the definitions of the names
+    /// the items add to their scope.
+    fn declare_items(&mut self, items : &[&Item]) -> Vec<usize> {
+        let mut definitions = vec![];
+
+        for item in items {
+            match item.node {
+                ItemKind::Use(ref view_path) => definitions.extend(self.declare_imports(view_path, item.span)),
+                ItemKind::ForeignMod(ref foreign_mod) => {
+                    for foreign_item in &foreign_mod.items {
+                        let kind = match foreign_item.node {
+                            ForeignItemKind::Fn(..) => StructureElementKind::Function,
+                            ForeignItemKind::Static(..) => StructureElementKind::Var,
+                        };
+                        let name = foreign_item.ident.name.as_str();
+                        let offsets = self.find_name_in(&name, foreign_item.span);
+                        definitions.push(self.define(&name, DefinitionKind::Item(kind), offsets, foreign_item.span));
+                    }
+                }
+                _ => {
+                    if let Some(definition) = self.declare_item(item) {
+                        definitions.push(definition);
+                    }
+                }
+            }
+        }
+
+        // The items of impls are members of the implemented type
+        for item in items {
+            if let ItemKind::Impl(_, _, _, _, ref self_ty, ref impl_items) = item.node {
+                let impl_definitions = self.declare_impl_items(impl_items);
+
+                let type_name = match self_ty.node {
+                    TyKind::Path(None, ref path) if path.segments.len() == 1 => {
+                        Some(path.segments[0].identifier.name.as_str())
+                    }
+                    _ => None,
+                };
+                let type_definition = type_name.and_then(|type_name| definitions.iter().cloned().find(|&def| {
+                    self.definitions[def].name == &*type_name && self.definitions[def].in_namespace(Namespace::Type)
+                }));
+                if let Some(type_definition) = type_definition {
+                    self.members.entry(type_definition).or_insert(vec![]).extend(impl_definitions);
+                }
+            }
+        }
+        definitions
+    }
+
+    fn declare_item(&mut self, item : &Item) -> Option<usize> {
+        let kind = match item.node {
+            ItemKind::ExternCrate(_) => StructureElementKind::ExternCrate,
+            ItemKind::Static(..) | ItemKind::Const(..) => StructureElementKind::Var,
+            ItemKind::Fn(..) => StructureElementKind::Function,
+            ItemKind::Mod(_) => StructureElementKind::Mod,
+            ItemKind::Ty(..) => StructureElementKind::TypeAlias,
+            ItemKind::Enum(..) => StructureElementKind::Enum,
+            ItemKind::Struct(..) => StructureElementKind::Struct,
+            ItemKind::Union(..) => StructureElementKind::Union,
+            ItemKind::Trait(..) => StructureElementKind::Trait,
+            // A `macro_rules!` definition has the macro name as ident
+            ItemKind::Mac(_) if !item.ident.name.as_str().is_empty() => StructureElementKind::MacroDef,
+            ItemKind::Mac(_) | ItemKind::Use(_) | ItemKind::ForeignMod(_) | ItemKind::DefaultImpl(..) |
+            ItemKind::Impl(..) => return None,
+        };
+
+        let name_span = match item.node {
+            // Skip the `macro_rules` name
+            ItemKind::Mac(ref mac) => Span { lo : mac.node.path.span.hi, .. item.span },
+            _ => item.span,
+        };
+        let name = item.ident.name.as_str();
+        let offsets = self.find_name_in(&name, name_span);
+        let definition = self.define(&name, DefinitionKind::Item(kind), offsets, item.span);
+        self.item_definitions.insert(span_key(item.span), definition);
+
+        let members = match item.node {
+            ItemKind::Mod(ref module) => {
+                self.module_path.push(definition);
+                let items : Vec<&Item> = module.items.iter().map(|item| &**item).collect();
+                let members = self.declare_items(&items);
+                self.module_path.pop();
+                members
+            }
+            ItemKind::Enum(ref enum_def, _) => {
+                enum_def.variants.iter().map(|variant| {
+                    let name = variant.node.name.name.as_str();
+                    let offsets = self.find_name_in(&name, variant.span);
+                    self.define(&name, DefinitionKind::Item(StructureElementKind::EnumVariant), offsets,
+                        variant.span)
+                }).collect()
+            }
+            ItemKind::Trait(_, _, _, ref trait_items) => {
+                trait_items.iter().filter_map(|trait_item| {
+                    let kind = match trait_item.node {
+                        TraitItemKind::Const(..) => StructureElementKind::Var,
+                        TraitItemKind::Method(..) => StructureElementKind::Function,
+                        TraitItemKind::Type(..) => StructureElementKind::TypeAlias,
+                        TraitItemKind::Macro(_) => return None,
+                    };
+                    let definition = self.declare_member(trait_item.ident, kind, trait_item.span);
+                    if kind == StructureElementKind::Function {
+                        self.methods.push(definition);
+                    }
+                    Some(definition)
+                }).collect()
+            }
+            _ => vec![],
+        };
+        if !members.is_empty() {
+            self.members.insert(definition, members);
+        }
+        Some(definition)
+    }
+
+    fn declare_impl_items(&mut self, impl_items : &[ImplItem]) -> Vec<usize> {
+        impl_items.iter().filter_map(|impl_item| {
+            let kind = match impl_item.node {
+                ImplItemKind::Const(..) => StructureElementKind::Var,
+                ImplItemKind::Method(..) => StructureElementKind::Function,
+                ImplItemKind::Type(_) => StructureElementKind::TypeAlias,
+                ImplItemKind::Macro(_) => return None,
+            };
+            let definition = self.declare_member(impl_item.ident, kind, impl_item.span);
+            if kind == StructureElementKind::Function {
+                self.methods.push(definition);
+            }
+            Some(definition)
+        }).collect()
+    }
+
+    fn declare_member(&mut self, ident : Ident, kind : StructureElementKind, span : Span) -> usize {
+        let name = ident.name.as_str();
+        let offsets = self.find_name_in(&name, span);
+        self.define(&name, DefinitionKind::Item(kind), offsets, span)
+    }
+
+    /// Define the names imported by a `use` item.
+    fn declare_imports(&mut self, view_path : &ViewPath, item_span : Span) -> Vec<usize> {
+        let mut definitions = vec![];
+
+        match view_path.node {
+            ViewPathSimple(ident, ref path) => {
+                let segments = path_segment_names(path);
+                let name = ident.name.as_str();
+                let renamed = segments.last().map_or(true, |last| *last != &*name);
+                let offsets = if renamed {
+                    self.find_name(&name, view_path.span, file_offset(self.codemap, path.span.hi))
+                } else {
+                    self.segment_offsets(path).pop().and_then(|offsets| offsets)
+                };
+                definitions.push(self.declare_import(&name, offsets, item_span, segments, renamed));
+            }
+            ViewPathList(ref prefix, ref list_items) => {
+                let prefix_segments = path_segment_names(prefix);
+                for list_item in list_items {
+                    let name = list_item.node.name.name.as_str();
+                    let mut segments = prefix_segments.clone();
+                    if &*name != "self" {
+                        segments.push(String::from(&*name));
+                    }
+                    let imported_name = match list_item.node.rename {
+                        Some(rename) => String::from(&*rename.name.as_str()),
+                        None => segments.last().cloned().unwrap_or(String::new()),
+                    };
+                    let renamed = list_item.node.rename.is_some();
+
+                    // The name is `self` for the last segment of the prefix
+                    let offsets = self.find_name_in(&name, list_item.span);
+                    let offsets = if renamed {
+                        offsets.and_then(|(_, end)| self.find_name(&imported_name, list_item.span, end))
+                    } else {
+                        offsets
+                    };
+                    definitions.push(self.declare_import(&imported_name, offsets, item_span, segments, renamed));
+                }
+            }
+            ViewPathGlob(_) => { }
+        }
+        definitions
+    }
+
+    fn declare_import(&mut self, name : &str, offsets : Option<(usize, usize)>, item_span : Span,
+        segments : Vec<String>, renamed : bool) -> usize
+    {
+        let definition = self.define(name, DefinitionKind::Item(StructureElementKind::Use), offsets, item_span);
+        let import = Import { module_path : self.module_path.clone(), segments : segments, renamed : renamed };
+        self.imports.insert(definition, import);
+        definition
+    }
+
+    /// Declare the bindings of given pattern, which is the whole declaration of its bindings.
+    fn declare_pattern_bindings(&mut self, pat : &Pat) {
+        self.binding_decl = Some(pat.span);
+        self.visit_pat(pat);
+        self.binding_decl = None;
+    }
+
+    fn declare_binding(&mut self, ident : &SpannedIdent) {
+        let name = ident.node.name.as_str();
+        let offsets = self.span_offsets(ident.span);
+
+        if self.in_alternative {
+            let first = self.scopes.last().unwrap().definitions.iter().cloned()
+                .find(|&def| self.definitions[def].name == &*name);
+            if let Some(first) = first {
+                self.add_access(offsets, first, Access::Declaration);
+                return;
+            }
+        }
+        let decl_span = self.binding_decl.unwrap_or(ident.span);
+        let kind = self.binding_kind;
+        let definition = self.define(&name, kind, Some(offsets), decl_span);
+        self.add_to_scope(definition);
+    }
+
+    fn declare_label(&mut self, label : &Option<SpannedIdent>) {
+        if let Some(ref label) = *label {
+            let offsets = self.span_offsets(label.span);
+            let definition = self.define(&label.node.name.as_str(), DefinitionKind::Label, Some(offsets), label.span);
+            self.add_to_scope(definition);
+        }
+    }
+
+    fn declare_lifetime(&mut self, lifetime_def : &LifetimeDef) {
+        let lifetime = &lifetime_def.lifetime;
+        let offsets = self.span_offsets(lifetime.span);
+        let definition = self.define(&lifetime.name.as_str(), DefinitionKind::Lifetime, Some(offsets), lifetime.span);
+        self.add_to_scope(definition);
+    }
+
+    /// Visit the parameters of given function declaration, and its return type.
+    fn visit_params(&mut self, decl : &FnDecl) {
+        for arg in &decl.inputs {
+            let decl_span = if arg.ty.span.hi > arg.pat.span.hi {
+                Span { hi : arg.ty.span.hi, .. arg.pat.span }
+            } else {
+                arg.pat.span
+            };
+            self.binding_kind = DefinitionKind::Param;
+            self.binding_decl = Some(decl_span);
+            self.visit_pat(&arg.pat);
+            self.binding_kind = DefinitionKind::Local;
+            self.binding_decl = None;
+
+            self.visit_ty(&arg.ty);
+        }
+        self.visit_fn_ret_ty(&decl.output);
+    }
+
+    /* ----------------- lookup ----------------- */
+
+    /// The definition of given name visible in the current scope.
+    fn lookup(&self, name : &str, namespace : Namespace) -> Option<usize> {
+        let mut items_only = false;
+        let mut labels_visible = true;
+
+        for scope in self.scopes.iter().rev() {
+            let visible = namespace != Namespace::Label || labels_visible;
+            let found = scope.definitions.iter().rev().cloned().find(|&def| {
+                let definition = &self.definitions[def];
+                visible && definition.name == name && definition.in_namespace(namespace) &&
+                    (!items_only || is_item(definition))
+            });
+            if let Some(found) = found {
+                return Some(self.resolve_import(found, 0).unwrap_or(found));
+            }
+
+            match scope.kind {
+                ScopeKind::Module => break,
+                ScopeKind::Item => items_only = true,
+                ScopeKind::Function => labels_visible = false,
+                ScopeKind::Block => { }
+            }
+        }
+        None
+    }
+
+    /// All the definitions visible in the current scope, from the innermost one, without the shadowed ones.
+    fn visible_definitions(&self) -> Vec<usize> {
+        let mut visible : Vec<usize> = vec![];
+        let mut items_only = false;
+        let mut labels_visible = true;
+
+        for scope in self.scopes.iter().rev() {
+            for def in scope.definitions.iter().rev().cloned() {
+                let definition = &self.definitions[def];
+                let hidden = items_only && !is_item(definition) ||
+                    !labels_visible && definition.kind == DefinitionKind::Label;
+                let shadowed = visible.iter().any(|&other| {
+                    let other = &self.definitions[other];
+                    other.name == definition.name && NAMESPACES.iter().any(|&namespace| {
+                        other.in_namespace(namespace) && definition.in_namespace(namespace)
+                    })
+                });
+                if !hidden && !shadowed {
+                    visible.push(def);
+                }
+            }
+
+            match scope.kind {
+                ScopeKind::Module => break,
+                ScopeKind::Item => items_only = true,
+                ScopeKind::Function => labels_visible = false,
+                ScopeKind::Block => { }
+            }
+        }
+        visible
+    }
+
+    /// The definition imported by given import definition, if it's found in this source and not renamed.
+    fn resolve_import(&self, definition : usize, depth : usize) -> Option<usize> {
+        let import = match self.imports.get(&definition) {
+            Some(import) if !import.renamed && depth < MAX_IMPORT_DEPTH => import,
+            _ => return None,
+        };
+        let resolved = self.resolve_import_path(&import.module_path, &import.segments, depth + 1);
+        if resolved.len() == import.segments.len() {
+            resolved.last().and_then(|&definition| definition)
+        } else {
+            None
+        }
+    }
+
+    /// Resolve the segments of a `use` path, relative to the root module unless it starts with `self` or
+    /// `super`. Returns the definition of each segment up to the first one not found (`None` for `self` and
+    /// `super`).
+    fn resolve_import_path(&self, module_path : &[usize], segments : &[String], depth : usize)
+        -> Vec<Option<usize>>
+    {
+        let mut resolved : Vec<Option<usize>> = vec![];
+        let mut container = Container::Root;
+        let mut module_depth = module_path.len();
+
+        for (ix, segment) in segments.iter().enumerate() {
+            if segment == "self" && ix == 0 || segment == "super" && resolved.iter().all(|def| def.is_none()) {
+                if segment == "super" {
+                    module_depth = module_depth.saturating_sub(1);
+                }
+                container = if module_depth == 0 {
+                    Container::Root
+                } else {
+                    Container::Definition(module_path[module_depth - 1])
+                };
+                resolved.push(None);
+                continue;
+            }
+
+            match self.lookup_member(container, segment, depth) {
+                Some(definition) => {
+                    resolved.push(Some(definition));
+                    container = Container::Definition(definition);
+                }
+                None => break,
+            }
+        }
+        resolved
+    }
+
+    /// The member of given container with given name.
+    fn lookup_member(&self, container : Container, name : &str, depth : usize) -> Option<usize> {
+        let members = match container {
+            Container::Root => Some(&self.scopes[0].definitions),
+            Container::Definition(definition) => self.members.get(&definition),
+        };
+        let found = members.and_then(|members| members.iter().cloned().find(|&def| self.definitions[def].name == name));
+        found.map(|found| self.resolve_import(found, depth).unwrap_or(found))
+    }
+
+    /// Resolve the segments of given path, adding a reference for each one found.
+    /// The last segment is looked up in given namespace, the others are modules or types.
+    fn resolve_path(&mut self, path : &Path, namespace : Namespace) -> Option<usize> {
+        let offsets = self.segment_offsets(path);
+        let mut container = if path.global { Some(Container::Root) } else { None };
+        let mut module_depth = self.module_path.len();
+        let mut definition = None;
+
+        for (ix, segment) in path.segments.iter().enumerate() {
+            let name = segment.identifier.name.as_str();
+            let is_last = ix == path.segments.len() - 1;
+
+            if !is_last && (&*name == "self" && ix == 0 || &*name == "super" && definition.is_none()) {
+                if &*name == "super" {
+                    module_depth = module_depth.saturating_sub(1);
+                }
+                container = Some(if module_depth == 0 {
+                    Container::Root
+                } else {
+                    Container::Definition(self.module_path[module_depth - 1])
+                });
+                continue;
+            }
+
+            definition = match container {
+                None => self.lookup(&name, if is_last { namespace } else { Namespace::Type }),
+                Some(container) => self.lookup_member(container, &name, 0),
+            };
+            match (definition, offsets[ix]) {
+                (Some(found), Some(offsets)) => self.add_reference(offsets, found),
+                (Some(_), None) => { }
+                (None, _) => return None,
+            }
+            container = definition.map(Container::Definition);
+        }
+        definition
+    }
+
+    /// Resolve the paths of a `use` item.
+    fn resolve_use_item(&mut self, view_path : &ViewPath) {
+        let (path, list_items) = match view_path.node {
+            ViewPathSimple(_, ref path) | ViewPathGlob(ref path) => (path, &[][..]),
+            ViewPathList(ref prefix, ref list_items) => (prefix, &list_items[..]),
+        };
+
+        let segments = path_segment_names(path);
+        let module_path = self.module_path.clone();
+        let resolved = self.resolve_import_path(&module_path, &segments, 0);
+        for (definition, offsets) in resolved.iter().zip(self.segment_offsets(path)) {
+            if let (&Some(definition), Some(offsets)) = (definition, offsets) {
+                self.add_reference(offsets, definition);
+            }
+        }
+        if resolved.len() != segments.len() {
+            return;
+        }
+
+        let container = match resolved.last() {
+            Some(&Some(definition)) => Container::Definition(definition),
+            _ => return,
+        };
+        for list_item in list_items {
+            let name = list_item.node.name.name.as_str();
+            if &*name == "self" {
+                continue;
+            }
+            let definition = self.lookup_member(container, &name, 0);
+            let offsets = self.find_name_in(&name, list_item.span);
+            if let (Some(definition), Some(offsets)) = (definition, offsets) {
+                self.add_reference(offsets, definition);
+            }
+        }
+    }
+
+    /// Resolve the identifiers in the arguments of a macro invocation, as values. Macro arguments are not
+    /// parsed, so these are only the identifiers that are not field or method names.
+    fn resolve_token_trees(&mut self, tts : &[TokenTree]) {
+        let mut after_dot = false;
+        for tt in tts {
+            match *tt {
+                TokenTree::Token(span, token::Ident(ident)) if !after_dot => {
+                    if let Some(definition) = self.lookup(&ident.name.as_str(), Namespace::Value) {
+                        let offsets = self.span_offsets(span);
+                        self.add_reference(offsets, definition);
+                    }
+                }
+                TokenTree::Delimited(_, ref delimited) => self.resolve_token_trees(&delimited.tts),
+                TokenTree::Sequence(_, ref sequence) => self.resolve_token_trees(&sequence.tts),
+                _ => { }
+            }
+            after_dot = match *tt {
+                TokenTree::Token(_, token::Dot) | TokenTree::Token(_, token::ModSep) => true,
+                _ => false,
+            };
+        }
+    }
+
+}
+
+fn is_item(definition : &Definition) -> bool {
+    match definition.kind {
+        DefinitionKind::Item(_) => true,
+        _ => false,
+    }
+}
+
+fn path_segment_names(path : &Path) -> Vec<String> {
+    path.segments.iter().map(|segment| String::from(&*segment.identifier.name.as_str())).collect()
+}
+
+impl<'a> Visitor for NameResolver<'a> {
+
+    fn visit_item(&mut self, item : &Item) {
+        match item.node {
+            ItemKind::Mod(ref module) => {
+                let definition = self.item_definitions.get(&span_key(item.span)).cloned();
+                let members = definition.and_then(|def| self.members.get(&def).cloned()).unwrap_or(vec![]);
+
+                self.module_path.extend(definition);
+                self.scopes.push(Scope { kind : ScopeKind::Module, definitions : members });
+                if self.at_position(item.span) {
+                    self.collect_visible_at_position();
+                }
+                for item in &module.items {
+                    self.visit_item(item);
+                }
+                self.pop_scope();
+                if definition.is_some() {
+                    self.module_path.pop();
+                }
+            }
+            ItemKind::Use(ref view_path) => self.resolve_use_item(view_path),
+            ItemKind::Mac(ref mac) => {
+                // The body of a `macro_rules!` definition is not resolved
+                if item.ident.name.as_str().is_empty() {
+                    self.visit_mac(mac);
+                }
+            }
+            _ => {
+                self.push_scope(ScopeKind::Item);
+                let at_position = self.at_position(item.span);
+                if at_position {
+                    self.visible_at_position = None;
+                }
+                walk_item(self, item);
+                // Where no node of the item contains the position, its generic parameters are visible
+                if at_position && self.visible_at_position.is_none() {
+                    self.collect_visible_at_position();
+                }
+                self.pop_scope();
+            }
+        }
+    }
+
+    fn visit_foreign_item(&mut self, foreign_item : &ForeignItem) {
+        self.push_scope(ScopeKind::Function);
+        match foreign_item.node {
+            ForeignItemKind::Fn(ref decl, ref generics) => {
+                self.visit_generics(generics);
+                self.visit_params(decl);
+            }
+            ForeignItemKind::Static(ref ty, _) => self.visit_ty(ty),
+        }
+        self.pop_scope();
+    }
+
+    fn visit_trait_item(&mut self, trait_item : &TraitItem) {
+        self.push_scope(ScopeKind::Function);
+        match trait_item.node {
+            TraitItemKind::Method(ref sig, None) => {
+                self.visit_generics(&sig.generics);
+                self.visit_params(&sig.decl);
+            }
+            _ => walk_trait_item(self, trait_item),
+        }
+        self.pop_scope();
+    }
+
+    fn visit_impl_item(&mut self, impl_item : &ImplItem) {
+        self.push_scope(ScopeKind::Function);
+        walk_impl_item(self, impl_item);
+        self.pop_scope();
+    }
+
+    fn visit_fn(&mut self, kind : FnKind, decl : &FnDecl, body : &Block, _span : Span, _id : NodeId) {
+        match kind {
+            FnKind::ItemFn(_, generics, _, _, _, _) => self.visit_generics(generics),
+            FnKind::Method(_, sig, _) => self.visit_generics(&sig.generics),
+            FnKind::Closure => { }
+        }
+        self.visit_params(decl);
+        self.visit_block(body);
+    }
+
+    fn visit_block(&mut self, block : &Block) {
+        let items : Vec<&Item> = block.stmts.iter().filter_map(|stmt| match stmt.node {
+            StmtKind::Item(ref item) => Some(&**item),
+            _ => None,
+        }).collect();
+
+        self.push_scope(ScopeKind::Block);
+        let definitions = self.declare_items(&items);
+        self.scopes.last_mut().unwrap().definitions = definitions;
+
+        // The names visible at a position between statements are those declared by the statements before it
+        let (start, end) = self.span_offsets(block.span);
+        let position = self.position.and_then(|position| {
+            if start < position && position < end { Some(position) } else { None }
+        });
+        if position.is_some() {
+            self.collect_visible_at_position();
+        }
+        for stmt in &block.stmts {
+            self.visit_stmt(stmt);
+            if position.map_or(false, |position| file_offset(self.codemap, stmt.span.hi) <= position) {
+                self.collect_visible_at_position();
+            }
+        }
+        self.pop_scope();
+    }
+
+    fn visit_local(&mut self, local : &Local) {
+        // The bindings are not visible in the initializer
+        if let Some(ref ty) = local.ty {
+            self.visit_ty(ty);
+        }
+        if let Some(ref init) = local.init {
+            self.visit_expr(init);
+        }
+
+        self.binding_decl = Some(local.span);
+        self.visit_pat(&local.pat);
+        self.binding_decl = None;
+    }
+
+    fn visit_arm(&mut self, arm : &Arm) {
+        self.push_scope(ScopeKind::Block);
+        for (ix, pat) in arm.pats.iter().enumerate() {
+            self.in_alternative = ix > 0;
+            self.declare_pattern_bindings(pat);
+        }
+        self.in_alternative = false;
+
+        if let Some(ref guard) = arm.guard {
+            self.visit_expr(guard);
+        }
+        self.visit_expr(&arm.body);
+        self.pop_scope();
+    }
+
+    fn visit_expr(&mut self, expr : &Expr) {
+        let is_assignee = mem::replace(&mut self.in_assignee, false);
+        if self.at_position(expr.span) {
+            self.collect_visible_at_position();
+        }
+
+        match expr.node {
+            ExprKind::Path(None, ref path) if is_assignee && path.segments.len() == 1 => {
+                let name = path.segments[0].identifier.name.as_str();
+                if let Some(definition) = self.lookup(&name, Namespace::Value) {
+                    let offsets = self.span_offsets(path.span);
+                    self.add_access(offsets, definition, Access::Write);
+                }
+            }
+            ExprKind::Path(None, ref path) => {
+                self.resolve_path(path, Namespace::Value);
+                walk_expr(self, expr);
+            }
+            ExprKind::Assign(ref lhs, ref rhs) | ExprKind::AssignOp(_, ref lhs, ref rhs) => {
+                self.in_assignee = true;
+                self.visit_expr(lhs);
+                self.in_assignee = false;
+                self.visit_expr(rhs);
+            }
+            ExprKind::Struct(ref path, _, _) => {
+                self.resolve_path(path, Namespace::Type);
+                walk_expr(self, expr);
+            }
+            ExprKind::MethodCall(ref ident, _, _) => {
+                // Without types, a method call is only resolved if there is a single method with that name
+                let name = ident.node.name.as_str();
+                let methods : Vec<usize> = self.methods.iter().cloned()
+                    .filter(|&def| self.definitions[def].name == &*name).collect();
+                if methods.len() == 1 {
+                    let offsets = self.span_offsets(ident.span);
+                    self.add_reference(offsets, methods[0]);
+                }
+                walk_expr(self, expr);
+            }
+            ExprKind::IfLet(ref pat, ref subexpr, ref block, ref else_expr) => {
+                self.visit_expr(subexpr);
+                self.push_scope(ScopeKind::Block);
+                self.declare_pattern_bindings(pat);
+                self.visit_block(block);
+                self.pop_scope();
+                if let Some(ref else_expr) = *else_expr {
+                    self.visit_expr(else_expr);
+                }
+            }
+            ExprKind::WhileLet(ref pat, ref subexpr, ref block, ref label) => {
+                self.push_scope(ScopeKind::Block);
+                self.declare_label(label);
+                self.visit_expr(subexpr);
+                self.declare_pattern_bindings(pat);
+                self.visit_block(block);
+                self.pop_scope();
+            }
+            ExprKind::ForLoop(ref pat, ref subexpr, ref block, ref label) => {
+                self.visit_expr(subexpr);
+                self.push_scope(ScopeKind::Block);
+                self.declare_label(label);
+                self.declare_pattern_bindings(pat);
+                self.visit_block(block);
+                self.pop_scope();
+            }
+            ExprKind::While(_, _, ref label) | ExprKind::Loop(_, ref label) => {
+                self.push_scope(ScopeKind::Block);
+                self.declare_label(label);
+                walk_expr(self, expr);
+                self.pop_scope();
+            }
+            ExprKind::Break(Some(ref label)) | ExprKind::Continue(Some(ref label)) => {
+                if let Some(definition) = self.lookup(&label.node.name.as_str(), Namespace::Label) {
+                    let offsets = self.span_offsets(label.span);
+                    self.add_reference(offsets, definition);
+                }
+            }
+            ExprKind::Closure(..) => {
+                self.push_scope(ScopeKind::Function);
+                walk_expr(self, expr);
+                self.pop_scope();
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+
+    fn visit_pat(&mut self, pat : &Pat) {
+        match pat.node {
+            PatKind::Ident(_, ref ident, ref subpattern) => {
+                let name = ident.node.name.as_str();
+                // An identifier pattern can also be a constant, a unit struct or a unit variant
+                let item = if subpattern.is_none() {
+                    self.lookup(&name, Namespace::Value).into_iter().find(|&def| is_item(&self.definitions[def]))
+                } else {
+                    None
+                };
+                let is_capitalized = name.chars().next().map_or(false, |ch| ch.is_uppercase());
+
+                match item {
+                    Some(item) => {
+                        let offsets = self.span_offsets(ident.span);
+                        self.add_reference(offsets, item);
+                    }
+                    // A constant or variant not defined in this source, such as `None`
+                    None if is_capitalized && subpattern.is_none() => { }
+                    None => self.declare_binding(ident),
+                }
+                if let Some(ref subpattern) = *subpattern {
+                    self.visit_pat(subpattern);
+                }
+            }
+            PatKind::Struct(ref path, ref fields, _) => {
+                self.resolve_path(path, Namespace::Type);
+                for field in fields {
+                    self.visit_pat(&field.node.pat);
+                }
+            }
+            PatKind::TupleStruct(ref path, _, _) | PatKind::Path(None, ref path) => {
+                self.resolve_path(path, Namespace::Value);
+                walk_pat(self, pat);
+            }
+            _ => walk_pat(self, pat),
+        }
+    }
+
+    fn visit_ty(&mut self, ty : &Ty) {
+        if self.at_position(ty.span) {
+            self.collect_visible_at_position();
+        }
+        if let TyKind::Path(None, ref path) = ty.node {
+            self.resolve_path(path, Namespace::Type);
+        }
+        walk_ty(self, ty);
+    }
+
+    fn visit_trait_ref(&mut self, trait_ref : &TraitRef) {
+        self.resolve_path(&trait_ref.path, Namespace::Type);
+        walk_trait_ref(self, trait_ref);
+    }
+
+    fn visit_generics(&mut self, generics : &Generics) {
+        // Generic parameters are visible in the bounds of each other
+        for lifetime_def in &generics.lifetimes {
+            self.declare_lifetime(lifetime_def);
+        }
+        for param in &generics.ty_params {
+            let name = param.ident.name.as_str();
+            let offsets = self.find_name_in(&name, param.span);
+            let definition = self.define(&name, DefinitionKind::GenericParam, offsets, param.span);
+            self.add_to_scope(definition);
+        }
+
+        for lifetime_def in &generics.lifetimes {
+            for bound in &lifetime_def.bounds {
+                self.visit_lifetime(bound);
+            }
+        }
+        for param in &generics.ty_params {
+            for bound in param.bounds.iter() {
+                self.visit_ty_param_bound(bound);
+            }
+            if let Some(ref default) = param.default {
+                self.visit_ty(default);
+            }
+        }
+        for predicate in &generics.where_clause.predicates {
+            match *predicate {
+                WherePredicate::BoundPredicate(ref predicate) => {
+                    for lifetime_def in &predicate.bound_lifetimes {
+                        self.visit_lifetime_def(lifetime_def);
+                    }
+                    self.visit_ty(&predicate.bounded_ty);
+                    for bound in predicate.bounds.iter() {
+                        self.visit_ty_param_bound(bound);
+                    }
+                }
+                WherePredicate::RegionPredicate(ref predicate) => {
+                    self.visit_lifetime(&predicate.lifetime);
+                    for bound in &predicate.bounds {
+                        self.visit_lifetime(bound);
+                    }
+                }
+                WherePredicate::EqPredicate(ref predicate) => {
+                    walk_path(self, &predicate.path);
+                    self.visit_ty(&predicate.ty);
+                }
+            }
+        }
+    }
+
+    fn visit_lifetime_def(&mut self, lifetime_def : &LifetimeDef) {
+        // A `for<'a>` lifetime
+        self.declare_lifetime(lifetime_def);
+        for bound in &lifetime_def.bounds {
+            self.visit_lifetime(bound);
+        }
+    }
+
+    fn visit_lifetime(&mut self, lifetime : &Lifetime) {
+        if let Some(definition) = self.lookup(&lifetime.name.as_str(), Namespace::Lifetime) {
+            let offsets = self.span_offsets(lifetime.span);
+            self.add_reference(offsets, definition);
+        }
+    }
+
+    fn visit_mac(&mut self, mac : &Mac) {
+        self.resolve_path(&mac.node.path, Namespace::Macro);
+        self.resolve_token_trees(&mac.node.tts);
+    }
+}
+
+
+#[test]
+fn test_resolve_names() {
+    use parse_describe::analyse_source;
+
+    /// Check the definition found at each `$` marker of given source (the markers are removed first):
+    /// the name range of the definition, as its start and end markers, or None if not found.
+    fn check_definitions(source : &str, expected : &[Option<(usize, usize)>]) {
+        let markers : Vec<usize> = source.match_indices('$').enumerate().map(|(ix, (offset, _))| offset - ix).collect();
+        let source = source.replace("$", "");
+        let line_index = LineIndex::new(&source);
+
+        let (_, resolution) = analyse_source(&source, |parsed| resolve_names(parsed));
+        let resolution = resolution.unwrap();
+
+        let definitions : Vec<Option<(usize, usize)>> = markers.iter().map(|&marker| {
+            resolution.definition_at(&line_index.position_at(marker)).map(|definition| {
+                let (start, end) = line_index.range_offsets(&definition.name_range).unwrap();
+                (markers.iter().position(|&m| m == start).unwrap(), markers.iter().position(|&m| m == end).unwrap())
+            })
+        }).collect();
+        assert_eq!(definitions, expected);
+    }
+
+    // Locals, parameters and shadowing
+    check_definitions("fn f($x$ : u32) { let $y$ = $x; let $x$ = $x + $y; $x; }", &[
+        Some((0, 1)), Some((0, 1)), Some((2, 3)), Some((2, 3)), Some((0, 1)),
+        Some((5, 6)), Some((5, 6)), Some((0, 1)), Some((2, 3)), Some((5, 6)),
+    ]);
+    // Scopes of blocks, match arms and closures
+    check_definitions("fn f() { let $a$ = 1; { let a = 2; } match a { Some(a) | Ok(a) => a, _ => $a }; |$c$| $c; }", &[
+        Some((0, 1)), Some((0, 1)), Some((0, 1)), Some((3, 4)), Some((3, 4)), Some((3, 4)),
+    ]);
+    // Items, generic parameters, lifetimes and labels
+    check_definitions("struct $Foo$<$'a$, $T$>(&$'a $T);\nfn f() -> $Foo { $'l$: loop { break $'l; } $g(); }\nfn $g$() {}", &[
+        Some((0, 1)), Some((0, 1)), Some((2, 3)), Some((2, 3)), Some((4, 5)), Some((4, 5)), Some((2, 3)),
+        Some((4, 5)), Some((0, 1)), Some((9, 10)), Some((9, 10)), Some((9, 10)), Some((13, 14)), Some((13, 14)),
+        Some((13, 14)),
+    ]);
+    // Paths and imports
+    check_definitions("mod $m$ { pub enum $E$ { $A$ } }\nuse m::$E;\nfn f($e$ : $E) { $E::$A; $m::E::A; $Vec::new(); }", &[
+        Some((0, 1)), Some((0, 1)), Some((2, 3)), Some((2, 3)), Some((4, 5)), Some((4, 5)), Some((2, 3)),
+        Some((7, 8)), Some((7, 8)), Some((2, 3)), Some((2, 3)), Some((4, 5)), Some((0, 1)), None,
+    ]);
+}
+
+#[test]
+fn test_references() {
+    use parse_describe::analyse_source;
+
+    let source = "fn f() { let mut x = 1; x += 2; x = x; }\nfn g<T>(t : T) -> T { f(); f(); t }";
+    let (_, resolution) = analyse_source(source, |parsed| resolve_names(parsed));
+    let resolution = resolution.unwrap();
+    let line_index = LineIndex::new(source);
+
+    let references = |offset : usize| -> Vec<(Access, usize)> {
+        resolution.references_at(&line_index.position_at(offset)).iter().map(|reference| {
+            (reference.access, line_index.range_offsets(&reference.sourcerange).unwrap().0)
+        }).collect()
+    };
+    let expected = vec![(Access::Declaration, 17), (Access::Write, 24), (Access::Write, 32), (Access::Read, 36)];
+    assert_eq!(references(17), expected);
+    assert_eq!(references(36), expected);
+    assert_eq!(references(3), vec![(Access::Declaration, 3), (Access::Read, 63), (Access::Read, 68)]);
+    assert_eq!(references(46), vec![(Access::Declaration, 46), (Access::Read, 53), (Access::Read, 59)]);
+    assert_eq!(references(0), vec![]);
+}
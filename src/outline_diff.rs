@@ -0,0 +1,261 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! The structural differences between two versions of a source: the structure elements that were added,
+//! removed, renamed, moved or otherwise changed.
+//!
+
+use source_model::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutlineChangeKind {
+    Added,
+    Removed,
+    /// An element with a different name, but otherwise the same text, in the same parent.
+    Renamed,
+    /// An element with the same name and text, in another parent.
+    Moved,
+    /// An element whose type description (such as a function signature) changed.
+    SignatureChanged,
+    /// An element whose text changed, other than in its signature or children.
+    Modified,
+}
+
+impl OutlineChangeKind {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            OutlineChangeKind::Added => "Added",
+            OutlineChangeKind::Removed => "Removed",
+            OutlineChangeKind::Renamed => "Renamed",
+            OutlineChangeKind::Moved => "Moved",
+            OutlineChangeKind::SignatureChanged => "SignatureChanged",
+            OutlineChangeKind::Modified => "Modified",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutlineChange {
+    pub kind : OutlineChangeKind,
+    /// The element in the old source (without its children), unless it was added.
+    pub old : Option<StructureElement>,
+    /// The element in the new source (without its children), unless it was removed.
+    pub new : Option<StructureElement>,
+}
+
+impl OutlineChange {
+    pub fn element_kind(&self) -> StructureElementKind {
+        self.new.as_ref().or(self.old.as_ref()).map(|element| element.kind).unwrap()
+    }
+}
+
+/// The changes from the old elements to the new elements, with the (char column) ranges of the elements
+/// in the sources of given line indexes.
+/// Elements are matched by ID, and then the unmatched ones by text. The children of an added or removed
+/// element are not reported.
+pub fn outline_diff(old_index : &LineIndex, old_elements : &[StructureElement],
+    new_index : &LineIndex, new_elements : &[StructureElement]) -> Vec<OutlineChange>
+{
+    let mut differ = OutlineDiffer { old_index : old_index, new_index : new_index,
+        changes : vec![], removed : vec![], added : vec![] };
+    differ.diff_children("", old_elements, "", new_elements);
+    differ.finish()
+}
+
+struct OutlineDiffer<'a> {
+    old_index : &'a LineIndex,
+    new_index : &'a LineIndex,
+    changes : Vec<OutlineChange>,
+    /// The unmatched old elements, which were removed unless moved.
+    removed : Vec<&'a StructureElement>,
+    /// The unmatched new elements, which were added unless moved.
+    added : Vec<&'a StructureElement>,
+}
+
+impl<'a> OutlineDiffer<'a> {
+
+    fn diff_children(&mut self, old_parent : &str, old_elements : &'a [StructureElement],
+        new_parent : &str, new_elements : &'a [StructureElement])
+    {
+        let mut new_matched = vec![false; new_elements.len()];
+        let mut old_unmatched = vec![];
+
+        for old in old_elements {
+            let segment = id_segment(old, old_parent);
+            let position = (0..new_elements.len()).position(|ix| {
+                !new_matched[ix] && new_elements[ix].kind == old.kind
+                    && id_segment(&new_elements[ix], new_parent) == segment
+            });
+            match position {
+                Some(ix) => {
+                    new_matched[ix] = true;
+                    self.diff_matched(old, &new_elements[ix]);
+                }
+                None => old_unmatched.push(old),
+            }
+        }
+
+        for old in old_unmatched {
+            let old_text = self.text_without_name(self.old_index, old);
+            let position = (0..new_elements.len()).position(|ix| {
+                let new = &new_elements[ix];
+                !new_matched[ix] && new.kind == old.kind && !new.name.is_empty() && new.name != old.name
+                    && self.text_without_name(self.new_index, new) == old_text
+            });
+            match position {
+                Some(ix) => {
+                    new_matched[ix] = true;
+                    let new = &new_elements[ix];
+                    self.changes.push(change(OutlineChangeKind::Renamed, Some(old), Some(new)));
+                    self.diff_children(id_path(old), &old.children, id_path(new), &new.children);
+                }
+                None => self.removed.push(old),
+            }
+        }
+
+        for (ix, new) in new_elements.iter().enumerate() {
+            if !new_matched[ix] {
+                self.added.push(new);
+            }
+        }
+    }
+
+    fn diff_matched(&mut self, old : &'a StructureElement, new : &'a StructureElement) {
+        let change_count = self.changes.len() + self.removed.len() + self.added.len();
+
+        let signature_changed = old.type_desc != new.type_desc;
+        if signature_changed {
+            self.changes.push(change(OutlineChangeKind::SignatureChanged, Some(old), Some(new)));
+        }
+        self.diff_children(id_path(old), &old.children, id_path(new), &new.children);
+
+        let children_changed = self.changes.len() + self.removed.len() + self.added.len() != change_count;
+        if old.content_hash != new.content_hash && !signature_changed && !children_changed {
+            self.changes.push(change(OutlineChangeKind::Modified, Some(old), Some(new)));
+        }
+    }
+
+    /// Report the unmatched elements: moved if there is an unmatched element with the same name and text
+    /// in the other source, otherwise removed or added.
+    fn finish(mut self) -> Vec<OutlineChange> {
+        let mut added = self.added;
+
+        for old in self.removed {
+            let position = added.iter().position(|new| {
+                new.kind == old.kind && new.name == old.name && new.content_hash == old.content_hash
+            });
+            match position {
+                Some(ix) => {
+                    let new = added.remove(ix);
+                    self.changes.push(change(OutlineChangeKind::Moved, Some(old), Some(new)));
+                }
+                None => self.changes.push(change(OutlineChangeKind::Removed, Some(old), None)),
+            }
+        }
+        for new in added {
+            self.changes.push(change(OutlineChangeKind::Added, None, Some(new)));
+        }
+
+        self.changes
+    }
+
+    /// The text of given element, without its name (its selection range).
+    fn text_without_name(&self, line_index : &LineIndex, element : &StructureElement) -> Option<String> {
+        let (start, end) = match line_index.range_offsets(&element.sourcerange) {
+            Some(offsets) => offsets,
+            None => return None,
+        };
+        let text = &line_index.source()[start..end];
+
+        match line_index.range_offsets(&element.selection_range) {
+            Some((name_start, name_end)) if start <= name_start && name_end <= end => {
+                Some(String::new() + &text[..name_start - start] + &text[name_end - start..])
+            }
+            _ => Some(String::from(text)),
+        }
+    }
+
+}
+
+fn change(kind : OutlineChangeKind, old : Option<&StructureElement>, new : Option<&StructureElement>)
+    -> OutlineChange
+{
+    let without_children = |element : &StructureElement| StructureElement { children : vec![], .. element.clone() };
+    OutlineChange { kind : kind, old : old.map(&without_children), new : new.map(&without_children) }
+}
+
+/// The ID of given element, without the kind.
+fn id_path(element : &StructureElement) -> &str {
+    element.id.find(' ').map_or(&element.id[..], |ix| &element.id[ix+1..])
+}
+
+/// The last segment of the ID path of given element, which is a child of the element with given ID path.
+fn id_segment<'e>(element : &'e StructureElement, parent_id_path : &str) -> &'e str {
+    let path = id_path(element);
+    if parent_id_path.is_empty() {
+        return path;
+    }
+    if path.starts_with(parent_id_path) && path[parent_id_path.len()..].starts_with("::") {
+        &path[parent_id_path.len() + 2..]
+    } else {
+        path
+    }
+}
+
+
+#[test]
+fn test_outline_diff() {
+    use parse_describe::{ parse_crate_with_messages, source_line_index };
+
+    fn test_diff(old_source : &str, new_source : &str, expected : Vec<(OutlineChangeKind, &str, &str)>) {
+        let (_, old_elements) = parse_crate_with_messages(old_source);
+        let (_, new_elements) = parse_crate_with_messages(new_source);
+
+        let changes = outline_diff(&source_line_index(old_source), &old_elements,
+            &source_line_index(new_source), &new_elements);
+        let changes : Vec<(OutlineChangeKind, &str, &str)> = changes.iter().map(|change| {
+            (change.kind,
+                change.old.as_ref().map_or("", |element| &element.qualified_path),
+                change.new.as_ref().map_or("", |element| &element.qualified_path))
+        }).collect();
+        assert_eq!(changes, expected);
+    }
+
+    let source = "struct Foo;\nimpl Foo {\n  fn a(x : u32) {}\n  fn b() {}\n}\nmod m { }";
+    test_diff(source, source, vec![]);
+    // Unrelated code above doesn't change anything
+    test_diff(source, &format!("\n\n{}", source), vec![]);
+
+    test_diff(source, &source.replace("fn b() {}", "fn b() { 1; }"), vec![
+        (OutlineChangeKind::Modified, "Foo::b", "Foo::b"),
+    ]);
+    test_diff(source, &source.replace("fn a(x : u32)", "fn a(x : u64)"), vec![
+        (OutlineChangeKind::SignatureChanged, "Foo::a", "Foo::a"),
+    ]);
+    test_diff(source, &source.replace("fn b()", "fn c()"), vec![
+        (OutlineChangeKind::Renamed, "Foo::b", "Foo::c"),
+    ]);
+    test_diff(source, &source.replace("struct Foo;", "struct Foo;\nstruct Bar;").replace("mod m { }", ""), vec![
+        (OutlineChangeKind::Removed, "m", ""),
+        (OutlineChangeKind::Added, "", "Bar"),
+    ]);
+    test_diff(source, &source.replace("  fn b() {}\n", "").replace("mod m { }", "mod m { fn b() {} }"), vec![
+        (OutlineChangeKind::Moved, "Foo::b", "m::b"),
+    ]);
+    // The children of a renamed module are not moved
+    test_diff("mod m { fn f() {} }", "mod n { fn f() {} }", vec![
+        (OutlineChangeKind::Renamed, "m", "n"),
+    ]);
+}
@@ -15,9 +15,27 @@
 use util::core::*;
 use util::string::*;
 use source_model::*;
+use source_input::decode_source;
+use folding::FoldingRange;
+use highlighting::LexicalToken;
+use semantic_tokens::SemanticToken;
+use syntax_tree::SyntaxNode;
+use fragment::{ ParseTarget, ParsedTarget, ParsedFragment, parse_target };
+use doc_code::{ DocSourceKind, DocCodeBlock };
+use module_tree::ModuleNode;
+use symbol_index::{ SymbolIndex, SymbolMatch, SymbolQueryKind };
+use outline_diff::OutlineChange;
+use public_api::{ ApiItem, ApiChange, apply_column_encoding_to_items, semver_level };
+use name_resolution::{ Definition, NameReference };
+use rename::TextEdit;
+use hover::Hover;
+use signature_help::SignatureHelp;
+use completion::{ Completions, CompletionItem };
+use json_writer::JsonValue;
 
 use syntex_syntax::syntax::ast;
 use syntex_syntax::parse::{ self, ParseSess };
+use syntex_syntax::parse::lexer::TokenAndSpan;
 use syntex_syntax::visit;
 use syntex_syntax::codemap::{ self, MultiSpan, CodeMap};
 use syntex_errors::{ Handler, Level, DiagnosticBuilder };
@@ -47,59 +65,1013 @@ pub fn parse_analysis_for_Stdout(source : &str) {
 }
 
 pub fn parse_analysis<T : fmt::Write + 'static>(source : &str, out : T) -> GResult<T> {
-    let (messages, elements) = parse_crate_with_messages(source);
+    parse_analysis_with_options(source, &ParseAnalysisOptions::default(), out)
+}
+
+pub fn parse_analysis_with_options<T : fmt::Write + 'static>(source : &str, options : &ParseAnalysisOptions, 
+    out : T) -> GResult<T> 
+{
+    let line_index = source_line_index(source);
+    let (messages, result) = run_analysis(source, &line_index, options);
+    
+    write_analysis(&line_index, options, messages, result, out)
+}
+
+pub fn parse_analysis_bytes_for_Stdout(bytes : &[u8], options : &ParseAnalysisOptions) {
+    parse_analysis_bytes(bytes, options, StdoutWrite(io::stdout())).ok();
+    println!("");
+    io::stdout().flush().ok();
+}
+
+/// Same as parse_analysis_with_options, but decoding the source from raw bytes first.
+/// Invalid UTF-8 is decoded lossily, and reported as warning messages.
+pub fn parse_analysis_bytes<T : fmt::Write + 'static>(bytes : &[u8], options : &ParseAnalysisOptions, out : T) 
+    -> GResult<T> 
+{
+    let decoded = decode_source(bytes);
+    let line_index = LineIndex::new(&decoded.text);
+    let (messages, result) = run_analysis(&decoded.text, &line_index, options);
+    
+    let mut all_messages = decoded.messages;
+    all_messages.extend(messages);
+    
+    write_analysis(&line_index, options, all_messages, result, out)
+}
+
+/// Write an analysis document containing only the given error, for when the source could not be read.
+pub fn input_error_analysis_for_Stdout(error_message : &str, options : &ParseAnalysisOptions) {
+    let message = SourceMessage { severity : Severity::ERROR, sourcerange : None, 
+        message : String::from(error_message) };
+    write_analysis(&LineIndex::new(""), options, vec![message], AnalysisResult::Empty, 
+        StdoutWrite(io::stdout())).ok();
+    println!("");
+    io::stdout().flush().ok();
+}
+
+/// Run the analysis of given command-line options on given source.
+pub fn run_analysis(source : &str, line_index : &LineIndex, options : &ParseAnalysisOptions) 
+    -> (Vec<SourceMessage>, AnalysisResult) 
+{
+    let target = options.parse_target;
+    
+    match options.command {
+        AnalysisCommand::Describe => {
+            if let Some(ref root_path) = options.load_modules_from {
+                use module_loader::describe_module_tree;
+                
+                let (messages, elements) = describe_module_tree(root_path, source, options.column_encoding);
+                return (messages, AnalysisResult::Elements(elements));
+            }
+            
+            let (messages, elements) = analyse_source_as(source, target, |parsed| structure_elements(parsed));
+            (messages, AnalysisResult::Elements(elements.unwrap_or(vec![])))
+        }
+        AnalysisCommand::ElementAt(position) => {
+            use element_query::find_element_path;
+            
+            let pos = position.resolve(line_index, options.column_encoding);
+            let (messages, elements) = analyse_source_as(source, target, |parsed| structure_elements(parsed));
+            let elements = elements.unwrap_or(vec![]);
+            
+            let path = find_element_path(&elements, &pos).into_iter()
+                .map(|element| StructureElement { children : vec![], .. element.clone() })
+                .collect();
+            (messages, AnalysisResult::ElementPath(path))
+        }
+        AnalysisCommand::Definition(position) => {
+            use name_resolution::resolve_names;
+            
+            let pos = position.resolve(line_index, options.column_encoding);
+            let (messages, definition) = analyse_source_as(source, target, move |parsed| {
+                resolve_names(parsed).definition_at(&pos).cloned()
+            });
+            (messages, AnalysisResult::Definition(definition.and_then(|definition| definition)))
+        }
+        AnalysisCommand::References(position) => {
+            use name_resolution::resolve_names;
+            
+            let pos = position.resolve(line_index, options.column_encoding);
+            let (messages, references) = analyse_source_as(source, target, move |parsed| {
+                resolve_names(parsed).references_at(&pos).into_iter().cloned().collect()
+            });
+            (messages, AnalysisResult::References(references.unwrap_or(vec![])))
+        }
+        AnalysisCommand::Rename(position, ref new_name) => {
+            use rename::rename;
+            
+            let pos = position.resolve(line_index, options.column_encoding);
+            let (mut messages, edits) = rename(source, target, &pos, new_name);
+            let edits = match edits {
+                Ok(edits) => edits,
+                Err(error) => {
+                    messages.push(error);
+                    vec![]
+                }
+            };
+            (messages, AnalysisResult::Rename(edits))
+        }
+        AnalysisCommand::Hover(position) => {
+            use hover::hover_at;
+            
+            let pos = position.resolve(line_index, options.column_encoding);
+            let (messages, hover) = analyse_source_as(source, target, move |parsed| hover_at(parsed, &pos));
+            (messages, AnalysisResult::Hover(hover.and_then(|hover| hover)))
+        }
+        AnalysisCommand::SignatureHelp(position) => {
+            use signature_help::signature_help;
+            
+            let pos = position.resolve(line_index, options.column_encoding);
+            let (messages, help) = signature_help(source, target, &pos);
+            (messages, AnalysisResult::SignatureHelp(help))
+        }
+        AnalysisCommand::Completion(position) => {
+            use completion::completions;
+            
+            let pos = position.resolve(line_index, options.column_encoding);
+            let (messages, completions) = completions(source, target, &pos);
+            (messages, AnalysisResult::Completion(completions))
+        }
+        AnalysisCommand::FoldingRanges => {
+            use folding::folding_ranges;
+            
+            let (messages, folds) = analyse_source_as(source, target, |parsed| folding_ranges(parsed));
+            (messages, AnalysisResult::FoldingRanges(folds.unwrap_or(vec![])))
+        }
+        AnalysisCommand::LexicalTokens => {
+            use highlighting::lexical_tokens;
+            
+            let (messages, tokens) = analyse_source_as(source, target, |parsed| lexical_tokens(parsed));
+            (messages, AnalysisResult::LexicalTokens(tokens.unwrap_or(vec![])))
+        }
+        AnalysisCommand::SemanticTokens => {
+            use semantic_tokens::semantic_tokens;
+            
+            let (messages, tokens) = analyse_source_as(source, target, |parsed| semantic_tokens(parsed));
+            (messages, AnalysisResult::SemanticTokens(tokens.unwrap_or(vec![])))
+        }
+        AnalysisCommand::SemanticTokensDelta(ref previous_data_path) => {
+            use semantic_tokens::semantic_tokens;
+            
+            let (mut messages, tokens) = analyse_source_as(source, target, |parsed| semantic_tokens(parsed));
+            let previous_data = match read_semantic_tokens_data(previous_data_path) {
+                Ok(previous_data) => previous_data,
+                Err(error) => {
+                    messages.push(SourceMessage { severity : Severity::ERROR, sourcerange : None, 
+                        message : format!("Could not read previous semantic tokens: {}", error) });
+                    vec![]
+                }
+            };
+            (messages, AnalysisResult::SemanticTokensDelta(previous_data, tokens.unwrap_or(vec![])))
+        }
+        AnalysisCommand::SyntaxTree => {
+            use syntax_tree::syntax_tree;
+            
+            let (messages, tree) = analyse_source_as(source, target, |parsed| syntax_tree(parsed));
+            (messages, AnalysisResult::SyntaxTree(tree.and_then(|tree| tree)))
+        }
+        AnalysisCommand::CheckDocCode(kind) => {
+            use doc_code::check_doc_code_blocks;
+            
+            let (messages, blocks) = check_doc_code_blocks(line_index, kind);
+            (messages, AnalysisResult::DocCodeBlocks(blocks))
+        }
+        AnalysisCommand::ModuleTree(ref path) => {
+            use module_tree::{ crate_roots, module_tree };
+            
+            match crate_roots(Path::new(path)) {
+                Ok(roots) => {
+                    let (messages, modules) = module_tree(&roots, options.column_encoding);
+                    (messages, AnalysisResult::ModuleTree(modules))
+                }
+                Err(error) => {
+                    let message = SourceMessage { severity : Severity::ERROR, sourcerange : None, 
+                        message : format!("Could not read crate roots: {}", error) };
+                    (vec![message], AnalysisResult::ModuleTree(vec![]))
+                }
+            }
+        }
+        AnalysisCommand::WorkspaceSymbols(ref dir, ref query, query_kind) => {
+            let (messages, index) = workspace_symbol_index(Path::new(dir), &options.symbol_index_file);
+            let matches = index.map_or(vec![], |index| {
+                let mut matches = index.query(query, query_kind);
+                index.apply_column_encoding(&mut matches, options.column_encoding);
+                matches
+            });
+            (messages, AnalysisResult::WorkspaceSymbols(matches))
+        }
+        AnalysisCommand::OutlineDiff(ref old_path) => {
+            use module_loader::{ read_source_file, located_message };
+            use outline_diff::outline_diff;
+            
+            let (mut messages, new_elements) = analyse_source_as(source, target, |parsed| structure_elements(parsed));
+            let old_source = match read_source_file(Path::new(old_path)) {
+                Ok(old_source) => old_source,
+                Err(error) => {
+                    messages.push(SourceMessage { severity : Severity::ERROR, sourcerange : None, 
+                        message : format!("Could not read old source: {}", error) });
+                    return (messages, AnalysisResult::OutlineDiff(vec![]));
+                }
+            };
+            let old_line_index = source_line_index(&old_source.text);
+            let mut old_messages = old_source.messages;
+            let (parse_messages, old_elements) = analyse_source_as(&old_source.text, target, 
+                |parsed| structure_elements(parsed));
+            old_messages.extend(parse_messages);
+            messages.extend(old_messages.into_iter().map(|message| located_message(old_path, message)));
+            
+            let mut changes = outline_diff(&old_line_index, &old_elements.unwrap_or(vec![]), 
+                line_index, &new_elements.unwrap_or(vec![]));
+            // The new elements are encoded with the other results, but the old ones are of another source
+            for change in changes.iter_mut() {
+                let mut old_element : Vec<StructureElement> = change.old.take().into_iter().collect();
+                apply_column_encoding(&old_line_index, options.column_encoding, &mut vec![], &mut old_element);
+                change.old = old_element.pop();
+            }
+            (messages, AnalysisResult::OutlineDiff(changes))
+        }
+        AnalysisCommand::PublicApi => {
+            use public_api::crate_public_api;
+            
+            let root_path = options.load_modules_from.as_ref().map(|path| path.as_path());
+            let (messages, items) = crate_public_api(root_path, source, options.column_encoding);
+            (messages, AnalysisResult::PublicApi(items))
+        }
+        AnalysisCommand::PublicApiDiff(ref old_path) => {
+            use module_loader::{ read_source_file, located_message };
+            use public_api::{ crate_public_api, public_api_diff };
+            
+            let root_path = options.load_modules_from.as_ref().map(|path| path.as_path());
+            let (mut messages, new_items) = crate_public_api(root_path, source, options.column_encoding);
+            let old_source = match read_source_file(Path::new(old_path)) {
+                Ok(old_source) => old_source,
+                Err(error) => {
+                    messages.push(SourceMessage { severity : Severity::ERROR, sourcerange : None, 
+                        message : format!("Could not read old source: {}", error) });
+                    return (messages, AnalysisResult::PublicApiDiff(vec![]));
+                }
+            };
+            // The modules of the old version are loaded from the directory of its crate root
+            let old_root_path = root_path.map(|_| Path::new(old_path));
+            let mut old_messages = old_source.messages;
+            let (parse_messages, old_items) = crate_public_api(old_root_path, &old_source.text, 
+                options.column_encoding);
+            old_messages.extend(parse_messages);
+            messages.extend(old_messages.into_iter().map(|message| located_message(old_path, message)));
+            
+            (messages, AnalysisResult::PublicApiDiff(public_api_diff(&old_items, &new_items)))
+        }
+    }
+}
+
+/// The symbol index of given directory. If an index file is given, the index is loaded from it (and refreshed),
+/// or built and saved to it if it can't be loaded or is of another directory.
+fn workspace_symbol_index(dir : &Path, index_file : &Option<PathBuf>) 
+    -> (Vec<SourceMessage>, Option<SymbolIndex>)
+{
+    let mut messages = vec![];
+    let new_message = |severity, message| SourceMessage { severity : severity, sourcerange : None, 
+        message : message };
+    
+    let loaded_index = index_file.as_ref()
+        .and_then(|index_file| SymbolIndex::load(index_file).ok())
+        .into_iter().filter(|index| index.root == dir).next();
+    
+    let index = match loaded_index {
+        Some(mut index) => index.refresh().map(|_| index),
+        None => SymbolIndex::build(dir),
+    };
+    let index = match index {
+        Ok(index) => index,
+        Err(error) => {
+            messages.push(new_message(Severity::ERROR, format!("Could not index `{}`: {}", dir.display(), error)));
+            return (messages, None);
+        }
+    };
+    
+    if let Some(ref index_file) = *index_file {
+        if let Err(error) = index.save(index_file) {
+            messages.push(new_message(Severity::WARNING, format!("Could not save symbol index: {}", error)));
+        }
+    }
+    (messages, Some(index))
+}
+
+/// The result of an analysis, other than its messages.
+pub enum AnalysisResult {
+    Empty,
+    Elements(Vec<StructureElement>),
+    ElementPath(Vec<StructureElement>),
+    /// The definition of the name at a position, if found.
+    Definition(Option<Definition>),
+    /// The occurrences of the name at a position, including its declaration.
+    References(Vec<NameReference>),
+    /// The edits renaming the name at a position, empty if it can't be renamed.
+    Rename(Vec<TextEdit>),
+    /// The hover information of the name at a position, if found.
+    Hover(Option<Hover>),
+    /// The signature help of the call at a position, if found.
+    SignatureHelp(Option<SignatureHelp>),
+    /// The completions at a position.
+    Completion(Completions),
+    FoldingRanges(Vec<FoldingRange>),
+    LexicalTokens(Vec<LexicalToken>),
+    SemanticTokens(Vec<SemanticToken>),
+    /// The previous semantic tokens data, and the current semantic tokens.
+    SemanticTokensDelta(Vec<u32>, Vec<SemanticToken>),
+    /// The root node of the syntax tree, or None if the source could not be parsed.
+    SyntaxTree(Option<SyntaxNode>),
+    DocCodeBlocks(Vec<DocCodeBlock>),
+    /// The root module of each crate.
+    ModuleTree(Vec<ModuleNode>),
+    WorkspaceSymbols(Vec<SymbolMatch>),
+    OutlineDiff(Vec<OutlineChange>),
+    PublicApi(Vec<ApiItem>),
+    PublicApiDiff(Vec<ApiChange>),
+}
+
+impl AnalysisResult {
+    
+    /// Convert the columns of all ranges from chars to given encoding.
+    pub fn apply_column_encoding(&mut self, line_index : &LineIndex, encoding : ColumnEncoding) {
+        match *self {
+            AnalysisResult::Empty => { }
+            AnalysisResult::Elements(ref mut elements) |
+            AnalysisResult::ElementPath(ref mut elements) => {
+                apply_column_encoding(line_index, encoding, &mut vec![], elements);
+            }
+            AnalysisResult::Definition(ref mut definition) => {
+                if let Some(ref mut definition) = *definition {
+                    definition.name_range = definition.name_range.with_column_encoding(line_index, encoding);
+                    definition.decl_range = definition.decl_range.with_column_encoding(line_index, encoding);
+                }
+            }
+            AnalysisResult::References(ref mut references) => {
+                for reference in references.iter_mut() {
+                    reference.sourcerange = reference.sourcerange.with_column_encoding(line_index, encoding);
+                }
+            }
+            AnalysisResult::Rename(ref mut edits) => {
+                for edit in edits.iter_mut() {
+                    edit.sourcerange = edit.sourcerange.with_column_encoding(line_index, encoding);
+                }
+            }
+            AnalysisResult::Hover(ref mut hover) => {
+                if let Some(ref mut hover) = *hover {
+                    hover.sourcerange = hover.sourcerange.with_column_encoding(line_index, encoding);
+                }
+            }
+            AnalysisResult::SignatureHelp(_) => { }
+            AnalysisResult::Completion(ref mut completions) => {
+                completions.sourcerange = completions.sourcerange.with_column_encoding(line_index, encoding);
+            }
+            AnalysisResult::FoldingRanges(ref mut folds) => {
+                for fold in folds.iter_mut() {
+                    fold.sourcerange = fold.sourcerange.with_column_encoding(line_index, encoding);
+                }
+            }
+            AnalysisResult::LexicalTokens(ref mut tokens) => {
+                for token in tokens.iter_mut() {
+                    token.sourcerange = token.sourcerange.with_column_encoding(line_index, encoding);
+                }
+            }
+            AnalysisResult::SemanticTokens(ref mut tokens) |
+            AnalysisResult::SemanticTokensDelta(_, ref mut tokens) => {
+                for token in tokens.iter_mut() {
+                    token.sourcerange = token.sourcerange.with_column_encoding(line_index, encoding);
+                }
+            }
+            AnalysisResult::SyntaxTree(ref mut tree) => {
+                if let Some(ref mut root) = *tree {
+                    apply_column_encoding_to_node(line_index, encoding, root);
+                }
+            }
+            AnalysisResult::DocCodeBlocks(ref mut blocks) => {
+                for block in blocks.iter_mut() {
+                    block.sourcerange = block.sourcerange.with_column_encoding(line_index, encoding);
+                }
+            }
+            // The messages of the module files are converted with the line index of each file, by `module_tree`
+            AnalysisResult::ModuleTree(_) => { }
+            // The ranges of the symbols are converted with the line index of each file, in `run_analysis`
+            AnalysisResult::WorkspaceSymbols(_) => { }
+            AnalysisResult::OutlineDiff(ref mut changes) => {
+                for change in changes.iter_mut() {
+                    let mut new_element : Vec<StructureElement> = change.new.take().into_iter().collect();
+                    apply_column_encoding(line_index, encoding, &mut vec![], &mut new_element);
+                    change.new = new_element.pop();
+                }
+            }
+            AnalysisResult::PublicApi(ref mut items) => {
+                apply_column_encoding_to_items(line_index, encoding, items);
+            }
+            AnalysisResult::PublicApiDiff(_) => { }
+        }
+    }
+    
+    pub fn write_tokens(&self, options : &ParseAnalysisOptions, tokenWriter : &mut TokenWriter) -> Void {
+        match *self {
+            AnalysisResult::Empty => Ok(()),
+            AnalysisResult::Elements(ref elements) => {
+                for element in elements {
+                    try!(write_structure_element_do(tokenWriter, element, 0, true, options.element_ids));
+                }
+                Ok(())
+            }
+            AnalysisResult::ElementPath(ref path) => write_element_path(path, options.element_ids, tokenWriter),
+            AnalysisResult::Definition(ref definition) => write_definition(definition, tokenWriter),
+            AnalysisResult::References(ref references) => write_references(references, tokenWriter),
+            AnalysisResult::Rename(ref edits) => write_rename(edits, tokenWriter),
+            AnalysisResult::Hover(ref hover) => write_hover(hover, tokenWriter),
+            AnalysisResult::SignatureHelp(ref help) => write_signature_help(help, tokenWriter),
+            AnalysisResult::Completion(ref completions) => write_completion(completions, tokenWriter),
+            AnalysisResult::FoldingRanges(ref folds) => write_folding_ranges(folds, tokenWriter),
+            AnalysisResult::LexicalTokens(ref tokens) => write_lexical_tokens(tokens, tokenWriter),
+            AnalysisResult::SemanticTokens(ref tokens) => write_semantic_tokens(tokens, tokenWriter),
+            AnalysisResult::SemanticTokensDelta(ref previous_data, ref tokens) => {
+                write_semantic_tokens_delta(previous_data, tokens, tokenWriter)
+            }
+            AnalysisResult::SyntaxTree(ref tree) => write_syntax_tree(tree, tokenWriter),
+            AnalysisResult::DocCodeBlocks(ref blocks) => write_doc_code_blocks(blocks, tokenWriter),
+            AnalysisResult::ModuleTree(ref modules) => write_module_tree(modules, tokenWriter),
+            AnalysisResult::WorkspaceSymbols(ref matches) => write_workspace_symbols(matches, tokenWriter),
+            AnalysisResult::OutlineDiff(ref changes) => write_outline_diff(changes, tokenWriter),
+            AnalysisResult::PublicApi(ref items) => write_public_api(items, tokenWriter),
+            AnalysisResult::PublicApiDiff(ref changes) => write_public_api_diff(changes, tokenWriter),
+        }
+    }
+    
+    /// The members of the JSON document object with this result.
+    pub fn to_json_members(&self) -> Vec<(&'static str, JsonValue)> {
+        match *self {
+            AnalysisResult::Empty => vec![],
+            AnalysisResult::Elements(ref elements) => {
+                vec![("elements", JsonValue::Array(elements.iter().map(element_to_json).collect()))]
+            }
+            AnalysisResult::ElementPath(ref path) => {
+                vec![("element_path", JsonValue::Array(path.iter().map(element_to_json).collect()))]
+            }
+            AnalysisResult::Definition(ref definition) => {
+                vec![("definition", definition.as_ref().map_or(JsonValue::Null, definition_to_json))]
+            }
+            AnalysisResult::References(ref references) => {
+                vec![("references", JsonValue::Array(references.iter().map(reference_to_json).collect()))]
+            }
+            AnalysisResult::Rename(ref edits) => {
+                vec![("rename", JsonValue::Array(edits.iter().map(text_edit_to_json).collect()))]
+            }
+            AnalysisResult::Hover(ref hover) => {
+                vec![("hover", hover.as_ref().map_or(JsonValue::Null, hover_to_json))]
+            }
+            AnalysisResult::SignatureHelp(ref help) => {
+                vec![("signature_help", help.as_ref().map_or(JsonValue::Null, signature_help_to_json))]
+            }
+            AnalysisResult::Completion(ref completions) => {
+                vec![("completion", completions_to_json(completions))]
+            }
+            AnalysisResult::FoldingRanges(ref folds) => {
+                vec![("folding_ranges", JsonValue::Array(folds.iter().map(folding_range_to_json).collect()))]
+            }
+            AnalysisResult::LexicalTokens(ref tokens) => {
+                vec![("lexical_tokens", JsonValue::Array(tokens.iter().map(lexical_token_to_json).collect()))]
+            }
+            AnalysisResult::SemanticTokens(ref tokens) => {
+                vec![("semantic_tokens", semantic_tokens_to_json(tokens))]
+            }
+            AnalysisResult::SemanticTokensDelta(ref previous_data, ref tokens) => {
+                vec![("semantic_tokens_delta", semantic_tokens_delta_to_json(previous_data, tokens))]
+            }
+            AnalysisResult::SyntaxTree(ref tree) => {
+                vec![("syntax_tree", tree.as_ref().map_or(JsonValue::Null, syntax_node_to_json))]
+            }
+            AnalysisResult::DocCodeBlocks(ref blocks) => {
+                vec![("doc_code_blocks", JsonValue::Array(blocks.iter().map(doc_code_block_to_json).collect()))]
+            }
+            AnalysisResult::ModuleTree(ref modules) => {
+                vec![("module_tree", JsonValue::Array(modules.iter().map(module_node_to_json).collect()))]
+            }
+            AnalysisResult::WorkspaceSymbols(ref matches) => {
+                vec![("workspace_symbols", JsonValue::Array(matches.iter().map(symbol_match_to_json).collect()))]
+            }
+            AnalysisResult::OutlineDiff(ref changes) => {
+                vec![("outline_diff", JsonValue::Array(changes.iter().map(outline_change_to_json).collect()))]
+            }
+            AnalysisResult::PublicApi(ref items) => {
+                vec![("public_api", JsonValue::Array(items.iter().map(api_item_to_json).collect()))]
+            }
+            AnalysisResult::PublicApiDiff(ref changes) => {
+                vec![("public_api_diff", JsonValue::object(vec![
+                    ("level", JsonValue::string(semver_level(changes).to_string())),
+                    ("changes", JsonValue::Array(changes.iter().map(api_change_to_json).collect())),
+                ]))]
+            }
+        }
+    }
+    
+}
+
+pub fn write_analysis<T : fmt::Write + 'static>(line_index : &LineIndex, options : &ParseAnalysisOptions,
+    mut messages: Vec<SourceMessage>, mut result: AnalysisResult, out : T) -> GResult<T> 
+{
+    apply_column_encoding(line_index, options.column_encoding, &mut messages, &mut vec![]);
+    result.apply_column_encoding(line_index, options.column_encoding);
     
     let outRc = Rc::new(RefCell::new(out));
-    try!(write_parse_analysis_do(messages, elements, outRc.clone()));
-    let res = unwrap_Rc_RefCell(outRc);
-    return Ok(res);
+    match options.output_format {
+        OutputFormat::Tokens => {
+            let mut tokenWriter = TokenWriter { out : outRc.clone() };
+            
+            try!(write_analysis_header(line_index.line_endings(), &mut tokenWriter));
+            try!(write_messages(messages, &mut tokenWriter));
+            try!(result.write_tokens(options, &mut tokenWriter));
+            try!(tokenWriter.write_raw("\n}"));
+        }
+        OutputFormat::Json => {
+            let mut members = vec![
+                ("format", JsonValue::string("RUST_PARSE_DESCRIBE")),
+                ("version", JsonValue::string("1.0")),
+                ("line_endings", JsonValue::string(line_index.line_endings().to_string())),
+                ("messages", JsonValue::Array(messages.iter().map(message_to_json).collect())),
+            ];
+            members.extend(result.to_json_members());
+            
+            try!(JsonValue::object(members).write(&mut *outRc.borrow_mut()));
+        }
+    }
+    
+    Ok(unwrap_Rc_RefCell(outRc))
+}
+
+/// Convert the columns of all given ranges from chars to given encoding.
+pub fn apply_column_encoding(line_index : &LineIndex, encoding : ColumnEncoding, 
+    messages : &mut Vec<SourceMessage>, elements : &mut Vec<StructureElement>) 
+{
+    if encoding == ColumnEncoding::Chars {
+        return;
+    }
+    
+    for message in messages.iter_mut() {
+        if let Some(sourcerange) = message.sourcerange {
+            message.sourcerange = Some(sourcerange.with_column_encoding(line_index, encoding));
+        }
+    }
+    
+    fn apply_to_elements(line_index : &LineIndex, encoding : ColumnEncoding, elements : &mut Vec<StructureElement>) {
+        for element in elements.iter_mut() {
+            element.sourcerange = element.sourcerange.with_column_encoding(line_index, encoding);
+            element.selection_range = element.selection_range.with_column_encoding(line_index, encoding);
+            element.header_range = element.header_range.with_column_encoding(line_index, encoding);
+            element.body_range = element.body_range.map(|range| range.with_column_encoding(line_index, encoding));
+            // The children of a loaded module file are not in this source
+            if element.source_file.is_none() {
+                apply_to_elements(line_index, encoding, &mut element.children);
+            }
+        }
+    }
+    apply_to_elements(line_index, encoding, elements);
+}
+
+fn apply_column_encoding_to_node(line_index : &LineIndex, encoding : ColumnEncoding, node : &mut SyntaxNode) {
+    node.sourcerange = node.sourcerange.with_column_encoding(line_index, encoding);
+    for child in node.children.iter_mut() {
+        apply_column_encoding_to_node(line_index, encoding, child);
+    }
+}
+
+/* -----------------  ----------------- */
+
+/// The operation performed by a parse analysis.
+#[derive(Debug, Clone)]
+pub enum AnalysisCommand {
+    /// Describe the parse messages and the structure elements of the source. This is the default.
+    Describe,
+    /// Find the innermost structure element enclosing a position, and its ancestors.
+    ElementAt(SourcePosition),
+    /// Find the definition of the name at a position, within the source.
+    Definition(SourcePosition),
+    /// Find the occurrences of the name at a position, within the source, for occurrence highlighting.
+    References(SourcePosition),
+    /// Rename the local variable, parameter, generic parameter, lifetime or label at a position to given name.
+    Rename(SourcePosition, String),
+    /// Describe the definition of the name at a position: its signature and doc comment.
+    Hover(SourcePosition),
+    /// Describe the parameters of the function called at a position, which can be an incomplete call.
+    SignatureHelp(SourcePosition),
+    /// Offer the keywords valid at a position, and the names visible there.
+    Completion(SourcePosition),
+    /// Compute the folding ranges of the source.
+    FoldingRanges,
+    /// Classify the lexical tokens of the source, for syntax highlighting.
+    LexicalTokens,
+    /// Classify the identifiers of the source by their syntactic role, as LSP semantic tokens.
+    SemanticTokens,
+    /// Like `SemanticTokens`, but output the edits from the semantic tokens data in given file.
+    SemanticTokensDelta(String),
+    /// Dump the full syntax tree of the source.
+    SyntaxTree,
+    /// Parse the Rust code blocks in the doc comments of a Rust source, or in a Markdown source.
+    CheckDocCode(DocSourceKind),
+    /// Output the module hierarchy of the crates of given crate root file or `Cargo.toml`, read from the 
+    /// filesystem. The source is not used.
+    ModuleTree(String),
+    /// Find the structure elements of the `.rs` files in given directory whose name matches given query.
+    /// The source is not used.
+    WorkspaceSymbols(String, String, SymbolQueryKind),
+    /// Compare the structure elements of the source in given file (the old version) with those of the source.
+    OutlineDiff(String),
+    /// Extract the public API of the source, a crate root.
+    PublicApi,
+    /// Compare the public API of the crate root in given file (the old version) with that of the source.
+    PublicApiDiff(String),
+}
+
+impl AnalysisCommand {
+    
+    /// Whether the command analyses the source given as input.
+    pub fn reads_source(&self) -> bool {
+        match *self {
+            AnalysisCommand::ModuleTree(_) | AnalysisCommand::WorkspaceSymbols(..) => false,
+            _ => true,
+        }
+    }
+    
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The block tokens language. This is the default.
+    Tokens,
+    Json,
 }
 
+/// Options for a parse analysis.
+#[derive(Debug, Clone)]
+pub struct ParseAnalysisOptions {
+    pub command : AnalysisCommand,
+    pub output_format : OutputFormat,
+    /// The units of the columns of all source ranges and positions, both in the output and the arguments.
+    pub column_encoding : ColumnEncoding,
+    /// What the source is parsed as.
+    pub parse_target : ParseTarget,
+    /// The path of the source file, if its out-of-line modules should be loaded from the filesystem.
+    pub load_modules_from : Option<PathBuf>,
+    /// The file the workspace symbol index is loaded from and saved to, if any.
+    pub symbol_index_file : Option<PathBuf>,
+    /// Whether the ID and content hash of structure elements are written (in the tokens format).
+    pub element_ids : bool,
+}
+
+impl Default for ParseAnalysisOptions {
+    fn default() -> ParseAnalysisOptions {
+        ParseAnalysisOptions { 
+            command : AnalysisCommand::Describe, 
+            output_format : OutputFormat::Tokens,
+            column_encoding : ColumnEncoding::Chars,
+            parse_target : ParseTarget::Crate,
+            load_modules_from : None,
+            symbol_index_file : None,
+            element_ids : false,
+        }
+    }
+}
+
+impl ParseAnalysisOptions {
+    
+    /// Parse options from the command line arguments (excluding the program name).
+    pub fn from_args<ARGS : Iterator<Item=String>>(args : ARGS) -> GResult<ParseAnalysisOptions> {
+        let mut options = ParseAnalysisOptions::default();
+        let mut positional_args = vec![];
+        
+        for arg in args {
+            if arg.starts_with("--columns=") {
+                let value = &arg["--columns=".len()..];
+                options.column_encoding = try!(ColumnEncoding::from_string(value).ok_or_else(|| 
+                    format!("Invalid column encoding `{}`, expected one of: chars, utf16, bytes.", value)
+                ));
+            } else if arg.starts_with("--format=") {
+                options.output_format = match &arg["--format=".len()..] {
+                    "tokens" => OutputFormat::Tokens,
+                    "json" => OutputFormat::Json,
+                    value => return Err(format!("Invalid output format `{}`, expected one of: tokens, json.", 
+                        value).into()),
+                };
+            } else if arg.starts_with("--target=") {
+                let value = &arg["--target=".len()..];
+                options.parse_target = try!(ParseTarget::from_string(value).ok_or_else(|| 
+                    format!("Invalid parse target `{}`, expected one of: crate, items, statements, expr, type, \
+                        pattern, attribute.", value)
+                ));
+            } else if arg.starts_with("--load-modules=") {
+                options.load_modules_from = Some(PathBuf::from(&arg["--load-modules=".len()..]));
+            } else if arg.starts_with("--symbol-index=") {
+                options.symbol_index_file = Some(PathBuf::from(&arg["--symbol-index=".len()..]));
+            } else if arg == "--element-ids" {
+                options.element_ids = true;
+            } else if arg.starts_with("--") {
+                return Err(format!("Unknown option `{}`.", arg).into());
+            } else {
+                positional_args.push(arg);
+            }
+        }
+        
+        let mut positional_args = positional_args.into_iter();
+        options.command = match positional_args.next() {
+            None => AnalysisCommand::Describe,
+            Some(command) => match &command as &str {
+                "describe" => AnalysisCommand::Describe,
+                "element-at" => AnalysisCommand::ElementAt(try!(parse_position_arg(positional_args.next()))),
+                "definition" => AnalysisCommand::Definition(try!(parse_position_arg(positional_args.next()))),
+                "references" => AnalysisCommand::References(try!(parse_position_arg(positional_args.next()))),
+                "rename" => {
+                    let position = try!(parse_position_arg(positional_args.next()));
+                    let new_name = try!(positional_args.next().ok_or("Missing new name argument."));
+                    AnalysisCommand::Rename(position, new_name)
+                }
+                "hover" => AnalysisCommand::Hover(try!(parse_position_arg(positional_args.next()))),
+                "signature-help" => AnalysisCommand::SignatureHelp(try!(parse_position_arg(positional_args.next()))),
+                "completion" => AnalysisCommand::Completion(try!(parse_position_arg(positional_args.next()))),
+                "folding-ranges" => AnalysisCommand::FoldingRanges,
+                "lexical-tokens" => AnalysisCommand::LexicalTokens,
+                "semantic-tokens" => AnalysisCommand::SemanticTokens,
+                "semantic-tokens-delta" => {
+                    let path = try!(positional_args.next().ok_or("Missing previous semantic tokens file argument."));
+                    AnalysisCommand::SemanticTokensDelta(path)
+                }
+                "syntax-tree" => AnalysisCommand::SyntaxTree,
+                "check-doc-code" => {
+                    let kind = match positional_args.next() {
+                        None => DocSourceKind::Rust,
+                        Some(kind) => try!(DocSourceKind::from_string(&kind).ok_or_else(|| 
+                            format!("Invalid document kind `{}`, expected one of: rust, markdown.", kind)
+                        )),
+                    };
+                    AnalysisCommand::CheckDocCode(kind)
+                }
+                "module-tree" => {
+                    let path = try!(positional_args.next().ok_or("Missing crate root or Cargo.toml argument."));
+                    AnalysisCommand::ModuleTree(path)
+                }
+                "workspace-symbols" => {
+                    let dir = try!(positional_args.next().ok_or("Missing directory argument."));
+                    let query = try!(positional_args.next().ok_or("Missing query argument."));
+                    let query_kind = match positional_args.next() {
+                        None => SymbolQueryKind::Fuzzy,
+                        Some(kind) => try!(SymbolQueryKind::from_string(&kind).ok_or_else(|| 
+                            format!("Invalid query kind `{}`, expected one of: prefix, substring, fuzzy.", kind)
+                        )),
+                    };
+                    AnalysisCommand::WorkspaceSymbols(dir, query, query_kind)
+                }
+                "outline-diff" => {
+                    let path = try!(positional_args.next().ok_or("Missing old source file argument."));
+                    AnalysisCommand::OutlineDiff(path)
+                }
+                "public-api" => AnalysisCommand::PublicApi,
+                "public-api-diff" => {
+                    let path = try!(positional_args.next().ok_or("Missing old crate root file argument."));
+                    AnalysisCommand::PublicApiDiff(path)
+                }
+                _ => return Err(format!("Unknown command `{}`.", command).into()),
+            }
+        };
+        
+        if let Some(arg) = positional_args.next() {
+            return Err(format!("Unexpected argument `{}`.", arg).into());
+        }
+        
+        Ok(options)
+    }
+    
+}
+
+fn read_semantic_tokens_data(path : &str) -> GResult<Vec<u32>> {
+    use std::fs::File;
+    use std::io::Read;
+    use semantic_tokens::parse_semantic_tokens_data;
+    
+    let mut text = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut text));
+    Ok(try!(parse_semantic_tokens_data(&text)))
+}
+
+fn parse_position_arg(arg : Option<String>) -> GResult<SourcePosition> {
+    let arg = try!(arg.ok_or("Missing position argument."));
+    
+    SourcePosition::parse(&arg).ok_or_else(|| 
+        format!("Invalid position `{}`, expected `line:column` or `@byte_offset`.", arg).into()
+    )
+}
+
+/* ----------------- parsing ----------------- */
+
 use std::thread;
 use std::sync::{Arc, Mutex};
 
-pub fn parse_crate_with_messages(source: &str) -> (Vec<SourceMessage>, Vec<StructureElement>) {
+/// A parsed source, as given to a source analysis.
+pub struct ParsedSource {
+    /// The source, without any BOM
+    pub source : String,
+    pub codemap : Rc<CodeMap>,
+    pub line_index : Rc<LineIndex>,
+    /// The parsed crate, or None if the parser failed, or the source was parsed as a fragment
+    pub krate : Option<ast::Crate>,
+    /// The parsed fragment, if the source was parsed as something other than a crate or item list
+    pub fragment : Option<ParsedFragment>,
+}
+
+impl ParsedSource {
+    
+    /// Whether the source was parsed successfully.
+    pub fn is_parsed(&self) -> bool {
+        self.krate.is_some() || self.fragment.is_some()
+    }
+    
+    /// Visit the parsed crate or fragment, if any.
+    pub fn walk<V : visit::Visitor>(&self, visitor : &mut V) {
+        if let Some(ref krate) = self.krate {
+            visit::walk_crate(visitor, krate);
+        }
+        if let Some(ref fragment) = self.fragment {
+            fragment.walk(visitor);
+        }
+    }
     
+}
+
+/// Parse given source in a worker thread, and run given analysis on the parse result, in that same thread. 
+/// (The parser can panic, and its data structures can't be sent across threads.)
+/// The analysis result is None if the parser or the analysis panicked.
+pub fn analyse_source<RESULT, FN>(source: &str, analysis: FN) -> (Vec<SourceMessage>, Option<RESULT>)
+    where RESULT : Send + 'static, FN : FnOnce(&ParsedSource) -> RESULT + Send + 'static
+{
+    analyse_source_as(source, ParseTarget::Crate, analysis)
+}
+
+/// Like `analyse_source`, but parse the source as given target.
+pub fn analyse_source_as<RESULT, FN>(source: &str, target: ParseTarget, analysis: FN) 
+    -> (Vec<SourceMessage>, Option<RESULT>)
+    where RESULT : Send + 'static, FN : FnOnce(&ParsedSource) -> RESULT + Send + 'static
+{
     let messages = Arc::new(Mutex::new(vec![]));
-    let elements =
+    let result =
     {
         let source = String::from(source);
         let messages = messages.clone();
         
         let worker_thread = thread::Builder::new().name("parser_thread".to_string()).spawn(move || {
-            parse_crate_with_messages_do(&source, messages)
+            let parsed = parse_source_as(&source, target, messages);
+            analysis(&parsed)
         }).unwrap();
         
-        worker_thread.join().unwrap_or(vec![])
+        worker_thread.join().ok()
     };
     
     let messages : Mutex<Vec<SourceMessage>> = Arc::try_unwrap(messages).ok().unwrap(); 
     let messages : Vec<SourceMessage> = messages.into_inner().unwrap();
     
-    return (messages, elements);
+    return (messages, result);
 }
 
-pub fn parse_crate_with_messages_do(source: &str, messages: Arc<Mutex<Vec<SourceMessage>>>) 
-    -> Vec<StructureElement> 
+/// Like `analyse_source_as`, but if the source can't be parsed, the analysis is run on the source with the
+/// incomplete statement before given position blanked out, or else with the contents of the braced blocks enclosing
+/// the position blanked out, from the innermost one, until it can be parsed.
+/// Since the parser doesn't recover from errors, this allows analysing the rest of a source with incomplete code
+/// at the position, such as a call being typed.
+/// The messages are those of the original source. The analysis result is None if no source could be parsed.
+pub fn analyse_source_recovering<RESULT, FN>(source: &str, target: ParseTarget, pos: &LineColumnPosition, 
+    analysis: FN) -> (Vec<SourceMessage>, Option<RESULT>)
+    where RESULT : Send + 'static, FN : Fn(&ParsedSource) -> RESULT + Send + Sync + 'static
 {
-    use ::structure_visitor::StructureVisitor;
+    use source_lexer::{ lex_all_tokens, matching_brace_starts };
     
-    let mut elements = vec![];
+    let analysis = Arc::new(analysis);
+    let analyse_parsed = |source : &str| {
+        let analysis = analysis.clone();
+        analyse_source_as(source, target, move |parsed| {
+            if parsed.is_parsed() { Some((*analysis)(parsed)) } else { None }
+        })
+    };
+    
+    let (messages, result) = analyse_parsed(source);
+    if let Some(Some(result)) = result {
+        return (messages, Some(result));
+    }
     
+    let line_index = source_line_index(source);
+    let source = line_index.source();
+    let offset = match line_index.offset_at(pos) {
+        Some(offset) => offset,
+        None => return (messages, None),
+    };
+    let codemap = Rc::new(CodeMap::new());
+    let tokens = lex_all_tokens(codemap.clone(), source);
+    let mut blocks : Vec<(usize, usize)> = matching_brace_starts(&codemap, &tokens).into_iter()
+        .filter(|&(end, start)| start < offset && offset < end)
+        .map(|(end, start)| (start + 1, end - 1))
+        .collect();
+    // The enclosing blocks are nested, so the innermost one starts last
+    blocks.sort_by(|a, b| b.cmp(a));
+    let statement = incomplete_statement_start(&codemap, &tokens, offset).into_iter()
+        .filter(|&start| start < offset)
+        .map(|start| (start, offset));
+    
+    for (start, end) in statement.into_iter().chain(blocks) {
+        // Line breaks are kept, so that the positions of the rest of the source don't change
+        let blanked : String = source[start .. end].chars()
+            .map(|ch| if ch == '\n' || ch == '\r' { ch } else { ' ' }).collect();
+        let recovered = format!("{}{}{}", &source[.. start], blanked, &source[end ..]);
+        
+        if let (_, Some(Some(result))) = analyse_parsed(&recovered) {
+            return (messages, Some(result));
+        }
+    }
+    (messages, None)
+}
+
+/// The start offset of the statement that given offset is in: the end of the previous `;`, or of the opening brace
+/// of the enclosing block. The unclosed parentheses and brackets of an incomplete statement are skipped.
+fn incomplete_statement_start(codemap : &CodeMap, tokens : &[TokenAndSpan], offset : usize) -> Option<usize> {
+    use syntex_syntax::parse::token;
+    use source_lexer::file_offset;
+    
+    let mut depth = 0;
+    for token in tokens.iter().rev() {
+        let end = file_offset(codemap, token.sp.hi);
+        if end > offset {
+            continue;
+        }
+        match token.tok {
+            token::CloseDelim(_) => depth += 1,
+            token::OpenDelim(_) if depth > 0 => depth -= 1,
+            token::OpenDelim(token::Brace) | token::Semi if depth == 0 => return Some(end),
+            _ => { }
+        }
+    }
+    None
+}
+
+pub fn parse_source(source: &str, messages: Arc<Mutex<Vec<SourceMessage>>>) -> ParsedSource {
+    parse_source_as(source, ParseTarget::Crate, messages)
+}
+
+pub fn parse_source_as(source: &str, target: ParseTarget, messages: Arc<Mutex<Vec<SourceMessage>>>) 
+    -> ParsedSource 
+{
     let fileLoader = Box::new(DummyFileLoader::new());
     let codemap = Rc::new(CodeMap::with_file_loader(fileLoader));
+    let line_index = Rc::new(source_line_index(source));
     
-    let krate = parse_crate(source, codemap.clone(), messages.clone());
+    let (krate, fragment) = match parse_target_source(source, target, codemap.clone(), line_index.clone(), messages) {
+        Some(ParsedTarget::Crate(krate)) => (Some(krate), None),
+        Some(ParsedTarget::Fragment(fragment)) => (None, Some(fragment)),
+        None => (None, None),
+    };
+    
+    ParsedSource { 
+        source : String::from(line_index.source()), codemap : codemap, line_index : line_index, 
+        krate : krate, fragment : fragment,
+    }
+}
+
+pub fn parse_crate_with_messages(source: &str) -> (Vec<SourceMessage>, Vec<StructureElement>) {
+    let (messages, elements) = analyse_source(source, |parsed| structure_elements(parsed));
+    
+    return (messages, elements.unwrap_or(vec![]));
+}
+
+pub fn parse_crate_with_messages_do(source: &str, messages: Arc<Mutex<Vec<SourceMessage>>>) 
+    -> Vec<StructureElement> 
+{
+    structure_elements(&parse_source(source, messages))
+}
+
+pub fn structure_elements(parsed : &ParsedSource) -> Vec<StructureElement> {
+    use ::structure_visitor::StructureVisitor;
+    use source_lexer::{ lex_all_tokens, matching_brace_starts };
+    
+    let mut elements = vec![];
     
-    if let Some(krate) = krate {
-        let mut visitor : StructureVisitor = StructureVisitor::new(&codemap);  
-        visit::walk_crate(&mut visitor, &krate);
+    if parsed.is_parsed() {
+        let tokens = lex_all_tokens(parsed.codemap.clone(), &parsed.source);
+        
+        let mut visitor : StructureVisitor = StructureVisitor::new(&parsed.codemap, &parsed.line_index);  
+        visitor.brace_starts = matching_brace_starts(&parsed.codemap, &tokens);
+        parsed.walk(&mut visitor);
         
         elements = visitor.elements;
     }
     return elements;
 }
 
+/// Create the line index for given source, as it will be seen by the parser. 
+pub fn source_line_index(source : &str) -> LineIndex {
+    // CodeMap::new_filemap removes the BOM, so we do the same 
+    if source.starts_with("\u{feff}") {
+        LineIndex::new(&source["\u{feff}".len()..])
+    } else {
+        LineIndex::new(source)
+    }
+}
+
 /* -----------------  ----------------- */
 
 
@@ -137,22 +1109,24 @@ impl codemap::FileLoader for DummyFileLoader {
 
 struct MessagesHandler {
     codemap : Rc<CodeMap>,
+    line_index : Rc<LineIndex>,
     messages : Arc<Mutex<Vec<SourceMessage>>>,
 }
 
 
-fn parse_crate<'a>(source: &str, codemap: Rc<CodeMap>, messages: Arc<Mutex<Vec<SourceMessage>>>) -> Option<ast::Crate> 
+fn parse_target_source(source: &str, target: ParseTarget, codemap: Rc<CodeMap>, line_index: Rc<LineIndex>, 
+    messages: Arc<Mutex<Vec<SourceMessage>>>) -> Option<ParsedTarget> 
 {
-    let emitter = MessagesHandler::new(codemap.clone(), messages.clone());
+    let emitter = MessagesHandler::new(codemap.clone(), line_index, messages.clone());
     
     let handler = Handler::with_emitter(true, false, Box::new(emitter));
     let sess = ParseSess::with_span_handler(handler, codemap.clone());
     
-    let krate_result = parse_crate_do(source, &sess);
+    let parse_result = parse_target_do(source, target, &sess);
     
-    return match krate_result {
-        Ok(_krate) => { 
-            Some(_krate) 
+    return match parse_result {
+        Ok(parsed) => { 
+            Some(parsed) 
         }
         Err(mut db) => { 
             db.emit();
@@ -162,6 +1136,22 @@ fn parse_crate<'a>(source: &str, codemap: Rc<CodeMap>, messages: Arc<Mutex<Vec<S
 }
 
 pub fn parse_crate_do<'a>(source : &str, sess : &'a ParseSess) -> parse::PResult<'a, ast::Crate> 
+{
+    let mut parser = try!(new_source_parser(source, sess));
+    
+    return parser.parse_crate_mod();
+}
+
+/// Parse given source as given target: a crate, or a fragment such as an expression.
+pub fn parse_target_do<'a>(source : &str, target : ParseTarget, sess : &'a ParseSess) 
+    -> parse::PResult<'a, ParsedTarget> 
+{
+    let mut parser = try!(new_source_parser(source, sess));
+    
+    return parse_target(&mut parser, target);
+}
+
+fn new_source_parser<'a>(source : &str, sess : &'a ParseSess) -> parse::PResult<'a, parse::parser::Parser<'a>> 
 {
     let source = source.to_string();
     
@@ -181,9 +1171,7 @@ pub fn parse_crate_do<'a>(source : &str, sess : &'a ParseSess) -> parse::PResult
     };
     
     let trdr = parse::lexer::new_tt_reader(&sess.span_diagnostic, None, tts);
-    let mut parser = parse::parser::Parser::new(sess, Box::new(trdr));
-    
-    return parser.parse_crate_mod();
+    return Ok(parse::parser::Parser::new(sess, Box::new(trdr)));
 }
 
 
@@ -191,8 +1179,10 @@ pub fn parse_crate_do<'a>(source : &str, sess : &'a ParseSess) -> parse::PResult
 
 impl MessagesHandler {
     
-    fn new(codemap: Rc<CodeMap>, messages: Arc<Mutex<Vec<SourceMessage>>>) -> MessagesHandler {
-        MessagesHandler { codemap : codemap, messages : messages }
+    fn new(codemap: Rc<CodeMap>, line_index: Rc<LineIndex>, messages: Arc<Mutex<Vec<SourceMessage>>>) 
+        -> MessagesHandler 
+    {
+        MessagesHandler { codemap : codemap, line_index : line_index, messages : messages }
     }
     
     fn write_message_handled(&mut self, sourcerange : Option<SourceRange>, msg: &str, severity: Severity) {
@@ -220,7 +1210,7 @@ impl emitter::Emitter for MessagesHandler {
         }
         
         let sourceranges : Vec<_> = multispan.primary_spans().iter()
-            .map(|span| -> SourceRange { SourceRange::new(&self.codemap, *span)})
+            .map(|span| -> SourceRange { SourceRange::from_span(&self.codemap, &self.line_index, *span)})
             .collect();
         
         for sourcerange in sourceranges {
@@ -246,27 +1236,151 @@ impl MessagesHandler {
 
 /* ----------------- describe writting ----------------- */
 
-pub fn write_parse_analysis_do(messages: Vec<SourceMessage>, elements: Vec<StructureElement>, 
-    out : Rc<RefCell<fmt::Write>>) -> Void {
+pub fn write_parse_analysis_do(line_endings: LineEndingStyle, messages: Vec<SourceMessage>, 
+    elements: Vec<StructureElement>, out : Rc<RefCell<fmt::Write>>) -> Void {
     
     let mut tokenWriter = TokenWriter { out : out };
     
-    try!(tokenWriter.write_raw("RUST_PARSE_DESCRIBE 1.0 {\n"));
+    try!(write_analysis_header(line_endings, &mut tokenWriter));
     try!(write_parse_analysis_contents(messages, elements, &mut tokenWriter));
     try!(tokenWriter.write_raw("\n}"));
     
     Ok(())
 }
 
-pub fn write_parse_analysis_contents(messages: Vec<SourceMessage>, elements: Vec<StructureElement>, 
-    tokenWriter : &mut TokenWriter) -> Void {
+/// Write the start of the output document, up to the contents.
+pub fn write_analysis_header(line_endings: LineEndingStyle, tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("RUST_PARSE_DESCRIBE 1.0 {\n"));
+    try!(tokenWriter.write_raw("LINE_ENDINGS "));
+    try!(tokenWriter.write_raw_token(line_endings.to_string()));
+    try!(tokenWriter.write_raw("\n"));
     
+    Ok(())
+}
+
+pub fn write_messages(messages: Vec<SourceMessage>, tokenWriter : &mut TokenWriter) -> Void {
     try!(tokenWriter.write_raw("MESSAGES {\n"));
     for msg in messages {
         try!(output_message(tokenWriter, msg.sourcerange, &msg.message, &msg.severity));
     }
     try!(tokenWriter.write_raw("}\n"));
     
+    Ok(())
+}
+
+/// Write given elements (without their children), from the outermost one to the innermost one.
+pub fn write_element_path(path: &[StructureElement], with_ids : bool, tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("ELEMENT_PATH {\n"));
+    for element in path {
+        try!(write_structure_element_do(tokenWriter, element, 0, false, with_ids));
+        try!(tokenWriter.write_raw("\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_definition(definition: &Option<Definition>, tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("DEFINITION {\n"));
+    if let Some(ref definition) = *definition {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(definition.kind.to_string()));
+        try!(tokenWriter.write_string_token(&definition.name));
+        try!(output_SourceRange(&definition.name_range, tokenWriter));
+        try!(tokenWriter.write_raw(" "));
+        try!(output_SourceRange(&definition.decl_range, tokenWriter));
+        try!(tokenWriter.write_raw(" }\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_references(references: &[NameReference], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("REFERENCES {\n"));
+    for reference in references {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(reference.access.to_string()));
+        try!(output_SourceRange(&reference.sourcerange, tokenWriter));
+        try!(tokenWriter.write_raw(" }\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_rename(edits: &[TextEdit], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("RENAME {\n"));
+    for edit in edits {
+        try!(tokenWriter.write_raw("{ "));
+        try!(output_SourceRange(&edit.sourcerange, tokenWriter));
+        try!(tokenWriter.write_raw(" "));
+        try!(tokenWriter.write_string_token(&edit.new_text));
+        try!(tokenWriter.write_raw("}\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_hover(hover: &Option<Hover>, tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("HOVER {\n"));
+    if let Some(ref hover) = *hover {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(hover.kind.to_string()));
+        try!(tokenWriter.write_string_token(&hover.name));
+        try!(output_SourceRange(&hover.sourcerange, tokenWriter));
+        try!(tokenWriter.write_raw(" "));
+        try!(tokenWriter.write_string_token(&hover.signature));
+        try!(tokenWriter.write_string_token(&hover.type_desc));
+        try!(tokenWriter.write_string_token(&hover.docs));
+        try!(tokenWriter.write_raw("}\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_signature_help(help: &Option<SignatureHelp>, tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("SIGNATURE_HELP {\n"));
+    if let Some(ref help) = *help {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_string_token(&help.name));
+        try!(tokenWriter.write_string_token(&help.signature));
+        try!(tokenWriter.get_output().write_fmt(format_args!("{} ", help.active_param)));
+        try!(tokenWriter.write_raw("{ "));
+        for param in &help.params {
+            try!(tokenWriter.write_string_token(param));
+        }
+        try!(tokenWriter.write_raw("} "));
+        try!(tokenWriter.write_string_token(&help.docs));
+        try!(tokenWriter.write_raw("}\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_completion(completions: &Completions, tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("COMPLETION {\n"));
+    try!(output_SourceRange(&completions.sourcerange, tokenWriter));
+    try!(tokenWriter.write_raw("\n"));
+    for item in &completions.items {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(item.kind.to_string()));
+        try!(tokenWriter.write_string_token(&item.label));
+        try!(tokenWriter.write_raw("}\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_parse_analysis_contents(messages: Vec<SourceMessage>, elements: Vec<StructureElement>, 
+    tokenWriter : &mut TokenWriter) -> Void {
+    
+    try!(write_messages(messages, tokenWriter));
+    
     
     for element in elements {
         try!(write_structure_element(tokenWriter, &element, 0));
@@ -333,6 +1447,12 @@ pub fn write_indent(tokenWriter : &mut TokenWriter, level : u32) -> Void {
 }
 
 pub fn write_structure_element(tw : &mut TokenWriter, element: &StructureElement, level: u32) -> Void
+{
+    write_structure_element_do(tw, element, level, true, false)
+}
+
+fn write_structure_element_do(tw : &mut TokenWriter, element: &StructureElement, level: u32, 
+    with_children : bool, with_ids : bool) -> Void
 {
     try!(tw.write_raw_token(element.kind.to_string()));
     
@@ -342,14 +1462,44 @@ pub fn write_structure_element(tw : &mut TokenWriter, element: &StructureElement
     
     try!(output_SourceRange(&element.sourcerange, tw));
     
-    try!(tw.get_output().write_str(" {}")); // name source range, Not Supported
+    try!(tw.get_output().write_str(" "));
+    try!(output_SourceRange(&element.selection_range, tw));
     
     try!(tw.get_output().write_str(" "));
     try!(tw.write_string_token(&element.type_desc)); 
     
-    try!(tw.get_output().write_str("{}")); // attribs, Not Supported
+    // The qualified path is only written for elements where it's not just the name
+    let has_qualified_path = element.qualified_path != element.name;
     
-    if element.children.is_empty() {
+    if element.body_range.is_none() && !has_qualified_path && element.source_file.is_none() && !with_ids {
+        try!(tw.get_output().write_str("{}"));
+    } else {
+        try!(tw.write_raw("{ "));
+        if let Some(ref body_range) = element.body_range {
+            try!(tw.write_raw("HEADER_RANGE "));
+            try!(output_SourceRange(&element.header_range, tw));
+            try!(tw.write_raw(" BODY_RANGE "));
+            try!(output_SourceRange(body_range, tw));
+            try!(tw.write_raw(" "));
+        }
+        if has_qualified_path {
+            try!(tw.write_raw("QUALIFIED_PATH "));
+            try!(tw.write_string_token(&element.qualified_path));
+        }
+        if let Some(ref source_file) = element.source_file {
+            try!(tw.write_raw("SOURCE_FILE "));
+            try!(tw.write_string_token(source_file));
+        }
+        if with_ids {
+            try!(tw.write_raw("ID "));
+            try!(tw.write_string_token(&element.id));
+            try!(tw.write_raw("CONTENT_HASH "));
+            try!(tw.write_string_token(&content_hash_string(element.content_hash)));
+        }
+        try!(tw.write_raw("}"));
+    }
+    
+    if element.children.is_empty() || !with_children {
         try!(tw.get_output().write_str(" "));
     } else {
         let level = level + 1;
@@ -357,7 +1507,7 @@ pub fn write_structure_element(tw : &mut TokenWriter, element: &StructureElement
         for child in &element.children {
             try!(tw.get_output().write_str("\n"));
             try!(write_indent(tw, level));
-            try!(write_structure_element(tw, child, level));
+            try!(write_structure_element_do(tw, child, level, true, with_ids));
         }
         
         try!(tw.get_output().write_str("\n"));
@@ -370,6 +1520,513 @@ pub fn write_structure_element(tw : &mut TokenWriter, element: &StructureElement
 }
 
 
+pub fn write_folding_ranges(folds: &[FoldingRange], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("FOLDING_RANGES {\n"));
+    for fold in folds {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(fold.kind.to_string()));
+        try!(output_SourceRange(&fold.sourcerange, tokenWriter));
+        try!(tokenWriter.write_raw(" }\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_lexical_tokens(tokens: &[LexicalToken], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("LEXICAL_TOKENS {\n"));
+    for token in tokens {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(token.kind.to_string()));
+        try!(output_SourceRange(&token.sourcerange, tokenWriter));
+        try!(tokenWriter.write_raw(" }\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_semantic_tokens(tokens: &[SemanticToken], tokenWriter : &mut TokenWriter) -> Void {
+    use semantic_tokens::{ SEMANTIC_TOKEN_KINDS, SEMANTIC_TOKEN_MODIFIERS, encode_semantic_tokens };
+    
+    try!(tokenWriter.write_raw("SEMANTIC_TOKENS {\nLEGEND { { "));
+    for kind in SEMANTIC_TOKEN_KINDS.iter() {
+        try!(tokenWriter.write_raw_token(kind.to_string()));
+    }
+    try!(tokenWriter.write_raw("} { "));
+    for modifier in SEMANTIC_TOKEN_MODIFIERS.iter() {
+        try!(tokenWriter.write_raw_token(modifier));
+    }
+    try!(tokenWriter.write_raw("} }\nDATA "));
+    try!(write_integers(&encode_semantic_tokens(tokens), tokenWriter));
+    try!(tokenWriter.write_raw("\n}"));
+    
+    Ok(())
+}
+
+pub fn write_semantic_tokens_delta(previous_data: &[u32], tokens: &[SemanticToken], tokenWriter : &mut TokenWriter) 
+    -> Void 
+{
+    use semantic_tokens::{ encode_semantic_tokens, semantic_tokens_edits };
+    
+    try!(tokenWriter.write_raw("SEMANTIC_TOKENS_DELTA {\n"));
+    for edit in semantic_tokens_edits(previous_data, &encode_semantic_tokens(tokens)) {
+        try!(tokenWriter.get_output().write_fmt(format_args!("{{ {} {} ", edit.start, edit.delete_count)));
+        try!(write_integers(&edit.data, tokenWriter));
+        try!(tokenWriter.write_raw(" }\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_syntax_tree(tree: &Option<SyntaxNode>, tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("SYNTAX_TREE {\n"));
+    if let Some(ref root) = *tree {
+        try!(write_syntax_node(tokenWriter, root, 0));
+        try!(tokenWriter.write_raw("\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_doc_code_blocks(blocks: &[DocCodeBlock], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("DOC_CODE_BLOCKS {\n"));
+    for block in blocks {
+        try!(tokenWriter.write_raw("{ "));
+        try!(output_SourceRange(&block.sourcerange, tokenWriter));
+        try!(tokenWriter.write_raw(" "));
+        try!(tokenWriter.write_string_token(&block.info));
+        try!(tokenWriter.write_raw_token(if block.checked { "Checked" } else { "Ignored" }));
+        try!(tokenWriter.write_raw("}\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_module_tree(modules: &[ModuleNode], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("MODULE_TREE {\n"));
+    for module in modules {
+        try!(write_module_node(tokenWriter, module, 0));
+        try!(tokenWriter.write_raw("\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_workspace_symbols(matches: &[SymbolMatch], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("WORKSPACE_SYMBOLS {\n"));
+    for symbol_match in matches {
+        let symbol = &symbol_match.symbol;
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(symbol.kind.to_string()));
+        try!(tokenWriter.write_string_token(&symbol.name));
+        try!(tokenWriter.write_string_token(&symbol.module_path));
+        try!(tokenWriter.write_string_token(&symbol_match.file));
+        try!(output_SourceRange(&symbol.sourcerange, tokenWriter));
+        try!(tokenWriter.write_raw(" "));
+        try!(output_SourceRange(&symbol.selection_range, tokenWriter));
+        try!(tokenWriter.write_raw(" }\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_outline_diff(changes: &[OutlineChange], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("OUTLINE_DIFF {\n"));
+    for change in changes {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(change.kind.to_string()));
+        try!(tokenWriter.write_raw_token(change.element_kind().to_string()));
+        try!(write_changed_element(&change.old, tokenWriter));
+        try!(tokenWriter.write_raw(" "));
+        try!(write_changed_element(&change.new, tokenWriter));
+        try!(tokenWriter.write_raw(" }\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+fn write_changed_element(element: &Option<StructureElement>, tokenWriter : &mut TokenWriter) -> Void {
+    match *element {
+        Some(ref element) => {
+            try!(tokenWriter.write_raw("{ "));
+            try!(tokenWriter.write_string_token(&element.qualified_path));
+            try!(output_SourceRange(&element.sourcerange, tokenWriter));
+            try!(tokenWriter.write_raw(" "));
+            try!(tokenWriter.write_string_token(&element.type_desc));
+            try!(tokenWriter.write_raw("}"));
+        }
+        None => try!(tokenWriter.write_raw("{}")),
+    }
+    Ok(())
+}
+
+pub fn write_public_api(items: &[ApiItem], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("PUBLIC_API {\n"));
+    for item in items {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(item.kind.to_string()));
+        try!(tokenWriter.write_string_token(&item.path));
+        try!(tokenWriter.write_string_token(&item.signature));
+        try!(output_SourceRange(&item.sourcerange, tokenWriter));
+        if let Some(ref file) = item.file {
+            try!(tokenWriter.write_raw(" FILE "));
+            try!(tokenWriter.write_string_token(file));
+        } else {
+            try!(tokenWriter.write_raw(" "));
+        }
+        try!(tokenWriter.write_raw("}\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+pub fn write_public_api_diff(changes: &[ApiChange], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("PUBLIC_API_DIFF {\n"));
+    try!(tokenWriter.write_raw_token(semver_level(changes).to_string()));
+    try!(tokenWriter.write_raw("\n"));
+    for change in changes {
+        try!(tokenWriter.write_raw("{ "));
+        try!(tokenWriter.write_raw_token(change.kind.to_string()));
+        try!(tokenWriter.write_raw_token(change.level.to_string()));
+        try!(tokenWriter.write_raw_token(change.item().kind.to_string()));
+        try!(tokenWriter.write_string_token(&change.item().path));
+        for item in &[&change.old, &change.new] {
+            match **item {
+                Some(ref item) => try!(tokenWriter.write_string_token(&item.signature)),
+                None => try!(tokenWriter.write_raw("{} ")),
+            }
+        }
+        try!(tokenWriter.write_raw("}\n"));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+fn write_module_node(tw : &mut TokenWriter, module: &ModuleNode, level: u32) -> Void {
+    try!(tw.write_raw_token(module.kind.to_string()));
+    try!(tw.write_raw("{ "));
+    try!(tw.write_string_token(&module.name));
+    
+    try!(tw.write_raw("{ "));
+    if let Some(ref file) = module.file {
+        try!(tw.write_raw_token("FILE"));
+        try!(tw.write_string_token(file));
+    }
+    if let Some(ref vis) = module.vis {
+        try!(tw.write_raw_token("VIS"));
+        try!(tw.write_string_token(vis));
+    }
+    for cfg in &module.cfgs {
+        try!(tw.write_raw_token("CFG"));
+        try!(tw.write_string_token(cfg));
+    }
+    for missing_file in &module.missing_files {
+        try!(tw.write_raw_token("MISSING"));
+        try!(tw.write_string_token(missing_file));
+    }
+    try!(tw.write_raw("}"));
+    
+    if module.children.is_empty() {
+        try!(tw.write_raw(" "));
+    } else {
+        for child in &module.children {
+            try!(tw.write_raw("\n"));
+            try!(write_indent(tw, level + 1));
+            try!(write_module_node(tw, child, level + 1));
+        }
+        try!(tw.write_raw("\n"));
+        try!(write_indent(tw, level));
+    }
+    
+    try!(tw.write_raw("}"));
+    
+    Ok(())
+}
+
+fn write_syntax_node(tw : &mut TokenWriter, node: &SyntaxNode, level: u32) -> Void {
+    try!(tw.write_raw_token(&node.kind));
+    try!(tw.write_raw("{ "));
+    try!(output_SourceRange(&node.sourcerange, tw));
+    
+    try!(tw.write_raw(" { "));
+    for &(name, ref value) in &node.fields {
+        try!(tw.write_raw_token(name));
+        try!(tw.write_string_token(value));
+    }
+    try!(tw.write_raw("}"));
+    
+    if node.children.is_empty() {
+        try!(tw.write_raw(" "));
+    } else {
+        for child in &node.children {
+            try!(tw.write_raw("\n"));
+            try!(write_indent(tw, level + 1));
+            try!(write_syntax_node(tw, child, level + 1));
+        }
+        try!(tw.write_raw("\n"));
+        try!(write_indent(tw, level));
+    }
+    
+    try!(tw.write_raw("}"));
+    
+    Ok(())
+}
+
+fn write_integers(integers: &[u32], tokenWriter : &mut TokenWriter) -> Void {
+    try!(tokenWriter.write_raw("{ "));
+    for integer in integers {
+        try!(tokenWriter.get_output().write_fmt(format_args!("{} ", integer)));
+    }
+    try!(tokenWriter.write_raw("}"));
+    
+    Ok(())
+}
+
+/* ----------------- JSON output ----------------- */
+
+pub fn position_to_json(pos : &LineColumnPosition) -> JsonValue {
+    JsonValue::object(vec![
+        ("line", JsonValue::int(pos.line - 1)),
+        ("col", JsonValue::int(pos.col.0)),
+    ])
+}
+
+pub fn source_range_to_json(sr : &SourceRange) -> JsonValue {
+    JsonValue::object(vec![
+        ("start", position_to_json(&sr.start_pos)),
+        ("end", position_to_json(&sr.end_pos)),
+    ])
+}
+
+pub fn opt_source_range_to_json(sr : &Option<SourceRange>) -> JsonValue {
+    match *sr {
+        None => JsonValue::Null,
+        Some(ref sr) => source_range_to_json(sr),
+    }
+}
+
+pub fn message_to_json(msg : &SourceMessage) -> JsonValue {
+    JsonValue::object(vec![
+        ("severity", JsonValue::string(msg.severity.to_string())),
+        ("range", opt_source_range_to_json(&msg.sourcerange)),
+        ("message", JsonValue::string(&msg.message)),
+    ])
+}
+
+/// The content hash of an element, as written in the output: 16 hexadecimal digits.
+pub fn content_hash_string(content_hash : u64) -> String {
+    format!("{:016x}", content_hash)
+}
+
+pub fn element_to_json(element : &StructureElement) -> JsonValue {
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(element.kind.to_string())),
+        ("name", JsonValue::string(&element.name)),
+        ("range", source_range_to_json(&element.sourcerange)),
+        ("selection_range", source_range_to_json(&element.selection_range)),
+        ("header_range", source_range_to_json(&element.header_range)),
+        ("body_range", opt_source_range_to_json(&element.body_range)),
+        ("type_desc", JsonValue::string(&element.type_desc)),
+        ("qualified_path", JsonValue::string(&element.qualified_path)),
+        ("id", JsonValue::string(&element.id)),
+        ("content_hash", JsonValue::string(&content_hash_string(element.content_hash))),
+        ("source_file", element.source_file.as_ref().map_or(JsonValue::Null, |file| JsonValue::string(file))),
+        ("children", JsonValue::Array(element.children.iter().map(element_to_json).collect())),
+    ])
+}
+
+pub fn definition_to_json(definition : &Definition) -> JsonValue {
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(definition.kind.to_string())),
+        ("name", JsonValue::string(&definition.name)),
+        ("range", source_range_to_json(&definition.name_range)),
+        ("decl_range", source_range_to_json(&definition.decl_range)),
+    ])
+}
+
+pub fn reference_to_json(reference : &NameReference) -> JsonValue {
+    JsonValue::object(vec![
+        ("access", JsonValue::string(reference.access.to_string())),
+        ("range", source_range_to_json(&reference.sourcerange)),
+    ])
+}
+
+pub fn text_edit_to_json(edit : &TextEdit) -> JsonValue {
+    JsonValue::object(vec![
+        ("range", source_range_to_json(&edit.sourcerange)),
+        ("new_text", JsonValue::string(&edit.new_text)),
+    ])
+}
+
+pub fn hover_to_json(hover : &Hover) -> JsonValue {
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(hover.kind.to_string())),
+        ("name", JsonValue::string(&hover.name)),
+        ("range", source_range_to_json(&hover.sourcerange)),
+        ("signature", JsonValue::string(&hover.signature)),
+        ("type_desc", JsonValue::string(&hover.type_desc)),
+        ("docs", JsonValue::string(&hover.docs)),
+    ])
+}
+
+pub fn signature_help_to_json(help : &SignatureHelp) -> JsonValue {
+    JsonValue::object(vec![
+        ("name", JsonValue::string(&help.name)),
+        ("signature", JsonValue::string(&help.signature)),
+        ("params", JsonValue::Array(help.params.iter().map(|param| JsonValue::string(param)).collect())),
+        ("active_param", JsonValue::int(help.active_param)),
+        ("docs", JsonValue::string(&help.docs)),
+    ])
+}
+
+pub fn completions_to_json(completions : &Completions) -> JsonValue {
+    JsonValue::object(vec![
+        ("range", source_range_to_json(&completions.sourcerange)),
+        ("items", JsonValue::Array(completions.items.iter().map(completion_item_to_json).collect())),
+    ])
+}
+
+pub fn completion_item_to_json(item : &CompletionItem) -> JsonValue {
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(item.kind.to_string())),
+        ("label", JsonValue::string(&item.label)),
+    ])
+}
+
+pub fn folding_range_to_json(fold : &FoldingRange) -> JsonValue {
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(fold.kind.to_string())),
+        ("range", source_range_to_json(&fold.sourcerange)),
+    ])
+}
+
+pub fn semantic_tokens_to_json(tokens : &[SemanticToken]) -> JsonValue {
+    use semantic_tokens::{ SEMANTIC_TOKEN_KINDS, SEMANTIC_TOKEN_MODIFIERS, encode_semantic_tokens };
+    
+    let token_types = SEMANTIC_TOKEN_KINDS.iter().map(|kind| JsonValue::string(kind.to_string())).collect();
+    let token_modifiers = SEMANTIC_TOKEN_MODIFIERS.iter().map(|modifier| JsonValue::string(modifier)).collect();
+    
+    JsonValue::object(vec![
+        ("legend", JsonValue::object(vec![
+            ("tokenTypes", JsonValue::Array(token_types)),
+            ("tokenModifiers", JsonValue::Array(token_modifiers)),
+        ])),
+        ("data", integers_to_json(&encode_semantic_tokens(tokens))),
+    ])
+}
+
+pub fn semantic_tokens_delta_to_json(previous_data : &[u32], tokens : &[SemanticToken]) -> JsonValue {
+    use semantic_tokens::{ encode_semantic_tokens, semantic_tokens_edits };
+    
+    let edits = semantic_tokens_edits(previous_data, &encode_semantic_tokens(tokens)).iter()
+        .map(|edit| JsonValue::object(vec![
+            ("start", JsonValue::int(edit.start)),
+            ("deleteCount", JsonValue::int(edit.delete_count)),
+            ("data", integers_to_json(&edit.data)),
+        ]))
+        .collect();
+    JsonValue::object(vec![("edits", JsonValue::Array(edits))])
+}
+
+fn integers_to_json(integers : &[u32]) -> JsonValue {
+    JsonValue::Array(integers.iter().map(|&integer| JsonValue::int(integer as usize)).collect())
+}
+
+pub fn lexical_token_to_json(token : &LexicalToken) -> JsonValue {
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(token.kind.to_string())),
+        ("range", source_range_to_json(&token.sourcerange)),
+    ])
+}
+
+pub fn doc_code_block_to_json(block : &DocCodeBlock) -> JsonValue {
+    JsonValue::object(vec![
+        ("range", source_range_to_json(&block.sourcerange)),
+        ("info", JsonValue::string(&block.info)),
+        ("checked", JsonValue::Bool(block.checked)),
+    ])
+}
+
+pub fn module_node_to_json(module : &ModuleNode) -> JsonValue {
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(module.kind.to_string())),
+        ("name", JsonValue::string(&module.name)),
+        ("file", module.file.as_ref().map_or(JsonValue::Null, |file| JsonValue::string(file))),
+        ("vis", module.vis.as_ref().map_or(JsonValue::Null, |vis| JsonValue::string(vis))),
+        ("cfgs", JsonValue::Array(module.cfgs.iter().map(|cfg| JsonValue::string(cfg)).collect())),
+        ("missing_files", JsonValue::Array(module.missing_files.iter().map(|path| JsonValue::string(path)).collect())),
+        ("children", JsonValue::Array(module.children.iter().map(module_node_to_json).collect())),
+    ])
+}
+
+pub fn symbol_match_to_json(symbol_match : &SymbolMatch) -> JsonValue {
+    let symbol = &symbol_match.symbol;
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(symbol.kind.to_string())),
+        ("name", JsonValue::string(&symbol.name)),
+        ("module_path", JsonValue::string(&symbol.module_path)),
+        ("file", JsonValue::string(&symbol_match.file)),
+        ("range", source_range_to_json(&symbol.sourcerange)),
+        ("selection_range", source_range_to_json(&symbol.selection_range)),
+    ])
+}
+
+pub fn outline_change_to_json(change : &OutlineChange) -> JsonValue {
+    let changed_element_to_json = |element : &Option<StructureElement>| {
+        element.as_ref().map_or(JsonValue::Null, element_to_json)
+    };
+    JsonValue::object(vec![
+        ("change", JsonValue::string(change.kind.to_string())),
+        ("kind", JsonValue::string(change.element_kind().to_string())),
+        ("old", changed_element_to_json(&change.old)),
+        ("new", changed_element_to_json(&change.new)),
+    ])
+}
+
+pub fn api_item_to_json(item : &ApiItem) -> JsonValue {
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(item.kind.to_string())),
+        ("path", JsonValue::string(&item.path)),
+        ("signature", JsonValue::string(&item.signature)),
+        ("range", source_range_to_json(&item.sourcerange)),
+        ("file", item.file.as_ref().map_or(JsonValue::Null, |file| JsonValue::string(file))),
+    ])
+}
+
+pub fn api_change_to_json(change : &ApiChange) -> JsonValue {
+    let signature_to_json = |item : &Option<ApiItem>| {
+        item.as_ref().map_or(JsonValue::Null, |item| JsonValue::string(&item.signature))
+    };
+    JsonValue::object(vec![
+        ("change", JsonValue::string(change.kind.to_string())),
+        ("level", JsonValue::string(change.level.to_string())),
+        ("kind", JsonValue::string(change.item().kind.to_string())),
+        ("path", JsonValue::string(&change.item().path)),
+        ("old_signature", signature_to_json(&change.old)),
+        ("new_signature", signature_to_json(&change.new)),
+    ])
+}
+
+pub fn syntax_node_to_json(node : &SyntaxNode) -> JsonValue {
+    let fields = node.fields.iter().map(|&(name, ref value)| (name, JsonValue::string(value))).collect();
+    JsonValue::object(vec![
+        ("kind", JsonValue::string(&node.kind)),
+        ("range", source_range_to_json(&node.sourcerange)),
+        ("fields", JsonValue::object(fields)),
+        ("children", JsonValue::Array(node.children.iter().map(syntax_node_to_json).collect())),
+    ])
+}
+
+
 #[cfg(test)]
 mod parse_describe_tests {
 
@@ -388,8 +2045,9 @@ mod parse_describe_tests {
             let stringRc = Rc::new(RefCell::new(String::new()));
             {
                 let name = String::from(name);
-                let element = StructureElement { name: name, kind: kind, sourcerange: sr, type_desc: type_desc,
-                     children: vec![]}; 
+                let element = StructureElement { name: name.clone(), kind: kind, sourcerange: sr, selection_range: sr,
+                     header_range: sr, body_range: None, type_desc: type_desc, qualified_path: name.clone(),
+                     id: format!("{} {}", kind.to_string(), name), content_hash: 0, source_file: None, children: vec![]}; 
                 let mut tw = TokenWriter { out : stringRc.clone() };
                 
                 write_structure_element(&mut tw, &element, 0).ok();
@@ -401,7 +2059,7 @@ mod parse_describe_tests {
     #[test]
     fn write_structure_element__tests() {
         test_write_structure_element("blah", StructureElementKind::Var, source_range(1, 0, 2, 5), "desc".to_string(),
-            r#"Var { "blah" { 0:0 1:5 } {} "desc" {} }"#);
+            r#"Var { "blah" { 0:0 1:5 } { 0:0 1:5 } "desc" {} }"#);
     }
     
     
@@ -423,20 +2081,558 @@ mod parse_describe_tests {
             r#"{ ERROR { 0:10 0:11 } "character literal may only contain one codepoint: '" }"#
         );
         
+        // Test source with invalid UTF-8 and a BOM
+        test_parse_analysis_bytes(b"\xEF\xBB\xBFconst a : char = '\xFF';", 
+            &(r#"{ WARNING { 0:18 0:19 } "invalid UTF-8 byte at offset 21, replaced with U+FFFD" }"#.to_string() +
+                "\n}\n" + r#"Var { "a" { 0:0 0:21 } { 0:6 0:7 } "char" {}"#)
+        );
+        
+        // Test CRLF, lone CR and mixed line endings: lines and columns never count the terminators
+        test_parse_analysis_line_endings("fn foo(\r\n  blah", "CRLF", r#"
+{ ERROR { 1:6 1:6 } "this file contains an un-closed delimiter" }
+{ ERROR { 1:6 1:6 } "expected one of `:` or `@`, found `)`" }
+{ ERROR { 1:6 1:6 } "expected one of `->`, `where`, or `{`, found `<eof>`" }
+"#
+        );
+        test_parse_analysis_line_endings("\r\n\r\nconst a = '", "CRLF", 
+            r#"{ ERROR { 2:10 2:11 } "character literal may only contain one codepoint: '" }"#);
+        test_parse_analysis_line_endings("\r\rconst a = '", "CR", 
+            r#"{ ERROR { 2:10 2:11 } "character literal may only contain one codepoint: '" }"#);
+        test_parse_analysis_line_endings("\n\r\n\rconst a = '", "MIXED", 
+            r#"{ ERROR { 3:10 3:11 } "character literal may only contain one codepoint: '" }"#);
+        
+        // Test column encodings
+        let source = "const A : &str = \"\u{E9}\u{1F600}\"; fn foo() {}";
+        test_parse_analysis_columns(source, ColumnEncoding::Chars, 
+            &("}\n".to_string() + r#"Var { "A" { 0:0 0:22 } { 0:6 0:7 } "&str" {} }Function { "foo" { 0:23 0:34 } { 0:26 0:29 } "()" "# +
+                r#"{ HEADER_RANGE { 0:23 0:31 } BODY_RANGE { 0:32 0:34 } }"#));
+        test_parse_analysis_columns(source, ColumnEncoding::Utf16, 
+            &("}\n".to_string() + r#"Var { "A" { 0:0 0:23 } { 0:6 0:7 } "&str" {} }Function { "foo" { 0:24 0:35 } { 0:27 0:30 } "()" "# +
+                r#"{ HEADER_RANGE { 0:24 0:32 } BODY_RANGE { 0:33 0:35 } }"#));
+        test_parse_analysis_columns(source, ColumnEncoding::Bytes, 
+            &("}\n".to_string() + r#"Var { "A" { 0:0 0:26 } { 0:6 0:7 } "&str" {} }Function { "foo" { 0:27 0:38 } { 0:30 0:33 } "()" "# +
+                r#"{ HEADER_RANGE { 0:27 0:35 } BODY_RANGE { 0:36 0:38 } }"#));
+        
         // test `?` syntax shorthand for try:
         test_parse_analysis("fn foo() { 123? }", 
-            &("}\n".to_string() + r#"Function { "foo" { 0:0 0:17 } {} "()" {}"#)
+            &("}\n".to_string() + r#"Function { "foo" { 0:0 0:17 } { 0:3 0:6 } "()" "# + 
+                r#"{ HEADER_RANGE { 0:0 0:8 } BODY_RANGE { 0:9 0:17 } }"#)
         );
     }
     
     fn test_parse_analysis(source : &str, expected_msgs : &str) {
         let result = parse_analysis(source, String::new()).ok().unwrap();
-        let mut result : &str = &result;
+        check_parse_analysis_result(&result, None, expected_msgs);
+    }
+    
+    fn test_parse_analysis_line_endings(source : &str, line_endings : &str, expected_msgs : &str) {
+        let result = parse_analysis(source, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some(line_endings), expected_msgs);
+    }
+    
+    fn test_parse_analysis_columns(source : &str, column_encoding : ColumnEncoding, expected_msgs : &str) {
+        let options = ParseAnalysisOptions { column_encoding : column_encoding, .. ParseAnalysisOptions::default() };
+        let result = parse_analysis_with_options(source, &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, None, expected_msgs);
+    }
+    
+    #[test]
+    fn element_at__tests() {
+        let source = b"mod bar {\n  fn foo() { }\n}";
+        
+        fn test_element_at(source : &[u8], args : &[&str], expected : &str) {
+            let args = args.iter().map(|arg| arg.to_string());
+            let options = ParseAnalysisOptions::from_args(args).unwrap();
+            let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+            
+            check_parse_analysis_result(&result, Some("LF"), expected);
+        }
+        
+        test_element_at(source, &["element-at", "1:5"], r#"}
+ELEMENT_PATH {
+Mod { "bar" { 0:0 2:1 } { 0:4 0:7 } "" { HEADER_RANGE { 0:0 0:7 } BODY_RANGE { 0:8 2:1 } } }
+Function { "foo" { 1:2 1:14 } { 1:5 1:8 } "()" { HEADER_RANGE { 1:2 1:10 } BODY_RANGE { 1:11 1:14 } QUALIFIED_PATH "bar::foo" } }
+"#);
+        test_element_at(source, &["element-at", "@4"], r#"}
+ELEMENT_PATH {
+Mod { "bar" { 0:0 2:1 } { 0:4 0:7 } "" { HEADER_RANGE { 0:0 0:7 } BODY_RANGE { 0:8 2:1 } } }
+"#);
+        test_element_at(source, &["element-at", "1:5", "--element-ids"], &format!(r#"}}
+ELEMENT_PATH {{
+Mod {{ "bar" {{ 0:0 2:1 }} {{ 0:4 0:7 }} "" {{ HEADER_RANGE {{ 0:0 0:7 }} BODY_RANGE {{ 0:8 2:1 }} ID "Mod bar" CONTENT_HASH "{}" }} }}
+Function {{ "foo" {{ 1:2 1:14 }} {{ 1:5 1:8 }} "()" {{ HEADER_RANGE {{ 1:2 1:10 }} BODY_RANGE {{ 1:11 1:14 }} QUALIFIED_PATH "bar::foo" ID "Function bar::foo" CONTENT_HASH "{}" }} }}
+"#, "b4856703688759bf", "ce65c74ef29e687c"));
+        test_element_at(source, &["element-at", "5:0"], r#"}
+ELEMENT_PATH {
+"#);
+        
+        ParseAnalysisOptions::from_args(vec!["element-at".to_string()].into_iter()).unwrap_err();
+        ParseAnalysisOptions::from_args(vec!["element-at".to_string(), "x".to_string()].into_iter()).unwrap_err();
+        ParseAnalysisOptions::from_args(vec!["blah".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn definition__tests() {
+        let source = b"fn foo(x : u32) {\n  x + bar()\n}\nfn bar() -> u32 { 1 }";
+        
+        fn test_definition(source : &[u8], args : &[&str], expected : &str) {
+            let args = args.iter().map(|arg| arg.to_string());
+            let options = ParseAnalysisOptions::from_args(args).unwrap();
+            let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+            
+            check_parse_analysis_result(&result, Some("LF"), expected);
+        }
+        
+        test_definition(source, &["definition", "1:2"], r#"}
+DEFINITION {
+{ Param "x" { 0:7 0:8 } { 0:7 0:14 } }
+"#);
+        test_definition(source, &["definition", "1:7"], r#"}
+DEFINITION {
+{ Function "bar" { 3:3 3:6 } { 3:0 3:21 } }
+"#);
+        test_definition(source, &["definition", "0:1"], r#"}
+DEFINITION {
+"#);
+        
+        ParseAnalysisOptions::from_args(vec!["definition".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn references__tests() {
+        let source = b"fn foo() {\n  let mut x = 1;\n  x = x + 1;\n}";
+        
+        let args = vec!["references".to_string(), "2:6".to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("LF"), r#"}
+REFERENCES {
+{ Declaration { 1:10 1:11 } }
+{ Write { 2:2 2:3 } }
+{ Read { 2:6 2:7 } }
+"#);
+        
+        ParseAnalysisOptions::from_args(vec!["references".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn rename__tests() {
+        let source = b"fn foo(x : u32) -> u32 {\n  let y = 1;\n  x + y\n}";
+        
+        fn test_rename(source : &[u8], args : &[&str], expected : &str) {
+            let args = args.iter().map(|arg| arg.to_string());
+            let options = ParseAnalysisOptions::from_args(args).unwrap();
+            let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+            
+            check_parse_analysis_result(&result, Some("LF"), expected);
+        }
+        
+        test_rename(source, &["rename", "2:2", "z"], r#"}
+RENAME {
+{ { 0:7 0:8 } "z" }
+{ { 2:2 2:3 } "z" }
+"#);
+        test_rename(source, &["rename", "2:2", "y"], r#"{ ERROR { 2:2 2:3 } "Renaming `x` to `y` would change the definition this name refers to." }
+}
+RENAME {
+"#);
+        
+        ParseAnalysisOptions::from_args(vec!["rename".to_string(), "2:2".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn hover__tests() {
+        let source = b"/// Increment.\nfn inc(x : u32) -> u32 {\n  let y = x;\n  y + 1\n}\nconst ONE : u32 = inc(0);";
+        
+        fn test_hover(source : &[u8], args : &[&str], expected : &str) {
+            let args = args.iter().map(|arg| arg.to_string());
+            let options = ParseAnalysisOptions::from_args(args).unwrap();
+            let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+            
+            check_parse_analysis_result(&result, Some("LF"), expected);
+        }
+        
+        test_hover(source, &["hover", "5:18"], r#"}
+HOVER {
+{ Function "inc" { 5:18 5:21 } "fn inc(x : u32) -> u32" "(u32) -> u32" "Increment." }
+"#);
+        test_hover(source, &["hover", "2:10"], r#"}
+HOVER {
+{ Param "x" { 2:10 2:11 } "x: u32" "u32" "" }
+"#);
+        test_hover(source, &["hover", "3:2"], r#"}
+HOVER {
+{ Local "y" { 3:2 3:3 } "y" "" "" }
+"#);
+        test_hover(source, &["hover", "4:0"], r#"}
+HOVER {
+"#);
+        
+        ParseAnalysisOptions::from_args(vec!["hover".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn signature_help__tests() {
+        let source = b"/// Adds.\nfn add(a : u32, b : u32) -> u32 { a + b }\nfn main() {\n  add(1, 2);\n}";
+        
+        fn test_signature_help(source : &[u8], args : &[&str], expected : &str) {
+            let args = args.iter().map(|arg| arg.to_string());
+            let options = ParseAnalysisOptions::from_args(args).unwrap();
+            let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+            
+            check_parse_analysis_result(&result, Some("LF"), expected);
+        }
+        
+        test_signature_help(source, &["signature-help", "3:9"], r#"}
+SIGNATURE_HELP {
+{ "add" "fn add(a : u32, b : u32) -> u32" 1 { "a : u32" "b : u32" } "Adds." }
+"#);
+        test_signature_help(source, &["signature-help", "3:6"], r#"}
+SIGNATURE_HELP {
+{ "add" "fn add(a : u32, b : u32) -> u32" 0 { "a : u32" "b : u32" } "Adds." }
+"#);
+        test_signature_help(source, &["signature-help", "1:20"], r#"}
+SIGNATURE_HELP {
+"#);
+        
+        ParseAnalysisOptions::from_args(vec!["signature-help".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn completion__tests() {
+        let source = b"struct Point;\nfn main(count : u32) {\n  let total = count;\n  to\n}";
+        
+        fn test_completion(source : &[u8], args : &[&str], expected : &str) {
+            let args = args.iter().map(|arg| arg.to_string());
+            let options = ParseAnalysisOptions::from_args(args).unwrap();
+            let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+            
+            check_parse_analysis_result(&result, Some("LF"), expected);
+        }
+        
+        test_completion(source, &["completion", "3:4"], r#"}
+COMPLETION {
+{ 3:2 3:4 }
+{ Local "total" }
+"#);
+        test_completion(source, &["completion", "3:3"], r#"}
+COMPLETION {
+{ 3:2 3:3 }
+{ Local "total" }
+{ Keyword "trait" }
+{ Keyword "type" }
+{ Keyword "true" }
+"#);
+        test_completion(source, &["completion", "1:17"], r#"}
+COMPLETION {
+{ 1:16 1:17 }
+{ Keyword "unsafe" }
+"#);
+        test_completion(source, &["completion", "0:13"], r#"}
+COMPLETION {
+{ 0:13 0:13 }
+{ Keyword "const" }
+{ Keyword "enum" }
+{ Keyword "extern" }
+{ Keyword "fn" }
+{ Keyword "impl" }
+{ Keyword "mod" }
+{ Keyword "pub" }
+{ Keyword "static" }
+{ Keyword "struct" }
+{ Keyword "trait" }
+{ Keyword "type" }
+{ Keyword "unsafe" }
+{ Keyword "use" }
+"#);
+        
+        ParseAnalysisOptions::from_args(vec!["completion".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn folding_ranges__tests() {
+        let source = b"fn foo() {\n}";
+        
+        let options = ParseAnalysisOptions::from_args(vec!["folding-ranges".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("LF"), r#"}
+FOLDING_RANGES {
+{ Code { 0:9 1:1 } }
+"#);
+        
+        let args = vec!["folding-ranges".to_string(), "--format=json".to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        let result = parse_analysis_bytes(source, &options, String::new()).ok().unwrap();
+        check_equal(&result, &(String::new() + 
+            r#"{"format":"RUST_PARSE_DESCRIBE","version":"1.0","line_endings":"LF","messages":[],"# +
+            r#""folding_ranges":[{"kind":"Code","range":{"start":{"line":0,"col":9},"end":{"line":1,"col":1}}}]}"#
+        ));
+    }
+    
+    #[test]
+    fn lexical_tokens__tests() {
+        let options = ParseAnalysisOptions::from_args(vec!["lexical-tokens".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"fn f() {}", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("NONE"), r#"}
+LEXICAL_TOKENS {
+{ Keyword { 0:0 0:2 } }
+{ Identifier { 0:3 0:4 } }
+{ Delimiter { 0:4 0:5 } }
+{ Delimiter { 0:5 0:6 } }
+{ Delimiter { 0:7 0:8 } }
+{ Delimiter { 0:8 0:9 } }
+"#);
+    }
+    
+    #[test]
+    fn semantic_tokens__tests() {
+        use test_util::TempDir;
+        
+        let options = ParseAnalysisOptions::from_args(vec!["semantic-tokens".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"fn f() {}", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("NONE"), r#"}
+SEMANTIC_TOKENS {
+LEGEND { { namespace type struct enum enumMember interface typeParameter property variable function method macro lifetime selfKeyword } { declaration readonly static } }
+DATA { 0 3 1 9 1 }
+"#);
+        
+        let dir = TempDir::new("rainicorn_semantic_tokens_tests");
+        dir.write_file("previous.txt", "[0, 3, 1, 9, 0]");
+        let previous_data_path = dir.join("previous.txt");
+        
+        let args = vec!["semantic-tokens-delta".to_string(), previous_data_path.to_str().unwrap().to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"fn f() {}", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("NONE"), r#"}
+SEMANTIC_TOKENS_DELTA {
+{ 0 5 { 0 3 1 9 1 } }
+"#);
+        
+        ParseAnalysisOptions::from_args(vec!["semantic-tokens-delta".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn syntax_tree__tests() {
+        let options = ParseAnalysisOptions::from_args(vec!["syntax-tree".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"fn f() {}", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("NONE"), r#"}
+SYNTAX_TREE {
+Crate { { 0:0 0:9 } { }
+  Item.Fn { { 0:0 0:9 } { name "f" }
+    Block { { 0:7 0:9 } { } }
+  }
+}
+"#);
+        
+        let options = ParseAnalysisOptions::from_args(
+            vec!["syntax-tree".to_string(), "--format=json".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_with_options("x", &options, String::new()).ok().unwrap();
+        assert!(result.ends_with(r#""syntax_tree":null}"#));
+    }
+    
+    #[test]
+    fn parse_target__tests() {
+        let options = ParseAnalysisOptions::from_args(vec!["--target=expr".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"1 + 2 3", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("NONE"), 
+            r#"{ ERROR { 0:6 0:7 } "expected end of expression, found `3`" }"#);
+        
+        let options = ParseAnalysisOptions::from_args(vec!["--target=statements".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"let x = 1;; fn g() {}", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("NONE"), r#"}
+Function { "g" { 0:12 0:21 } { 0:15 0:16 } "()" { HEADER_RANGE { 0:12 0:18 } BODY_RANGE { 0:19 0:21 } }"#);
+        
+        let options = ParseAnalysisOptions::from_args(vec!["--target=items".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"fn g() {} 1", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("NONE"), 
+            r#"{ ERROR { 0:10 0:11 } "expected item, found `1`" }"#);
+        
+        ParseAnalysisOptions::from_args(vec!["--target=module".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn check_doc_code__tests() {
+        let options = ParseAnalysisOptions::from_args(vec!["check-doc-code".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"/// ```\n/// a + ;\n/// ```\nfn f() {}", &options, String::new())
+            .ok().unwrap();
+        check_parse_analysis_result(&result, Some("LF"), r#"{ ERROR { 1:8 1:9 } "expected expression, found `;`" }
+}
+DOC_CODE_BLOCKS {
+{ { 0:4 2:7 } "" Checked }
+"#);
+        
+        let args = vec!["check-doc-code".to_string(), "markdown".to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"```rust,ignore\n```", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("LF"), r#"}
+DOC_CODE_BLOCKS {
+{ { 0:0 1:3 } "rust,ignore" Ignored }
+"#);
+        
+        let args = vec!["check-doc-code".to_string(), "html".to_string()];
+        ParseAnalysisOptions::from_args(args.into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn module_tree__tests() {
+        let args = vec!["module-tree".to_string(), "no_such_dir/Cargo.toml".to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        assert!(!options.command.reads_source());
+        let result = parse_analysis_bytes(b"", &options, String::new()).ok().unwrap();
+        assert!(result.contains("{ ERROR { } \"Could not read crate roots: "));
+        assert!(result.ends_with("MODULE_TREE {\n}\n}"));
+        
+        ParseAnalysisOptions::from_args(vec!["module-tree".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn workspace_symbols__tests() {
+        use test_util::TempDir;
+        
+        let dir = TempDir::new("rainicorn_workspace_symbols_tests");
+        dir.write_file("lib.rs", "fn foo_bar() {}\nfn other() {}");
+        let index_file = dir.join("symbols.index");
+        
+        let args = vec!["workspace-symbols".to_string(), dir.path.to_str().unwrap().to_string(), "fb".to_string(), 
+            format!("--symbol-index={}", index_file.display())];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        assert!(!options.command.reads_source());
+        for _ in 0..2 {
+            let result = parse_analysis_bytes(b"", &options, String::new()).ok().unwrap();
+            check_parse_analysis_result(&result, None, r#"}
+WORKSPACE_SYMBOLS {
+{ Function "foo_bar" "" "lib.rs" { 0:0 0:15 } { 0:3 0:10 } }
+"#);
+            assert!(index_file.is_file());
+        }
+        
+        // The columns of each file are converted with its own line index
+        dir.write_file("wide.rs", "/* \u{1F600} */ fn wide() {}");
+        let args = vec!["workspace-symbols".to_string(), dir.path.to_str().unwrap().to_string(), "w".to_string(), 
+            "prefix".to_string(), "--columns=utf16".to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, None, r#"}
+WORKSPACE_SYMBOLS {
+{ Function "wide" "wide" "wide.rs" { 0:9 0:21 } { 0:12 0:16 } }
+"#);
+        
+        let args = vec!["workspace-symbols".to_string(), "dir".to_string(), "x".to_string(), "exact".to_string()];
+        ParseAnalysisOptions::from_args(args.into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn outline_diff__tests() {
+        use test_util::TempDir;
+        use std::fs::File;
+        use std::io::Write;
+        
+        let dir = TempDir::new("rainicorn_outline_diff_tests");
+        dir.write_file("old.rs", "fn foo() {}\nfn bar(x : u32) {}");
+        let old_path = dir.join("old.rs");
+        
+        let args = vec!["outline-diff".to_string(), old_path.to_str().unwrap().to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"fn bar(x : u64) {}\nfn baz() {}", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("LF"), r#"}
+OUTLINE_DIFF {
+{ SignatureChanged Function { "bar" { 1:0 1:18 } "(u32)" } { "bar" { 0:0 0:18 } "(u64)" } }
+{ Renamed Function { "foo" { 0:0 0:11 } "()" } { "baz" { 1:0 1:11 } "()" } }
+"#);
+        
+        // The old source is decoded lossily
+        File::create(&old_path).unwrap().write_all(b"fn foo() {}\n// \xFF").unwrap();
+        let result = parse_analysis_bytes(b"fn foo() {}", &options, String::new()).ok().unwrap();
+        assert!(result.contains(":1:3: invalid UTF-8 byte at offset 15, replaced with U+FFFD"));
+        assert!(result.contains("OUTLINE_DIFF {\n}"));
+        
+        let args = vec!["outline-diff".to_string(), "<missing>".to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"", &options, String::new()).ok().unwrap();
+        assert!(result.contains("Could not read old source: "));
+        
+        ParseAnalysisOptions::from_args(vec!["outline-diff".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn public_api__tests() {
+        use test_util::TempDir;
+        use std::fs::File;
+        use std::io::Write;
+        
+        let options = ParseAnalysisOptions::from_args(vec!["public-api".to_string()].into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"pub fn foo() {}\nfn bar() {}", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("LF"), r#"}
+PUBLIC_API {
+{ Function "foo" "fn foo()" { 0:0 0:15 } }
+"#);
+        
+        let dir = TempDir::new("rainicorn_public_api_tests");
+        dir.write_file("old.rs", "pub fn foo() {}\npub fn bar() {}");
+        let old_path = dir.join("old.rs");
+        
+        let args = vec!["public-api-diff".to_string(), old_path.to_str().unwrap().to_string()];
+        let options = ParseAnalysisOptions::from_args(args.into_iter()).unwrap();
+        let result = parse_analysis_bytes(b"pub fn foo(x: u32) {}", &options, String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, Some("NONE"), r#"}
+PUBLIC_API_DIFF {
+Breaking 
+{ Changed Breaking Function "foo" "fn foo()" "fn foo(x: u32)" }
+{ Removed Breaking Function "bar" "fn bar()" {} }
+"#);
+        
+        // The old source is decoded lossily
+        File::create(&old_path).unwrap().write_all(b"pub fn foo() {}\n// \xFF").unwrap();
+        let result = parse_analysis_bytes(b"pub fn foo() {}", &options, String::new()).ok().unwrap();
+        assert!(result.contains(":1:3: invalid UTF-8 byte at offset 19, replaced with U+FFFD"));
+        assert!(result.contains("PUBLIC_API_DIFF {\nPatch \n}"));
+        
+        ParseAnalysisOptions::from_args(vec!["public-api-diff".to_string()].into_iter()).unwrap_err();
+    }
+    
+    #[test]
+    fn json_format__tests() {
+        let options = ParseAnalysisOptions::from_args(vec!["--format=json".to_string()].into_iter()).unwrap();
+        
+        let result = parse_analysis_with_options("fn foo() { }", &options, String::new()).ok().unwrap();
+        check_equal(&result, &(String::new() + 
+            r#"{"format":"RUST_PARSE_DESCRIBE","version":"1.0","line_endings":"NONE","messages":[],"# +
+            r#""elements":[{"kind":"Function","name":"foo","range":{"start":{"line":0,"col":0},"# + 
+            r#""end":{"line":0,"col":12}},"selection_range":{"start":{"line":0,"col":3},"end":{"line":0,"col":6}},"# +
+            r#""header_range":{"start":{"line":0,"col":0},"end":{"line":0,"col":8}},"# +
+            r#""body_range":{"start":{"line":0,"col":9},"end":{"line":0,"col":12}},"type_desc":"()","# +
+            r#""qualified_path":"foo","id":"Function foo","content_hash":"ce65c74ef29e687c","source_file":null,"children":[]}]}"#
+        ));
+        
+        let result = parse_analysis_with_options(" #blah ", &options, String::new()).ok().unwrap();
+        check_equal(&result, &(String::new() + 
+            r#"{"format":"RUST_PARSE_DESCRIBE","version":"1.0","line_endings":"NONE","messages":[{"# +
+            r#""severity":"ERROR","range":{"start":{"line":0,"col":2},"end":{"line":0,"col":6}},"# +
+            r#""message":"expected `[`, found `blah`"}],"elements":[]}"#
+        ));
+        
+        ParseAnalysisOptions::from_args(vec!["--format=xml".to_string()].into_iter()).unwrap_err();
+    }
+    
+    fn test_parse_analysis_bytes(source : &[u8], expected_msgs : &str) {
+        let result = parse_analysis_bytes(source, &ParseAnalysisOptions::default(), String::new()).ok().unwrap();
+        check_parse_analysis_result(&result, None, expected_msgs);
+    }
+    
+    fn check_parse_analysis_result(result : &str, line_endings : Option<&str>, expected_msgs : &str) {
+        let mut result : &str = result;
         
         result = assert_surrounding_string("RUST_PARSE_DESCRIBE 1.0 {", result, "}");
         
+        result = assert_starts_with("LINE_ENDINGS ", result.trim());
+        let line_endings_end = result.find('\n').unwrap();
+        if let Some(line_endings) = line_endings {
+            check_equal(result[..line_endings_end].trim(), line_endings);
+        }
+        result = &result[line_endings_end..];
+        
         result = assert_starts_with("MESSAGES {", result.trim());
-        expected_msgs.replace("\r\n", "\n");
+        let expected_msgs = expected_msgs.replace("\r\n", "\n");
         result = assert_starts_with(expected_msgs.trim(), result.trim());
         check_equal(result.trim(), "}");
     }
@@ -0,0 +1,657 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! The public API of a crate: the `pub` items reachable from the crate root, with their signatures.
+//! And the semver classification of the changes between two versions of it.
+//!
+//! This is purely syntactic: re-exports are items of their own, and the items of an `impl` are only
+//! part of the API if the `impl` is in a reachable module.
+//!
+
+use source_model::*;
+use parse_describe::{ ParsedSource, analyse_source, source_line_index };
+use module_loader::{ ModuleDecl, ModuleDirs, cfg_conditions, find_module_file, owns_directory, read_source_file,
+    warning, located_message };
+use syntax_tree::visibility_to_string;
+
+use syntex_syntax::ast::{ self, ItemKind, TraitItemKind, ImplItemKind, ForeignItemKind, TyKind, VariantData,
+    Visibility, Mutability, ImplPolarity };
+use syntex_syntax::attr;
+use syntex_syntax::codemap::Span;
+use syntex_syntax::print::pprust;
+
+use std::path::{ Path, PathBuf };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiItemKind {
+    Mod,
+    Function,
+    /// A function or method in an inherent `impl`.
+    Method,
+    Struct,
+    Union,
+    /// A public field of a struct or union.
+    Field,
+    Enum,
+    Variant,
+    Trait,
+    /// A trait method, associated type or constant without default.
+    RequiredTraitItem,
+    /// A trait method, associated type or constant with a default.
+    ProvidedTraitItem,
+    TraitImpl,
+    /// A constant, or an associated constant in an inherent `impl`.
+    Const,
+    Static,
+    /// A type alias, or an associated type in an inherent `impl`.
+    TypeAlias,
+    /// A `#[macro_export]` macro.
+    Macro,
+    /// A `pub use` declaration.
+    Reexport,
+}
+
+impl ApiItemKind {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            ApiItemKind::Mod => "Mod",
+            ApiItemKind::Function => "Function",
+            ApiItemKind::Method => "Method",
+            ApiItemKind::Struct => "Struct",
+            ApiItemKind::Union => "Union",
+            ApiItemKind::Field => "Field",
+            ApiItemKind::Enum => "Enum",
+            ApiItemKind::Variant => "Variant",
+            ApiItemKind::Trait => "Trait",
+            ApiItemKind::RequiredTraitItem => "RequiredTraitItem",
+            ApiItemKind::ProvidedTraitItem => "ProvidedTraitItem",
+            ApiItemKind::TraitImpl => "TraitImpl",
+            ApiItemKind::Const => "Const",
+            ApiItemKind::Static => "Static",
+            ApiItemKind::TypeAlias => "TypeAlias",
+            ApiItemKind::Macro => "Macro",
+            ApiItemKind::Reexport => "Reexport",
+        }
+    }
+
+    fn is_trait_item(&self) -> bool {
+        *self == ApiItemKind::RequiredTraitItem || *self == ApiItemKind::ProvidedTraitItem
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiItem {
+    pub kind : ApiItemKind,
+    /// The path of the item from the crate root, such as `foo::Bar::new`, or `foo::<impl Display for Bar>`.
+    pub path : String,
+    /// The declaration of the item without its body or value, such as `fn new(x: u32) -> Bar`.
+    /// A struct with private fields ends with `{ .. }`, or `(..)` for a tuple struct.
+    pub signature : String,
+    pub sourcerange : SourceRange,
+    /// The file of the item, if it is in a module file loaded from the filesystem.
+    pub file : Option<String>,
+}
+
+/// The public API items of given source, whose items are in the module with given path,
+/// and the declarations of the reachable out-of-line modules, with their module path.
+pub fn public_api_items(parsed : &ParsedSource, module_path : &[String])
+    -> (Vec<ApiItem>, Vec<(Vec<String>, ModuleDecl)>)
+{
+    let mut collector = ApiCollector { parsed : parsed, module_path : module_path.to_vec(), inline_path : vec![],
+        items : vec![], module_decls : vec![] };
+    if let Some(ref krate) = parsed.krate {
+        collector.collect_module(&krate.module);
+    }
+    (collector.items, collector.module_decls)
+}
+
+struct ApiCollector<'a> {
+    parsed : &'a ParsedSource,
+    module_path : Vec<String>,
+    /// The directories of the inline modules enclosing the current one, see `ModuleDecl::inline_path`.
+    inline_path : Vec<String>,
+    items : Vec<ApiItem>,
+    module_decls : Vec<(Vec<String>, ModuleDecl)>,
+}
+
+impl<'a> ApiCollector<'a> {
+
+    fn collect_module(&mut self, module : &ast::Mod) {
+        for item in &module.items {
+            self.collect_item(item);
+        }
+    }
+
+    fn collect_item(&mut self, item : &ast::Item) {
+        let name = item.ident.name.as_str().to_string();
+
+        // These items have no visibility of their own
+        match item.node {
+            ItemKind::Impl(unsafety, polarity, ref generics, ref trait_ref, ref self_ty, ref impl_items) => {
+                self.collect_impl(unsafety, polarity, generics, trait_ref, self_ty, impl_items, item.span);
+                return;
+            }
+            ItemKind::ForeignMod(ref foreign_mod) => {
+                for foreign_item in foreign_mod.items.iter().filter(|item| item.vis == Visibility::Public) {
+                    let name = foreign_item.ident.name.as_str().to_string();
+                    match foreign_item.node {
+                        ForeignItemKind::Fn(ref decl, ref generics) => {
+                            let signature = pprust::fun_to_string(decl, ast::Unsafety::Normal,
+                                ast::Constness::NotConst, foreign_item.ident, generics);
+                            self.add_item(ApiItemKind::Function, &name, signature, foreign_item.span);
+                        }
+                        ForeignItemKind::Static(ref ty, mutable) => {
+                            let signature = format!("static {}{}: {}", if mutable { "mut " } else { "" }, name,
+                                pprust::ty_to_string(ty));
+                            self.add_item(ApiItemKind::Static, &name, signature, foreign_item.span);
+                        }
+                    }
+                }
+                return;
+            }
+            ItemKind::Mac(ref mac) => {
+                let is_macro_rules = pprust::path_to_string(&mac.node.path) == "macro_rules";
+                if is_macro_rules && attr::contains_name(&item.attrs, "macro_export") {
+                    // Exported macros are at the crate root
+                    let signature = format!("macro_rules! {}", name);
+                    let sourcerange = self.source_range(item.span);
+                    self.items.push(ApiItem { kind : ApiItemKind::Macro, path : name, signature : signature,
+                        sourcerange : sourcerange, file : None });
+                }
+                return;
+            }
+            _ => { }
+        }
+
+        if item.vis != Visibility::Public {
+            return;
+        }
+
+        match item.node {
+            ItemKind::Mod(ref module) => {
+                self.add_item(ApiItemKind::Mod, &name, format!("mod {}", name), item.span);
+                self.collect_submodule(item, module, name);
+            }
+            ItemKind::Fn(ref decl, unsafety, constness, _, ref generics, _) => {
+                let signature = pprust::fun_to_string(decl, unsafety, constness.node, item.ident, generics);
+                self.add_item(ApiItemKind::Function, &name, signature, item.span);
+            }
+            ItemKind::Const(ref ty, _) => {
+                let signature = format!("const {}: {}", name, pprust::ty_to_string(ty));
+                self.add_item(ApiItemKind::Const, &name, signature, item.span);
+            }
+            ItemKind::Static(ref ty, mutability, _) => {
+                let mutable = if mutability == Mutability::Mutable { "mut " } else { "" };
+                let signature = format!("static {}{}: {}", mutable, name, pprust::ty_to_string(ty));
+                self.add_item(ApiItemKind::Static, &name, signature, item.span);
+            }
+            ItemKind::Ty(ref ty, ref generics) => {
+                let signature = format!("type {}{} = {}", name, generics_signature(generics),
+                    pprust::ty_to_string(ty));
+                self.add_item(ApiItemKind::TypeAlias, &name, signature, item.span);
+            }
+            ItemKind::Struct(ref data, ref generics) => {
+                self.collect_struct(ApiItemKind::Struct, "struct", &name, data, generics, item.span);
+            }
+            ItemKind::Union(ref data, ref generics) => {
+                self.collect_struct(ApiItemKind::Union, "union", &name, data, generics, item.span);
+            }
+            ItemKind::Enum(ref enum_def, ref generics) => {
+                let signature = format!("enum {}{}", name, generics_signature(generics));
+                self.add_item(ApiItemKind::Enum, &name, signature, item.span);
+
+                for variant in &enum_def.variants {
+                    let path = format!("{}::{}", name, variant.node.name.name.as_str());
+                    self.add_item(ApiItemKind::Variant, &path, pprust::variant_to_string(variant), variant.span);
+                }
+            }
+            ItemKind::Trait(unsafety, ref generics, ref bounds, ref trait_items) => {
+                let signature = format!("{}trait {}{}{}", unsafety_prefix(unsafety), name,
+                    generics_signature(generics),
+                    if bounds.is_empty() { String::new() } else { format!(":{}", pprust::bounds_to_string(bounds)) });
+                self.add_item(ApiItemKind::Trait, &name, signature, item.span);
+
+                for trait_item in trait_items {
+                    self.collect_trait_item(&name, trait_item);
+                }
+            }
+            ItemKind::Use(ref view_path) => {
+                let use_decl = format!("use {}", pprust::to_string(|s| s.print_view_path(view_path)));
+                self.add_item(ApiItemKind::Reexport, &format!("<{}>", use_decl), format!("pub {}", use_decl),
+                    item.span);
+            }
+            _ => { }
+        }
+    }
+
+    fn collect_submodule(&mut self, item : &ast::Item, module : &ast::Mod, name : String) {
+        let path_attr = attr::first_attr_value_str_by_name(&item.attrs, "path").map(|path| path.to_string());
+        let is_out_of_line = self.parsed.codemap.span_to_snippet(item.span)
+            .map_or(false, |snippet| snippet.trim_right().ends_with(';'));
+
+        let mut module_path = self.module_path.clone();
+        module_path.push(name.clone());
+
+        if is_out_of_line {
+            let decl = ModuleDecl {
+                name : name,
+                sourcerange : self.source_range(item.span),
+                vis : visibility_to_string(&item.vis),
+                cfgs : cfg_conditions(&item.attrs),
+                path_attr : path_attr,
+                inline_path : self.inline_path.clone(),
+                inline_decls : None,
+            };
+            self.module_decls.push((module_path, decl));
+        } else {
+            let outer_module_path = ::std::mem::replace(&mut self.module_path, module_path);
+            self.inline_path.push(path_attr.unwrap_or(name));
+            self.collect_module(module);
+            self.inline_path.pop();
+            self.module_path = outer_module_path;
+        }
+    }
+
+    fn collect_struct(&mut self, kind : ApiItemKind, keyword : &str, name : &str, data : &VariantData,
+        generics : &ast::Generics, span : Span)
+    {
+        let has_private_fields = data.fields().iter().any(|field| field.vis != Visibility::Public);
+        let private_fields = match *data {
+            VariantData::Struct(..) if has_private_fields => " { .. }",
+            VariantData::Tuple(..) if has_private_fields => "(..)",
+            _ => "",
+        };
+        let signature = format!("{} {}{}{}", keyword, name, generics_signature(generics), private_fields);
+        self.add_item(kind, name, signature, span);
+
+        for (ix, field) in data.fields().iter().enumerate() {
+            if field.vis != Visibility::Public {
+                continue;
+            }
+            let field_name = field.ident.map_or(ix.to_string(), |ident| ident.name.as_str().to_string());
+            let signature = format!("{}: {}", field_name, pprust::ty_to_string(&field.ty));
+            self.add_item(ApiItemKind::Field, &format!("{}::{}", name, field_name), signature, field.span);
+        }
+    }
+
+    fn collect_trait_item(&mut self, trait_name : &str, trait_item : &ast::TraitItem) {
+        let name = trait_item.ident.name.as_str().to_string();
+        let (signature, has_default) = match trait_item.node {
+            TraitItemKind::Method(ref sig, ref body) => {
+                let signature = pprust::fun_to_string(&sig.decl, sig.unsafety, sig.constness.node,
+                    trait_item.ident, &sig.generics);
+                (signature, body.is_some())
+            }
+            TraitItemKind::Const(ref ty, ref default) => {
+                (format!("const {}: {}", name, pprust::ty_to_string(ty)), default.is_some())
+            }
+            TraitItemKind::Type(ref bounds, ref default) => {
+                let bounds = if bounds.is_empty() { String::new() } else {
+                    format!(":{}", pprust::bounds_to_string(bounds))
+                };
+                (format!("type {}{}", name, bounds), default.is_some())
+            }
+            TraitItemKind::Macro(_) => return,
+        };
+        let kind = if has_default { ApiItemKind::ProvidedTraitItem } else { ApiItemKind::RequiredTraitItem };
+        self.add_item(kind, &format!("{}::{}", trait_name, name), signature, trait_item.span);
+    }
+
+    fn collect_impl(&mut self, unsafety : ast::Unsafety, polarity : ImplPolarity, generics : &ast::Generics,
+        trait_ref : &Option<ast::TraitRef>, self_ty : &ast::Ty, impl_items : &[ast::ImplItem], span : Span)
+    {
+        if let Some(ref trait_ref) = *trait_ref {
+            // The items of a trait impl are part of the API through the trait
+            let trait_path = pprust::path_to_string(&trait_ref.path);
+            let negative = if polarity == ImplPolarity::Negative { "!" } else { "" };
+            let path = format!("<impl {} for {}>", trait_path, pprust::ty_to_string(self_ty));
+            let signature = format!("{}impl{} {}{} for {}", unsafety_prefix(unsafety), generics_signature(generics),
+                negative, trait_path, pprust::ty_to_string(self_ty));
+            self.add_item(ApiItemKind::TraitImpl, &path, signature, span);
+            return;
+        }
+
+        // The items are in the path of the type, without generic arguments
+        let type_name = match self_ty.node {
+            TyKind::Path(None, ref path) if !path.segments.is_empty() => {
+                path.segments[path.segments.len() - 1].identifier.name.as_str().to_string()
+            }
+            _ => pprust::ty_to_string(self_ty),
+        };
+
+        for impl_item in impl_items.iter().filter(|item| item.vis == Visibility::Public) {
+            let name = impl_item.ident.name.as_str().to_string();
+            let (kind, signature) = match impl_item.node {
+                ImplItemKind::Method(ref sig, _) => {
+                    (ApiItemKind::Method, pprust::fun_to_string(&sig.decl, sig.unsafety, sig.constness.node,
+                        impl_item.ident, &sig.generics))
+                }
+                ImplItemKind::Const(ref ty, _) => {
+                    (ApiItemKind::Const, format!("const {}: {}", name, pprust::ty_to_string(ty)))
+                }
+                ImplItemKind::Type(ref ty) => {
+                    (ApiItemKind::TypeAlias, format!("type {} = {}", name, pprust::ty_to_string(ty)))
+                }
+                ImplItemKind::Macro(_) => continue,
+            };
+            self.add_item(kind, &format!("{}::{}", type_name, name), signature, impl_item.span);
+        }
+    }
+
+    /// Add an item with given path, relative to the current module.
+    fn add_item(&mut self, kind : ApiItemKind, path : &str, signature : String, span : Span) {
+        let mut full_path = self.module_path.clone();
+        full_path.push(String::from(path));
+
+        let sourcerange = self.source_range(span);
+        self.items.push(ApiItem { kind : kind, path : full_path.join("::"),
+            signature : normalize_whitespace(&signature), sourcerange : sourcerange, file : None });
+    }
+
+    fn source_range(&self, span : Span) -> SourceRange {
+        SourceRange::from_span(&self.parsed.codemap, &self.parsed.line_index, span)
+    }
+
+}
+
+fn generics_signature(generics : &ast::Generics) -> String {
+    pprust::generics_to_string(generics) + &pprust::where_clause_to_string(&generics.where_clause)
+}
+
+fn unsafety_prefix(unsafety : ast::Unsafety) -> &'static str {
+    if unsafety == ast::Unsafety::Unsafe { "unsafe " } else { "" }
+}
+
+/// Given text with each whitespace run replaced by a single space (the pretty printer breaks long lines).
+pub fn normalize_whitespace(text : &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+
+/// The public API of the crate root with given source. If the path of the crate root is given, the reachable
+/// out-of-line modules are loaded from the filesystem, and their items have ranges in given encoding.
+pub fn crate_public_api(root_path : Option<&Path>, source : &str, encoding : ColumnEncoding)
+    -> (Vec<SourceMessage>, Vec<ApiItem>)
+{
+    let mut loader = ApiLoader { encoding : encoding, loading : vec![] };
+    loader.file_api(root_path, source, vec![], true)
+}
+
+struct ApiLoader {
+    encoding : ColumnEncoding,
+    /// The files being loaded, to detect circular modules.
+    loading : Vec<PathBuf>,
+}
+
+impl ApiLoader {
+
+    fn file_api(&mut self, path : Option<&Path>, source : &str, module_path : Vec<String>, owns_directory : bool)
+        -> (Vec<SourceMessage>, Vec<ApiItem>)
+    {
+        let (mut messages, result) = analyse_source(source, move |parsed| public_api_items(parsed, &module_path));
+        let (mut items, decls) = result.unwrap_or((vec![], vec![]));
+
+        let path = match path {
+            Some(path) => path,
+            None => return (messages, items),
+        };
+        let dirs = ModuleDirs::new(path, owns_directory);
+        self.loading.push(path.to_path_buf());
+
+        for (module_path, decl) in decls {
+            if let Some(file_path) = find_module_file(&dirs, &decl, &mut messages) {
+                self.load_module(&decl, &file_path, module_path, &mut messages, &mut items);
+            }
+        }
+
+        self.loading.pop();
+        (messages, items)
+    }
+
+    fn load_module(&mut self, decl : &ModuleDecl, file_path : &Path, module_path : Vec<String>,
+        messages : &mut Vec<SourceMessage>, items : &mut Vec<ApiItem>)
+    {
+        if self.loading.iter().any(|path| path == file_path) {
+            messages.push(warning(Some(decl.sourcerange),
+                format!("circular modules: `{}` is already being loaded", file_path.display())));
+            return;
+        }
+        let source = match read_source_file(file_path) {
+            Ok(source) => source,
+            Err(error) => {
+                messages.push(warning(Some(decl.sourcerange),
+                    format!("could not read file `{}`: {}", file_path.display(), error)));
+                return;
+            }
+        };
+
+        let file_name = file_path.display().to_string();
+        let mut file_messages = source.messages;
+        let (parse_messages, mut file_items) =
+            self.file_api(Some(file_path), &source.text, module_path, owns_directory(decl, file_path));
+        file_messages.extend(parse_messages);
+        apply_column_encoding_to_items(&source_line_index(&source.text), self.encoding, &mut file_items);
+        for item in file_items.iter_mut().filter(|item| item.file.is_none()) {
+            item.file = Some(file_name.clone());
+        }
+
+        messages.extend(file_messages.into_iter().map(|message| located_message(&file_name, message)));
+        items.extend(file_items);
+    }
+
+}
+
+/// Apply given column encoding to the ranges of given items of the source of given line index (those with
+/// no file).
+pub fn apply_column_encoding_to_items(line_index : &LineIndex, encoding : ColumnEncoding, items : &mut [ApiItem]) {
+    for item in items.iter_mut().filter(|item| item.file.is_none()) {
+        item.sourcerange = item.sourcerange.with_column_encoding(line_index, encoding);
+    }
+}
+
+
+/// How a change affects the users of an API, as in semantic versioning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SemverLevel {
+    /// No change to the API.
+    Patch,
+    /// A compatible change, such as a new item.
+    Additive,
+    /// A change that can break the users of the API, such as a removed item.
+    Breaking,
+}
+
+impl SemverLevel {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            SemverLevel::Patch => "Patch",
+            SemverLevel::Additive => "Additive",
+            SemverLevel::Breaking => "Breaking",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiChangeKind {
+    Added,
+    Removed,
+    /// The signature or the kind of the item changed.
+    Changed,
+}
+
+impl ApiChangeKind {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            ApiChangeKind::Added => "Added",
+            ApiChangeKind::Removed => "Removed",
+            ApiChangeKind::Changed => "Changed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiChange {
+    pub kind : ApiChangeKind,
+    pub level : SemverLevel,
+    pub old : Option<ApiItem>,
+    pub new : Option<ApiItem>,
+}
+
+impl ApiChange {
+    pub fn item(&self) -> &ApiItem {
+        self.new.as_ref().or(self.old.as_ref()).unwrap()
+    }
+}
+
+/// The changes from the old API items to the new ones. Items are matched by path and kind (a trait item
+/// that gained or lost a default is changed).
+pub fn public_api_diff(old_items : &[ApiItem], new_items : &[ApiItem]) -> Vec<ApiChange> {
+    let mut changes = vec![];
+    let mut new_matched = vec![false; new_items.len()];
+
+    let same_item = |old : &ApiItem, new : &ApiItem| {
+        old.path == new.path && (old.kind == new.kind || (old.kind.is_trait_item() && new.kind.is_trait_item()))
+    };
+
+    for old in old_items {
+        let position = (0..new_items.len()).position(|ix| !new_matched[ix] && same_item(old, &new_items[ix]));
+        let ix = match position {
+            Some(ix) => ix,
+            None => {
+                changes.push(ApiChange { kind : ApiChangeKind::Removed, level : SemverLevel::Breaking,
+                    old : Some(old.clone()), new : None });
+                continue;
+            }
+        };
+        new_matched[ix] = true;
+        let new = &new_items[ix];
+
+        if old.signature != new.signature || old.kind != new.kind {
+            // A trait item that only gained a default doesn't break implementations
+            let level = if old.signature == new.signature && new.kind == ApiItemKind::ProvidedTraitItem {
+                SemverLevel::Additive
+            } else {
+                SemverLevel::Breaking
+            };
+            changes.push(ApiChange { kind : ApiChangeKind::Changed, level : level,
+                old : Some(old.clone()), new : Some(new.clone()) });
+        }
+    }
+
+    for (ix, new) in new_items.iter().enumerate() {
+        if !new_matched[ix] {
+            changes.push(ApiChange { kind : ApiChangeKind::Added, level : added_item_level(old_items, new),
+                old : None, new : Some(new.clone()) });
+        }
+    }
+    changes
+}
+
+/// The level of adding given item. Adding an item is compatible, unless it's part of an existing enum,
+/// trait or struct, and must be handled by the users of it: a variant (in matches), a required trait item
+/// (in implementations), a field of a struct with no private fields (in struct expressions).
+fn added_item_level(old_items : &[ApiItem], new : &ApiItem) -> SemverLevel {
+    let parent_path = match new.path.rfind("::") {
+        Some(ix) => &new.path[..ix],
+        None => return SemverLevel::Additive,
+    };
+    let old_parent = old_items.iter().find(|old| old.path == parent_path && match old.kind {
+        ApiItemKind::Enum | ApiItemKind::Trait | ApiItemKind::Struct | ApiItemKind::Union => true,
+        _ => false,
+    });
+    let old_parent = match old_parent {
+        Some(old_parent) => old_parent,
+        None => return SemverLevel::Additive,
+    };
+
+    let breaking = match new.kind {
+        ApiItemKind::Variant | ApiItemKind::RequiredTraitItem => true,
+        ApiItemKind::Field => !(old_parent.signature.ends_with("{ .. }") || old_parent.signature.ends_with("(..)")),
+        _ => false,
+    };
+    if breaking { SemverLevel::Breaking } else { SemverLevel::Additive }
+}
+
+/// The level of a new version with given changes: the level of the most severe change.
+pub fn semver_level(changes : &[ApiChange]) -> SemverLevel {
+    changes.iter().map(|change| change.level).max().unwrap_or(SemverLevel::Patch)
+}
+
+
+#[test]
+fn test_public_api() {
+    fn api(source : &str) -> Vec<(ApiItemKind, String, String)> {
+        let (_, items) = crate_public_api(None, source, ColumnEncoding::Chars);
+        items.into_iter().map(|item| (item.kind, item.path, item.signature)).collect()
+    }
+    fn item(kind : ApiItemKind, path : &str, signature : &str) -> (ApiItemKind, String, String) {
+        (kind, String::from(path), String::from(signature))
+    }
+
+    assert_eq!(api("pub fn foo(x: u32) -> u32 { x } fn private() {} pub(crate) fn krate() {}"), vec![
+        item(ApiItemKind::Function, "foo", "fn foo(x: u32) -> u32"),
+    ]);
+    assert_eq!(api("pub mod m { pub struct Foo<T> { pub x: T, y: u32 } pub struct Bar(pub u8); \
+        impl<T> Foo<T> { pub fn new() -> Self { } fn private(&self) {} } } mod private { pub fn hidden() {} }"), vec![
+        item(ApiItemKind::Mod, "m", "mod m"),
+        item(ApiItemKind::Struct, "m::Foo", "struct Foo<T> { .. }"),
+        item(ApiItemKind::Field, "m::Foo::x", "x: T"),
+        item(ApiItemKind::Struct, "m::Bar", "struct Bar"),
+        item(ApiItemKind::Field, "m::Bar::0", "0: u8"),
+        item(ApiItemKind::Method, "m::Foo::new", "fn new() -> Self"),
+    ]);
+    assert_eq!(api("pub enum E { A, B(u32) } pub trait T: Clone { fn req(&self); fn prov(&self) {} type X; }\n\
+        impl T for E { } #[macro_export] macro_rules! mac { () => () } pub use m::Foo;"), vec![
+        item(ApiItemKind::Enum, "E", "enum E"),
+        item(ApiItemKind::Variant, "E::A", "A"),
+        item(ApiItemKind::Variant, "E::B", "B(u32)"),
+        item(ApiItemKind::Trait, "T", "trait T: Clone"),
+        item(ApiItemKind::RequiredTraitItem, "T::req", "fn req(&self)"),
+        item(ApiItemKind::ProvidedTraitItem, "T::prov", "fn prov(&self)"),
+        item(ApiItemKind::RequiredTraitItem, "T::X", "type X"),
+        item(ApiItemKind::TraitImpl, "<impl T for E>", "impl T for E"),
+        item(ApiItemKind::Macro, "mac", "macro_rules! mac"),
+        item(ApiItemKind::Reexport, "<use m::Foo>", "pub use m::Foo"),
+    ]);
+}
+
+#[test]
+fn test_public_api_diff() {
+    fn diff(old_source : &str, new_source : &str) -> (SemverLevel, Vec<(ApiChangeKind, SemverLevel, String)>) {
+        let (_, old_items) = crate_public_api(None, old_source, ColumnEncoding::Chars);
+        let (_, new_items) = crate_public_api(None, new_source, ColumnEncoding::Chars);
+        let changes = public_api_diff(&old_items, &new_items);
+        (semver_level(&changes),
+            changes.iter().map(|change| (change.kind, change.level, change.item().path.clone())).collect())
+    }
+    use self::ApiChangeKind::*;
+    use self::SemverLevel::*;
+
+    assert_eq!(diff("pub fn foo() {}", "pub fn foo() { 1; } fn other() {}"), (Patch, vec![]));
+    assert_eq!(diff("pub fn foo() {}", "pub fn foo() {} pub fn bar() {}"),
+        (Additive, vec![(Added, Additive, "bar".to_string())]));
+    assert_eq!(diff("pub fn foo() {} pub fn bar() {}", "pub fn foo(x: u32) {}"),
+        (Breaking, vec![(Changed, Breaking, "foo".to_string()), (Removed, Breaking, "bar".to_string())]));
+
+    assert_eq!(diff("pub enum E { A }", "pub enum E { A, B }"), (Breaking, vec![(Added, Breaking, "E::B".to_string())]));
+    assert_eq!(diff("pub trait T { fn a(&self); }", "pub trait T { fn a(&self) {} fn b(&self) {} }"),
+        (Additive, vec![(Changed, Additive, "T::a".to_string()), (Added, Additive, "T::b".to_string())]));
+    assert_eq!(diff("pub trait T { }", "pub trait T { fn a(&self); }"),
+        (Breaking, vec![(Added, Breaking, "T::a".to_string())]));
+    assert_eq!(diff("pub struct S { pub x: u32 }", "pub struct S { pub x: u32, pub y: u32 }"),
+        (Breaking, vec![(Added, Breaking, "S::y".to_string())]));
+    assert_eq!(diff("pub struct S { pub x: u32, z: u32 }", "pub struct S { pub x: u32, pub y: u32, z: u32 }"),
+        (Additive, vec![(Added, Additive, "S::y".to_string())]));
+}
@@ -0,0 +1,357 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Syntactic rename of local variables, parameters, generic parameters, lifetimes and labels.
+//!
+
+use source_model::*;
+use source_lexer::{ lex_all_tokens, file_offset };
+use name_resolution::{ resolve_names, NameResolution, DefinitionKind };
+use fragment::ParseTarget;
+use parse_describe::{ ParsedSource, analyse_source_as };
+
+use syntex_syntax::visit::*;
+use syntex_syntax::ast::*;
+use syntex_syntax::parse::token;
+use syntex_syntax::codemap::{ Span, CodeMap };
+
+use std::collections::{ HashMap, HashSet };
+use std::rc::Rc;
+
+/// A replacement of the text in a range of the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub sourcerange : SourceRange,
+    pub new_text : String,
+}
+
+/// Rename the local variable, parameter, generic parameter, lifetime or label at given position to `new_name`.
+/// Returns the edits, sorted by range, or an error message if the name can't be renamed.
+///
+/// The rename is refused if the new name would shadow another definition, or if any name of the renamed
+/// source would resolve to another definition.
+pub fn rename(source : &str, target : ParseTarget, pos : &LineColumnPosition, new_name : &str)
+    -> (Vec<SourceMessage>, Result<Vec<TextEdit>, SourceMessage>)
+{
+    let pos = *pos;
+    let name = String::from(new_name);
+    let (messages, plan) = analyse_source_as(source, target, move |parsed| plan_rename(parsed, &pos, &name));
+
+    let result = match plan {
+        Some(Ok(plan)) => check_rename(&plan, target, new_name).map(|_| {
+            plan.edits.into_iter().map(|edit| TextEdit { sourcerange : edit.sourcerange, new_text : edit.new_text })
+                .collect()
+        }),
+        Some(Err(error)) => Err(error),
+        None => Err(error_message(None, "Could not analyse the source.".to_string())),
+    };
+    (messages, result)
+}
+
+fn error_message(sourcerange : Option<SourceRange>, message : String) -> SourceMessage {
+    SourceMessage { severity : Severity::ERROR, sourcerange : sourcerange, message : message }
+}
+
+/// An edit by offsets.
+struct NameEdit {
+    start : usize,
+    end : usize,
+    sourcerange : SourceRange,
+    new_text : String,
+    /// The offset of the new name in the new text.
+    name_offset : usize,
+}
+
+/// A reference by offsets.
+struct ReferenceOffsets {
+    start : usize,
+    /// The start offset of the name of the definition.
+    definition_start : usize,
+    sourcerange : SourceRange,
+}
+
+/// The edits of a rename, to be checked against the renamed source.
+struct RenamePlan {
+    old_name : String,
+    edits : Vec<NameEdit>,
+    renamed_source : String,
+    /// The start offset of the name of the renamed definition.
+    definition_start : usize,
+    references : Vec<ReferenceOffsets>,
+}
+
+impl RenamePlan {
+
+    /// The offset in the renamed source of given offset of the source.
+    fn renamed_offset(&self, offset : usize) -> usize {
+        let mut delta : isize = 0;
+        for edit in &self.edits {
+            if offset >= edit.start && offset < edit.end {
+                return (edit.start as isize + delta) as usize + edit.name_offset;
+            }
+            if edit.end <= offset {
+                delta += edit.new_text.len() as isize - (edit.end - edit.start) as isize;
+            }
+        }
+        (offset as isize + delta) as usize
+    }
+
+}
+
+fn plan_rename(parsed : &ParsedSource, pos : &LineColumnPosition, new_name : &str)
+    -> Result<RenamePlan, SourceMessage>
+{
+    let resolution = resolve_names(parsed);
+    let reference = match resolution.reference_at(pos) {
+        Some(reference) => reference,
+        None => return Err(error_message(None, "There is no name to rename at the position.".to_string())),
+    };
+    let definition = &resolution.definitions[reference.definition];
+    let is_lifetime = match definition.kind {
+        DefinitionKind::Local | DefinitionKind::Param | DefinitionKind::GenericParam => false,
+        DefinitionKind::Lifetime | DefinitionKind::Label => true,
+        DefinitionKind::Item(_) => {
+            return Err(error_message(Some(reference.sourcerange),
+                "Only local variables, parameters, generic parameters, lifetimes and labels can be renamed."
+                .to_string()));
+        }
+    };
+    if !is_valid_name(new_name, is_lifetime) {
+        let message = if is_lifetime {
+            format!("`{}` is not a valid lifetime or label name.", new_name)
+        } else {
+            format!("`{}` is not a valid identifier.", new_name)
+        };
+        return Err(error_message(Some(reference.sourcerange), message));
+    }
+
+    let mut shorthand_fields = ShorthandFields { codemap : &parsed.codemap, field_starts : HashMap::new() };
+    parsed.walk(&mut shorthand_fields);
+    let field_starts = shorthand_fields.field_starts;
+
+    let mut edits = vec![];
+    if definition.name != new_name {
+        for reference in resolution.references_to(reference.definition) {
+            let (start, end) = match parsed.line_index.range_offsets(&reference.sourcerange) {
+                Some(offsets) => offsets,
+                None => continue,
+            };
+            let edit = match field_starts.get(&start) {
+                // The field name of a shorthand field is kept: `Foo { ref x }` becomes `Foo { x: ref y }`
+                Some(&field_start) => {
+                    let new_text = format!("{}: {}{}", &parsed.source[start..end],
+                        &parsed.source[field_start..start], new_name);
+                    NameEdit { start : field_start, end : end,
+                        sourcerange : parsed.line_index.range_at(field_start, end),
+                        name_offset : new_text.len() - new_name.len(), new_text : new_text }
+                }
+                None => NameEdit { start : start, end : end, sourcerange : reference.sourcerange,
+                    new_text : String::from(new_name), name_offset : 0 },
+            };
+            edits.push(edit);
+        }
+    }
+
+    let mut renamed_source = String::new();
+    let mut last_end = 0;
+    for edit in &edits {
+        renamed_source.push_str(&parsed.source[last_end..edit.start]);
+        renamed_source.push_str(&edit.new_text);
+        last_end = edit.end;
+    }
+    renamed_source.push_str(&parsed.source[last_end..]);
+
+    let definition_start = parsed.line_index.range_offsets(&definition.name_range).map_or(0, |(start, _)| start);
+    Ok(RenamePlan {
+        old_name : definition.name.clone(), edits : edits, renamed_source : renamed_source,
+        definition_start : definition_start, references : reference_offsets(&parsed.line_index, &resolution),
+    })
+}
+
+/// Check that the renamed definition doesn't shadow another one, and that the other names still resolve
+/// to the same definitions in the renamed source.
+fn check_rename(plan : &RenamePlan, target : ParseTarget, new_name : &str) -> Result<(), SourceMessage> {
+    if plan.edits.is_empty() {
+        return Ok(());
+    }
+
+    let renamed_start = plan.renamed_offset(plan.definition_start);
+    let (_, renamed) = analyse_source_as(&plan.renamed_source, target, move |parsed| {
+        let resolution = resolve_names(parsed);
+        let start_of = |range : &SourceRange| parsed.line_index.range_offsets(range).map(|(start, _)| start);
+
+        let shadowed = resolution.definitions.iter()
+            .find(|definition| start_of(&definition.name_range) == Some(renamed_start))
+            .and_then(|definition| definition.shadowed)
+            .and_then(|shadowed| start_of(&resolution.definitions[shadowed].name_range));
+        (reference_offsets(&parsed.line_index, &resolution), shadowed)
+    });
+    let (renamed_references, shadowed) = match renamed {
+        Some(renamed) => renamed,
+        None => return Err(error_message(None, "Could not analyse the renamed source.".to_string())),
+    };
+
+    if let Some(shadowed_start) = shadowed {
+        let sourcerange = plan.references.iter()
+            .find(|reference| plan.renamed_offset(reference.start) == shadowed_start)
+            .map(|reference| reference.sourcerange);
+        return Err(error_message(sourcerange,
+            format!("Renaming `{}` to `{}` would shadow another definition of `{}`.",
+                plan.old_name, new_name, new_name)));
+    }
+
+    let renamed_references : HashSet<(usize, usize)> = renamed_references.iter()
+        .map(|reference| (reference.start, reference.definition_start)).collect();
+    for reference in &plan.references {
+        let renamed_reference =
+            (plan.renamed_offset(reference.start), plan.renamed_offset(reference.definition_start));
+        if !renamed_references.contains(&renamed_reference) {
+            return Err(error_message(Some(reference.sourcerange),
+                format!("Renaming `{}` to `{}` would change the definition this name refers to.",
+                    plan.old_name, new_name)));
+        }
+    }
+    Ok(())
+}
+
+fn reference_offsets(line_index : &LineIndex, resolution : &NameResolution) -> Vec<ReferenceOffsets> {
+    let start_of = |range : &SourceRange| line_index.range_offsets(range).map(|(start, _)| start);
+
+    resolution.references.iter().filter_map(|reference| {
+        let definition = &resolution.definitions[reference.definition];
+        match (start_of(&reference.sourcerange), start_of(&definition.name_range)) {
+            (Some(start), Some(definition_start)) => Some(ReferenceOffsets {
+                start : start, definition_start : definition_start, sourcerange : reference.sourcerange
+            }),
+            _ => None,
+        }
+    }).collect()
+}
+
+/// Whether given name is a single identifier, that isn't a keyword, or a single lifetime other than `'static`.
+fn is_valid_name(name : &str, is_lifetime : bool) -> bool {
+    let tokens = lex_all_tokens(Rc::new(CodeMap::new()), name);
+    if tokens.len() != 1 {
+        return false;
+    }
+    match tokens[0].tok {
+        token::Lifetime(ident) => is_lifetime && &*ident.name.as_str() != "'static",
+        ref tok @ token::Ident(_) => !is_lifetime && !tok.is_any_keyword(),
+        _ => false,
+    }
+}
+
+/// Collects the fields written in shorthand form, `Foo { x }`: the start offset of each field, by the
+/// offset of its name.
+struct ShorthandFields<'a> {
+    codemap : &'a CodeMap,
+    field_starts : HashMap<usize, usize>,
+}
+
+impl<'a> Visitor for ShorthandFields<'a> {
+
+    fn visit_pat(&mut self, pat : &Pat) {
+        if let PatKind::Struct(_, ref fields, _) = pat.node {
+            for field in fields.iter().filter(|field| field.node.is_shorthand) {
+                if let Some(name_span) = binding_name_span(&field.node.pat) {
+                    let name_start = file_offset(self.codemap, name_span.lo);
+                    self.field_starts.insert(name_start, file_offset(self.codemap, field.span.lo));
+                }
+            }
+        }
+        walk_pat(self, pat);
+    }
+
+    fn visit_expr(&mut self, expr : &Expr) {
+        if let ExprKind::Struct(_, ref fields, _) = expr.node {
+            // The expression of a shorthand field is the field name itself
+            for field in fields.iter().filter(|field| field.expr.span == field.ident.span) {
+                let start = file_offset(self.codemap, field.span.lo);
+                self.field_starts.insert(start, start);
+            }
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_mac(&mut self, _mac : &Mac) {
+        // Macro invocations are not expanded, nothing to visit
+    }
+}
+
+/// The span of the name bound by the pattern of a shorthand field, such as `ref x` or `box x`.
+fn binding_name_span(pat : &Pat) -> Option<Span> {
+    match pat.node {
+        PatKind::Ident(_, ref ident, _) => Some(ident.span),
+        PatKind::Box(ref pat) => binding_name_span(pat),
+        _ => None,
+    }
+}
+
+
+#[test]
+fn test_rename() {
+
+    fn check_rename(source : &str, name_offset : usize, new_name : &str, expected : Result<&str, &str>) {
+        let line_index = LineIndex::new(source);
+        let pos = line_index.position_at(name_offset);
+
+        let result = rename(source, ParseTarget::Crate, &pos, new_name).1.map(|edits| {
+            let mut renamed = String::from(source);
+            for edit in edits.iter().rev() {
+                let (start, end) = line_index.range_offsets(&edit.sourcerange).unwrap();
+                renamed = format!("{}{}{}", &renamed[..start], edit.new_text, &renamed[end..]);
+            }
+            renamed
+        });
+        match (result, expected) {
+            (Ok(renamed), Ok(expected)) => assert_eq!(renamed, expected),
+            (Err(error), Err(expected)) => assert!(error.message.starts_with(expected), error.message),
+            (result, expected) => panic!("{:?} != {:?}", result.map_err(|error| error.message), expected),
+        }
+    }
+
+    check_rename("fn f(x : u32) -> u32 { let y = x; x + y }", 5, "z",
+        Ok("fn f(z : u32) -> u32 { let y = z; z + y }"));
+    check_rename("fn f(x : u32) -> u32 { let y = x; x + y }", 31, "z",
+        Ok("fn f(z : u32) -> u32 { let y = z; z + y }"));
+    check_rename("fn f<T>(t : T) -> T { t }", 5, "U", Ok("fn f<U>(t : U) -> U { t }"));
+    check_rename("fn f<'a>(x : &'a u32) -> &'a u32 { x }", 5, "'b", Ok("fn f<'b>(x : &'b u32) -> &'b u32 { x }"));
+    check_rename("fn f() { 'outer: loop { break 'outer; } }", 9, "'done",
+        Ok("fn f() { 'done: loop { break 'done; } }"));
+    check_rename("fn f(x : u32) {}", 5, "x", Ok("fn f(x : u32) {}"));
+
+    // Shorthand fields
+    check_rename("struct P { x : u32 }\nfn f(p : P) -> u32 { let P { ref x } = p; *x }", 54, "y",
+        Ok("struct P { x : u32 }\nfn f(p : P) -> u32 { let P { x: ref y } = p; *y }"));
+    check_rename("struct P { x : u32 }\nfn f(p : P) -> u32 { match p { P { x } => x } }", 56, "y",
+        Ok("struct P { x : u32 }\nfn f(p : P) -> u32 { match p { P { x: y } => y } }"));
+    check_rename("struct P { x : u32 }\nfn f(x : u32) -> P { P { x } }", 26, "y",
+        Ok("struct P { x : u32 }\nfn f(y : u32) -> P { P { x: y } }"));
+    check_rename("struct P { x : u32 }\nfn f(x : u32) -> P { P { x } }", 46, "y",
+        Ok("struct P { x : u32 }\nfn f(y : u32) -> P { P { x: y } }"));
+
+    // Refused renames
+    check_rename("fn f() {}", 3, "g", Err("Only local variables"));
+    check_rename("fn f() {}", 8, "g", Err("There is no name"));
+    check_rename("fn f(x : u32) {}", 5, "fn", Err("`fn` is not a valid identifier"));
+    check_rename("fn f(x : u32) {}", 5, "a b", Err("`a b` is not a valid identifier"));
+    check_rename("fn f<'a>(x : &'a u32) {}", 5, "b", Err("`b` is not a valid lifetime"));
+    check_rename("fn f(a : u32, b : u32) -> u32 { b }", 14, "a", Err("Renaming `b` to `a` would shadow"));
+    check_rename("fn f(a : u32) -> u32 { let b = a; b }", 27, "a", Err("Renaming `b` to `a` would shadow"));
+    check_rename("fn f(a : u32) -> u32 { let b = 1; a + b }", 5, "b",
+        Err("Renaming `a` to `b` would change the definition"));
+    check_rename("fn f() -> u32 { let a = 1; { let b = 2; a + b } }", 20, "b",
+        Err("Renaming `a` to `b` would change the definition"));
+}
@@ -0,0 +1,507 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Semantic highlighting: classification of identifiers by their syntactic role in the AST,
+//! encoded as in the LSP semantic tokens protocol.
+//!
+
+use source_model::*;
+use source_lexer::file_offset;
+use structure_visitor::find_identifier;
+use parse_describe::ParsedSource;
+
+use syntex_syntax::visit::*;
+use syntex_syntax::ast::*;
+use syntex_syntax::codemap::{ Span, CodeMap };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SemanticTokenKind {
+    Module,
+    Type,
+    Struct,
+    Enum,
+    EnumMember,
+    Trait,
+    TypeParameter,
+    Field,
+    /// A const or static item.
+    Variable,
+    Function,
+    Method,
+    Macro,
+    Lifetime,
+    /// `self` or `Self`.
+    SelfKeyword,
+}
+
+/// The token kinds, in the order of their legend indexes.
+pub const SEMANTIC_TOKEN_KINDS : [SemanticTokenKind; 14] = [
+    SemanticTokenKind::Module,
+    SemanticTokenKind::Type,
+    SemanticTokenKind::Struct,
+    SemanticTokenKind::Enum,
+    SemanticTokenKind::EnumMember,
+    SemanticTokenKind::Trait,
+    SemanticTokenKind::TypeParameter,
+    SemanticTokenKind::Field,
+    SemanticTokenKind::Variable,
+    SemanticTokenKind::Function,
+    SemanticTokenKind::Method,
+    SemanticTokenKind::Macro,
+    SemanticTokenKind::Lifetime,
+    SemanticTokenKind::SelfKeyword,
+];
+
+impl SemanticTokenKind {
+    /// The LSP token type name.
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            SemanticTokenKind::Module => "namespace",
+            SemanticTokenKind::Type => "type",
+            SemanticTokenKind::Struct => "struct",
+            SemanticTokenKind::Enum => "enum",
+            SemanticTokenKind::EnumMember => "enumMember",
+            SemanticTokenKind::Trait => "interface",
+            SemanticTokenKind::TypeParameter => "typeParameter",
+            SemanticTokenKind::Field => "property",
+            SemanticTokenKind::Variable => "variable",
+            SemanticTokenKind::Function => "function",
+            SemanticTokenKind::Method => "method",
+            SemanticTokenKind::Macro => "macro",
+            SemanticTokenKind::Lifetime => "lifetime",
+            SemanticTokenKind::SelfKeyword => "selfKeyword",
+        }
+    }
+
+    /// The index of this kind in the legend.
+    pub fn legend_index(&self) -> u32 {
+        SEMANTIC_TOKEN_KINDS.iter().position(|kind| kind == self).unwrap() as u32
+    }
+}
+
+/// Token modifier: the token is the name of a declaration.
+pub const MODIFIER_DECLARATION : u32 = 1 << 0;
+/// Token modifier: the token is a constant.
+pub const MODIFIER_READONLY : u32 = 1 << 1;
+/// Token modifier: the token is a static.
+pub const MODIFIER_STATIC : u32 = 1 << 2;
+
+/// The modifier names, in the order of their bits.
+pub const SEMANTIC_TOKEN_MODIFIERS : [&'static str; 3] = ["declaration", "readonly", "static"];
+
+#[derive(Debug, Clone)]
+pub struct SemanticToken {
+    pub kind : SemanticTokenKind,
+    /// A set of `MODIFIER_` bits.
+    pub modifiers : u32,
+    pub sourcerange : SourceRange,
+}
+
+/// An edit of a semantic tokens data array: replace `delete_count` integers at `start` with `data`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemanticTokensEdit {
+    pub start : usize,
+    pub delete_count : usize,
+    pub data : Vec<u32>,
+}
+
+/// Classify the identifiers of given source by their syntactic role. The tokens are sorted,
+/// and do not overlap.
+pub fn semantic_tokens(parsed : &ParsedSource) -> Vec<SemanticToken> {
+    let mut tokens = vec![];
+
+    if parsed.is_parsed() {
+        let mut visitor = SemanticTokensVisitor {
+            codemap : &parsed.codemap, line_index : &parsed.line_index, tokens : vec![]
+        };
+        parsed.walk(&mut visitor);
+        tokens = visitor.tokens;
+    }
+
+    tokens.sort_by(|a, b| a.sourcerange.cmp(&b.sourcerange));
+    
+    // Drop the tokens found twice
+    let mut result : Vec<SemanticToken> = vec![];
+    for token in tokens {
+        if result.last().map_or(true, |last| last.sourcerange.start_pos != token.sourcerange.start_pos) {
+            result.push(token);
+        }
+    }
+    result
+}
+
+/// Encode given tokens as an LSP semantic tokens data array: 5 integers per token, with the line and
+/// start column relative to the previous token. Tokens spanning more than one line are skipped.
+pub fn encode_semantic_tokens(tokens : &[SemanticToken]) -> Vec<u32> {
+    let mut data = vec![];
+    let mut previous_line = 0;
+    let mut previous_col = 0;
+
+    for token in tokens {
+        let start = token.sourcerange.start_pos;
+        let end = token.sourcerange.end_pos;
+        if start.line != end.line {
+            continue;
+        }
+        let line = (start.line - 1) as u32;
+        let col = start.col.0 as u32;
+
+        data.push(line - previous_line);
+        data.push(if line == previous_line { col - previous_col } else { col });
+        data.push((end.col.0 - start.col.0) as u32);
+        data.push(token.kind.legend_index());
+        data.push(token.modifiers);
+
+        previous_line = line;
+        previous_col = col;
+    }
+    data
+}
+
+/// Compute the edits that turn the previous data array into the current one.
+/// This is a single edit replacing the tokens between the common prefix and suffix, or no edits at all.
+pub fn semantic_tokens_edits(previous : &[u32], current : &[u32]) -> Vec<SemanticTokensEdit> {
+    const TOKEN_LEN : usize = 5;
+
+    let previous_tokens : Vec<&[u32]> = previous.chunks(TOKEN_LEN).collect();
+    let current_tokens : Vec<&[u32]> = current.chunks(TOKEN_LEN).collect();
+
+    let prefix = previous_tokens.iter().zip(current_tokens.iter())
+        .take_while(|&(a, b)| a == b).count();
+    let suffix = previous_tokens[prefix..].iter().rev().zip(current_tokens[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b).count();
+
+    let delete_count = previous_tokens.len() - prefix - suffix;
+    let inserted = &current_tokens[prefix .. current_tokens.len() - suffix];
+    if delete_count == 0 && inserted.is_empty() {
+        return vec![];
+    }
+
+    vec![SemanticTokensEdit {
+        start : prefix * TOKEN_LEN,
+        delete_count : (previous.len() - suffix * TOKEN_LEN) - prefix * TOKEN_LEN,
+        data : inserted.iter().flat_map(|token| token.iter().cloned()).collect(),
+    }]
+}
+
+/// Parse a data array, as integers separated by whitespace or commas, optionally enclosed in brackets
+/// (so a JSON array is accepted).
+pub fn parse_semantic_tokens_data(text : &str) -> Result<Vec<u32>, String> {
+    text.trim().trim_left_matches('[').trim_right_matches(']')
+        .split(|ch : char| ch == ',' || ch.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<u32>().map_err(|_| format!("Invalid semantic tokens data value `{}`.", part)))
+        .collect()
+}
+
+struct SemanticTokensVisitor<'a> {
+    codemap : &'a CodeMap,
+    line_index : &'a LineIndex,
+    tokens : Vec<SemanticToken>,
+}
+
+impl<'a> SemanticTokensVisitor<'a> {
+
+    fn add(&mut self, start : usize, end : usize, kind : SemanticTokenKind, modifiers : u32) {
+        let sourcerange = self.line_index.range_at(start, end);
+        self.tokens.push(SemanticToken { kind : kind, modifiers : modifiers, sourcerange : sourcerange });
+    }
+
+    fn add_span(&mut self, span : Span, kind : SemanticTokenKind, modifiers : u32) {
+        let start = file_offset(self.codemap, span.lo);
+        let end = file_offset(self.codemap, span.hi);
+        self.add(start, end, kind, modifiers);
+    }
+
+    /// The offset of the first occurrence of given name in the source of given span, starting at `from`.
+    fn find_name(&self, name : &str, span : Span, from : usize) -> Option<usize> {
+        let start = file_offset(self.codemap, span.lo);
+        let end = file_offset(self.codemap, span.hi);
+        if from < start || from > end {
+            return None;
+        }
+        find_identifier(&self.line_index.source()[from..end], name).map(|ix| from + ix)
+    }
+
+    /// Add the first occurrence of the name of given ident in the source of given span,
+    /// which is the declaration of an item.
+    fn add_declaration(&mut self, ident : Ident, span : Span, kind : SemanticTokenKind, modifiers : u32) {
+        let name = ident.name.as_str();
+        let start = file_offset(self.codemap, span.lo);
+        if let Some(offset) = self.find_name(&name, span, start) {
+            self.add(offset, offset + name.len(), kind, modifiers | MODIFIER_DECLARATION);
+        }
+    }
+
+    /// The start offset of each segment of given path, if found.
+    fn segment_offsets(&self, path : &Path) -> Vec<Option<usize>> {
+        let mut from = file_offset(self.codemap, path.span.lo);
+        let mut offsets = vec![];
+
+        for segment in &path.segments {
+            let name = segment.identifier.name.as_str();
+            let offset = self.find_name(&name, path.span, from);
+            if let Some(offset) = offset {
+                from = offset + name.len();
+            }
+            offsets.push(offset);
+        }
+        offsets
+    }
+
+    /// Add the last segment of given path, which refers to an element of given kind.
+    fn add_path_target(&mut self, path : &Path, kind : SemanticTokenKind) {
+        if let (Some(segment), Some(&Some(offset))) = (path.segments.last(), self.segment_offsets(path).last()) {
+            let name = segment.identifier.name.as_str();
+            if !is_self_name(&name) {
+                self.add(offset, offset + name.len(), kind, 0);
+            }
+        }
+    }
+}
+
+fn is_self_name(name : &str) -> bool {
+    name == "self" || name == "Self"
+}
+
+impl<'a> Visitor for SemanticTokensVisitor<'a> {
+
+    fn visit_item(&mut self, item : &Item) {
+        let declaration = match item.node {
+            ItemKind::ExternCrate(_) | ItemKind::Mod(_) => Some((SemanticTokenKind::Module, 0)),
+            ItemKind::Static(..) => Some((SemanticTokenKind::Variable, MODIFIER_STATIC)),
+            ItemKind::Const(..) => Some((SemanticTokenKind::Variable, MODIFIER_READONLY)),
+            ItemKind::Fn(..) => Some((SemanticTokenKind::Function, 0)),
+            ItemKind::Ty(..) => Some((SemanticTokenKind::Type, 0)),
+            ItemKind::Enum(..) => Some((SemanticTokenKind::Enum, 0)),
+            ItemKind::Struct(..) | ItemKind::Union(..) => Some((SemanticTokenKind::Struct, 0)),
+            ItemKind::Trait(..) => Some((SemanticTokenKind::Trait, 0)),
+            // A `macro_rules!` definition has the macro name as ident
+            ItemKind::Mac(_) if !item.ident.name.as_str().is_empty() => Some((SemanticTokenKind::Macro, 0)),
+            ItemKind::Use(_) | ItemKind::ForeignMod(_) | ItemKind::DefaultImpl(..) | ItemKind::Impl(..) |
+            ItemKind::Mac(_) => None,
+        };
+
+        if let Some((kind, modifiers)) = declaration {
+            let span = match item.node {
+                // Skip the `macro_rules` name
+                ItemKind::Mac(ref mac) => Span { lo : mac.node.path.span.hi, .. item.span },
+                _ => item.span,
+            };
+            self.add_declaration(item.ident, span, kind, modifiers);
+        }
+        walk_item(self, item);
+    }
+
+    fn visit_foreign_item(&mut self, item : &ForeignItem) {
+        match item.node {
+            ForeignItemKind::Fn(..) => self.add_declaration(item.ident, item.span, SemanticTokenKind::Function, 0),
+            ForeignItemKind::Static(..) => {
+                self.add_declaration(item.ident, item.span, SemanticTokenKind::Variable, MODIFIER_STATIC)
+            }
+        }
+        walk_foreign_item(self, item);
+    }
+
+    fn visit_variant(&mut self, v : &Variant, g : &Generics, item_id : NodeId) {
+        self.add_declaration(v.node.name, v.span, SemanticTokenKind::EnumMember, 0);
+        walk_variant(self, v, g, item_id);
+    }
+
+    fn visit_struct_field(&mut self, sf : &StructField) {
+        if let Some(ident) = sf.ident {
+            self.add_declaration(ident, sf.span, SemanticTokenKind::Field, 0);
+        }
+        walk_struct_field(self, sf);
+    }
+
+    fn visit_trait_item(&mut self, ti : &TraitItem) {
+        match ti.node {
+            TraitItemKind::Const(..) => {
+                self.add_declaration(ti.ident, ti.span, SemanticTokenKind::Variable, MODIFIER_READONLY)
+            }
+            TraitItemKind::Method(..) => self.add_declaration(ti.ident, ti.span, SemanticTokenKind::Method, 0),
+            TraitItemKind::Type(..) => self.add_declaration(ti.ident, ti.span, SemanticTokenKind::Type, 0),
+            TraitItemKind::Macro(_) => { }
+        }
+        walk_trait_item(self, ti);
+    }
+
+    fn visit_impl_item(&mut self, ii : &ImplItem) {
+        match ii.node {
+            ImplItemKind::Const(..) => {
+                self.add_declaration(ii.ident, ii.span, SemanticTokenKind::Variable, MODIFIER_READONLY)
+            }
+            ImplItemKind::Method(..) => self.add_declaration(ii.ident, ii.span, SemanticTokenKind::Method, 0),
+            ImplItemKind::Type(_) => self.add_declaration(ii.ident, ii.span, SemanticTokenKind::Type, 0),
+            ImplItemKind::Macro(_) => { }
+        }
+        walk_impl_item(self, ii);
+    }
+
+    fn visit_generics(&mut self, generics : &Generics) {
+        for param in generics.ty_params.iter() {
+            self.add_declaration(param.ident, param.span, SemanticTokenKind::TypeParameter, 0);
+        }
+        walk_generics(self, generics);
+    }
+
+    fn visit_lifetime_def(&mut self, lifetime_def : &LifetimeDef) {
+        self.add_span(lifetime_def.lifetime.span, SemanticTokenKind::Lifetime, MODIFIER_DECLARATION);
+        for bound in &lifetime_def.bounds {
+            self.visit_lifetime(bound);
+        }
+    }
+
+    fn visit_lifetime(&mut self, lifetime : &Lifetime) {
+        self.add_span(lifetime.span, SemanticTokenKind::Lifetime, 0);
+    }
+
+    fn visit_ty(&mut self, ty : &Ty) {
+        if let TyKind::Path(_, ref path) = ty.node {
+            self.add_path_target(path, SemanticTokenKind::Type);
+        }
+        walk_ty(self, ty);
+    }
+
+    fn visit_trait_ref(&mut self, trait_ref : &TraitRef) {
+        self.add_path_target(&trait_ref.path, SemanticTokenKind::Trait);
+        walk_trait_ref(self, trait_ref);
+    }
+
+    fn visit_path(&mut self, path : &Path, _id : NodeId) {
+        let offsets = self.segment_offsets(path);
+        for (segment, offset) in path.segments.iter().zip(offsets) {
+            let name = segment.identifier.name.as_str();
+            if let Some(offset) = offset {
+                if is_self_name(&name) {
+                    self.add(offset, offset + name.len(), SemanticTokenKind::SelfKeyword, 0);
+                }
+            }
+        }
+        walk_path(self, path);
+    }
+
+    fn visit_pat(&mut self, pat : &Pat) {
+        if let PatKind::Ident(_, ref ident, _) = pat.node {
+            if is_self_name(&ident.node.name.as_str()) {
+                self.add_span(ident.span, SemanticTokenKind::SelfKeyword, 0);
+            }
+        }
+        walk_pat(self, pat);
+    }
+
+    fn visit_expr(&mut self, expr : &Expr) {
+        match expr.node {
+            ExprKind::Call(ref callee, _) => {
+                if let ExprKind::Path(None, ref path) = callee.node {
+                    self.add_path_target(path, SemanticTokenKind::Function);
+                }
+            }
+            ExprKind::MethodCall(ref ident, _, _) => {
+                self.add_span(ident.span, SemanticTokenKind::Method, 0);
+            }
+            ExprKind::Field(_, ref ident) => {
+                self.add_span(ident.span, SemanticTokenKind::Field, 0);
+            }
+            ExprKind::Struct(ref path, ref fields, _) => {
+                self.add_path_target(path, SemanticTokenKind::Struct);
+                for field in fields {
+                    self.add_span(field.ident.span, SemanticTokenKind::Field, 0);
+                }
+            }
+            _ => { }
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_mac(&mut self, mac : &Mac) {
+        // The macro arguments are not parsed, only the macro name is classified
+        self.add_path_target(&mac.node.path, SemanticTokenKind::Macro);
+    }
+}
+
+
+#[test]
+fn test_semantic_tokens() {
+    use parse_describe::analyse_source;
+    use self::SemanticTokenKind::*;
+
+    fn test_tokens(source : &str, expected : &[(SemanticTokenKind, u32, SourceRange)]) {
+        let (_, tokens) = analyse_source(source, |parsed| semantic_tokens(parsed));
+        let tokens : Vec<(SemanticTokenKind, u32, SourceRange)> = tokens.unwrap().into_iter()
+            .map(|token| (token.kind, token.modifiers, token.sourcerange)).collect();
+        assert_eq!(tokens, expected);
+    }
+    const DECL : u32 = MODIFIER_DECLARATION;
+
+    test_tokens("struct Foo<'a, T : Bar> { x : &'a T }", &[
+        (Struct, DECL, source_range(1, 7, 1, 10)),
+        (Lifetime, DECL, source_range(1, 11, 1, 13)),
+        (TypeParameter, DECL, source_range(1, 15, 1, 16)),
+        (Trait, 0, source_range(1, 19, 1, 22)),
+        (Field, DECL, source_range(1, 26, 1, 27)),
+        (Lifetime, 0, source_range(1, 31, 1, 33)),
+        (Type, 0, source_range(1, 34, 1, 35)),
+    ]);
+
+    test_tokens("impl Foo for S { fn f(&self) { self.g(T { a : 1 }); m!(); } }", &[
+        (Trait, 0, source_range(1, 5, 1, 8)),
+        (Type, 0, source_range(1, 13, 1, 14)),
+        (Method, DECL, source_range(1, 20, 1, 21)),
+        (SelfKeyword, 0, source_range(1, 23, 1, 27)),
+        (SelfKeyword, 0, source_range(1, 31, 1, 35)),
+        (Method, 0, source_range(1, 36, 1, 37)),
+        (Struct, 0, source_range(1, 38, 1, 39)),
+        (Field, 0, source_range(1, 42, 1, 43)),
+        (Macro, 0, source_range(1, 52, 1, 53)),
+    ]);
+
+    test_tokens("mod m { const C : u8 = f(); enum E { V } }\nmacro_rules! mac { () => () }", &[
+        (Module, DECL, source_range(1, 4, 1, 5)),
+        (Variable, DECL | MODIFIER_READONLY, source_range(1, 14, 1, 15)),
+        (Type, 0, source_range(1, 18, 1, 20)),
+        (Function, 0, source_range(1, 23, 1, 24)),
+        (Enum, DECL, source_range(1, 33, 1, 34)),
+        (EnumMember, DECL, source_range(1, 37, 1, 38)),
+        (Macro, 0, source_range(2, 0, 2, 11)),
+        (Macro, DECL, source_range(2, 13, 2, 16)),
+    ]);
+}
+
+#[test]
+fn test_encode_semantic_tokens() {
+    let tokens = vec![
+        SemanticToken { kind : SemanticTokenKind::Function, modifiers : 1, sourcerange : source_range(1, 3, 1, 6) },
+        SemanticToken { kind : SemanticTokenKind::Type, modifiers : 0, sourcerange : source_range(1, 10, 1, 13) },
+        SemanticToken { kind : SemanticTokenKind::Field, modifiers : 0, sourcerange : source_range(3, 2, 3, 3) },
+    ];
+    assert_eq!(encode_semantic_tokens(&tokens), vec![0, 3, 3, 9, 1,  0, 7, 3, 1, 0,  2, 2, 1, 7, 0]);
+}
+
+#[test]
+fn test_semantic_tokens_edits() {
+    assert_eq!(semantic_tokens_edits(&[1, 2, 3, 4, 5], &[1, 2, 3, 4, 5]), vec![]);
+    assert_eq!(semantic_tokens_edits(&[], &[1, 2, 3, 4, 5]),
+        vec![SemanticTokensEdit { start : 0, delete_count : 0, data : vec![1, 2, 3, 4, 5] }]);
+    assert_eq!(semantic_tokens_edits(&[1, 1, 1, 1, 1,  2, 2, 2, 2, 2,  3, 3, 3, 3, 3], &[1, 1, 1, 1, 1,  3, 3, 3, 3, 3]),
+        vec![SemanticTokensEdit { start : 5, delete_count : 5, data : vec![] }]);
+    assert_eq!(semantic_tokens_edits(&[1, 1, 1, 1, 1], &[1, 1, 1, 1, 1,  4, 4, 4, 4, 4]),
+        vec![SemanticTokensEdit { start : 5, delete_count : 0, data : vec![4, 4, 4, 4, 4] }]);
+
+    assert_eq!(parse_semantic_tokens_data("[1, 2,3]\n"), Ok(vec![1, 2, 3]));
+    assert_eq!(parse_semantic_tokens_data(" 4 5 "), Ok(vec![4, 5]));
+    assert!(parse_semantic_tokens_data("1 x").is_err());
+}
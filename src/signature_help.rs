@@ -0,0 +1,296 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Signature help: the parameters of the function called at a position, and the active one.
+//!
+
+use source_model::*;
+use source_lexer::{ lex_all_tokens, file_offset };
+use name_resolution::resolve_names;
+use hover::doc_comment;
+use public_api::normalize_whitespace;
+use fragment::ParseTarget;
+use parse_describe::{ ParsedSource, analyse_source_as, analyse_source_recovering, source_line_index };
+
+use syntex_syntax::visit::*;
+use syntex_syntax::ast::*;
+use syntex_syntax::parse::token;
+use syntex_syntax::parse::token::keywords;
+use syntex_syntax::parse::lexer::TokenAndSpan;
+use syntex_syntax::codemap::{ Span, CodeMap };
+use syntex_syntax::print::pprust;
+
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureHelp {
+    /// The name of the called function or method.
+    pub name : String,
+    /// The declaration of the callee without its body, such as `fn foo<T>(x : T, y : u32) -> T`.
+    pub signature : String,
+    /// The parameters of the callee, such as `x : T`. The `self` parameter of a method is not included if the
+    /// method is called with the method call syntax.
+    pub params : Vec<String>,
+    /// The index of the argument at the position (which can be past the last parameter).
+    pub active_param : usize,
+    pub docs : String,
+}
+
+/// The signature help of the call whose argument list encloses given position, if the callee is a function or
+/// method of the source. The call can be incomplete, such as `foo(1, ` being typed.
+pub fn signature_help(source : &str, target : ParseTarget, pos : &LineColumnPosition)
+    -> (Vec<SourceMessage>, Option<SignatureHelp>)
+{
+    let line_index = source_line_index(source);
+    let call = match line_index.offset_at(pos).and_then(|offset| find_call(&line_index, offset)) {
+        Some(call) => call,
+        None => return (analyse_source_as(source, target, |_| ()).0, None),
+    };
+
+    let (messages, help) = analyse_source_recovering(source, target, pos, move |parsed| {
+        callee_signature(parsed, &call)
+    });
+    (messages, help.and_then(|help| help))
+}
+
+/// A call whose argument list encloses a position.
+struct CallContext {
+    name : String,
+    /// The position of the callee name.
+    name_pos : LineColumnPosition,
+    is_method_call : bool,
+    active_param : usize,
+}
+
+/// Find the call whose argument list encloses given offset, from the tokens before it, which don't need to parse.
+fn find_call(line_index : &LineIndex, offset : usize) -> Option<CallContext> {
+    let codemap = Rc::new(CodeMap::new());
+    let tokens : Vec<TokenAndSpan> = lex_all_tokens(codemap.clone(), line_index.source()).into_iter()
+        .filter(|token| match token.tok {
+            token::Whitespace | token::Comment | token::DocComment(_) => false,
+            _ => file_offset(&codemap, token.sp.hi) <= offset,
+        })
+        .collect();
+
+    let mut depth = 0;
+    let mut commas = 0;
+    for ix in (0..tokens.len()).rev() {
+        match tokens[ix].tok {
+            token::CloseDelim(_) => depth += 1,
+            token::OpenDelim(_) if depth > 0 => depth -= 1,
+            token::OpenDelim(token::Paren) => {
+                return call_context(&codemap, line_index, &tokens[..ix], commas);
+            }
+            // Inside a block, an array or an index, or past the start of the statement
+            token::OpenDelim(_) => return None,
+            token::Semi if depth == 0 => return None,
+            token::Comma if depth == 0 => commas += 1,
+            _ => { }
+        }
+    }
+    None
+}
+
+/// The call of the argument list opened after given tokens.
+fn call_context(codemap : &CodeMap, line_index : &LineIndex, tokens : &[TokenAndSpan], active_param : usize)
+    -> Option<CallContext>
+{
+    let name_token = match tokens.last() {
+        Some(name_token) => name_token,
+        None => return None,
+    };
+    let name = match name_token.tok {
+        token::Ident(ident) if !name_token.tok.is_any_keyword() => ident.name.as_str().to_string(),
+        _ => return None,
+    };
+    let previous = if tokens.len() >= 2 { Some(&tokens[tokens.len() - 2].tok) } else { None };
+    // The parameter list of a declaration, rather than a call
+    if previous.map_or(false, |tok| tok.is_keyword(keywords::Fn) || tok.is_keyword(keywords::Struct)) {
+        return None;
+    }
+
+    Some(CallContext {
+        name : name,
+        name_pos : line_index.position_at(file_offset(codemap, name_token.sp.lo)),
+        is_method_call : previous == Some(&token::Dot),
+        active_param : active_param,
+    })
+}
+
+fn callee_signature(parsed : &ParsedSource, call : &CallContext) -> Option<SignatureHelp> {
+    let mut collector = Functions { codemap : &parsed.codemap, functions : vec![] };
+    parsed.walk(&mut collector);
+    let functions = collector.functions;
+
+    // The call is resolved if it was parsed. Otherwise the callee is the only function with that name.
+    let resolution = resolve_names(parsed);
+    let resolved = resolution.definition_at(&call.name_pos)
+        .and_then(|definition| parsed.line_index.range_offsets(&definition.decl_range))
+        .and_then(|offsets| functions.iter().find(|function| function.offsets == offsets));
+    let function = match resolved {
+        Some(function) => function,
+        None => {
+            let candidates : Vec<&Function> = functions.iter().filter(|function| {
+                function.name == call.name && (function.has_self || !call.is_method_call)
+            }).collect();
+            if candidates.len() != 1 {
+                return None;
+            }
+            candidates[0]
+        }
+    };
+
+    let skipped_params = if call.is_method_call && function.has_self { 1 } else { 0 };
+    Some(SignatureHelp {
+        name : function.name.clone(), signature : function.signature.clone(),
+        params : function.params[skipped_params..].to_vec(), active_param : call.active_param,
+        docs : function.docs.clone(),
+    })
+}
+
+/// A function or method of the source.
+struct Function {
+    name : String,
+    /// The offsets of the item span.
+    offsets : (usize, usize),
+    signature : String,
+    params : Vec<String>,
+    has_self : bool,
+    docs : String,
+}
+
+struct Functions<'a> {
+    codemap : &'a CodeMap,
+    functions : Vec<Function>,
+}
+
+impl<'a> Functions<'a> {
+
+    fn snippet(&self, span : Span) -> String {
+        self.codemap.span_to_snippet(span).map(|snippet| normalize_whitespace(&snippet)).unwrap_or(String::new())
+    }
+
+    fn add_function(&mut self, ident : Ident, generics : &Generics, decl : &FnDecl, span : Span,
+        attrs : &[Attribute])
+    {
+        let name = ident.name.as_str().to_string();
+        let params : Vec<String> = decl.inputs.iter().map(|arg| {
+            // The type of a `self` parameter has no span
+            if arg.ty.span.hi > arg.pat.span.hi {
+                self.snippet(Span { hi : arg.ty.span.hi, .. arg.pat.span })
+            } else {
+                self.snippet(arg.pat.span)
+            }
+        }).collect();
+        let output = match decl.output {
+            FunctionRetTy::Ty(ref ty) => format!(" -> {}", self.snippet(ty.span)),
+            _ => String::new(),
+        };
+        let signature = format!("fn {}{}({}){}{}", name, pprust::generics_to_string(generics), params.join(", "),
+            output, pprust::where_clause_to_string(&generics.where_clause));
+
+        self.functions.push(Function {
+            name : name, offsets : (file_offset(self.codemap, span.lo), file_offset(self.codemap, span.hi)),
+            signature : signature, params : params,
+            has_self : decl.inputs.first().map_or(false, |arg| arg.to_self().is_some()),
+            docs : doc_comment(attrs),
+        });
+    }
+
+}
+
+impl<'a> Visitor for Functions<'a> {
+
+    fn visit_item(&mut self, item : &Item) {
+        if let ItemKind::Fn(ref decl, _, _, _, ref generics, _) = item.node {
+            self.add_function(item.ident, generics, decl, item.span, &item.attrs);
+        }
+        walk_item(self, item);
+    }
+
+    fn visit_foreign_item(&mut self, foreign_item : &ForeignItem) {
+        if let ForeignItemKind::Fn(ref decl, ref generics) = foreign_item.node {
+            self.add_function(foreign_item.ident, generics, decl, foreign_item.span, &foreign_item.attrs);
+        }
+        walk_foreign_item(self, foreign_item);
+    }
+
+    fn visit_trait_item(&mut self, trait_item : &TraitItem) {
+        if let TraitItemKind::Method(ref sig, _) = trait_item.node {
+            self.add_function(trait_item.ident, &sig.generics, &sig.decl, trait_item.span, &trait_item.attrs);
+        }
+        walk_trait_item(self, trait_item);
+    }
+
+    fn visit_impl_item(&mut self, impl_item : &ImplItem) {
+        if let ImplItemKind::Method(ref sig, _) = impl_item.node {
+            self.add_function(impl_item.ident, &sig.generics, &sig.decl, impl_item.span, &impl_item.attrs);
+        }
+        walk_impl_item(self, impl_item);
+    }
+
+    fn visit_mac(&mut self, _mac : &Mac) {
+        // Macro invocations are not expanded, nothing to visit
+    }
+}
+
+
+#[test]
+fn test_signature_help() {
+
+    // The position is marked with `$`
+    fn help(source : &str) -> Option<(String, Vec<String>, usize)> {
+        let offset = source.find('$').unwrap();
+        let source = source.replace("$", "");
+        let pos = LineIndex::new(&source).position_at(offset);
+
+        signature_help(&source, ParseTarget::Crate, &pos).1
+            .map(|help| (help.signature, help.params, help.active_param))
+    }
+    fn signature(signature : &str, params : &[&str], active_param : usize) -> Option<(String, Vec<String>, usize)> {
+        Some((String::from(signature), params.iter().map(|param| param.to_string()).collect(), active_param))
+    }
+
+    // The source continues with the declaration of `add`
+    fn add_help(source : &str) -> Option<(String, Vec<String>, usize)> {
+        help(&(String::from(source) + "\nfn add(a : u32, b : u32) -> u32 { a + b }"))
+    }
+    fn add_signature(active_param : usize) -> Option<(String, Vec<String>, usize)> {
+        signature("fn add(a : u32, b : u32) -> u32", &["a : u32", "b : u32"], active_param)
+    }
+
+    assert_eq!(add_help("fn main() { add($1, 2); }"), add_signature(0));
+    assert_eq!(add_help("fn main() { add(1, 2$); }"), add_signature(1));
+    assert_eq!(add_help("fn main() { add(add(1, 2), $) }"), add_signature(1));
+    assert_eq!(add_help("fn main() { add((1, 2).0, [3, 4][0]$) }"), add_signature(1));
+
+    // Incomplete calls
+    assert_eq!(add_help("fn main() {\n  let x = add(1, $\n}"), add_signature(1));
+    assert_eq!(add_help("fn main() {\n  add(add(1, x.$\n}"), add_signature(1));
+    assert_eq!(add_help("fn main() {\n  add($\n}"), add_signature(0));
+
+    let source = "struct S;\nimpl S { fn get<T>(&self, i : usize) -> u32 { 0 } }\nfn main() { S.get(1, $) }";
+    assert_eq!(help(source), signature("fn get<T>(&self, i : usize) -> u32", &["i : usize"], 1));
+    let source = "struct S;\nimpl S { fn get(&self, i : usize) -> u32 { 0 } }\nfn main() { S::get(&S, $) }";
+    assert_eq!(help(source), signature("fn get(&self, i : usize) -> u32", &["&self", "i : usize"], 1));
+
+    // Not a call to a function of the source
+    assert_eq!(add_help("fn main() { let x = $1; }"), None);
+    assert_eq!(add_help("fn main() { add(1, 2); $}"), None);
+    assert_eq!(add_help("fn main() { add(1, || { $ }) }"), None);
+    assert_eq!(help("fn foo(a$ : u32) {}"), None);
+    assert_eq!(help("fn main() { foo($) }"), None);
+    assert_eq!(help("fn main() { println!($) }"), None);
+}
@@ -0,0 +1,127 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Decoding of raw source input into the text given to the parser.
+//!
+
+use source_model::*;
+
+use syntex_syntax::codemap::CharPos;
+
+use std::str;
+
+pub const UTF8_BOM : &'static [u8] = b"\xEF\xBB\xBF";
+
+/// The result of decoding raw input bytes.
+pub struct DecodedSource {
+    /// The decoded source text, without any BOM.
+    pub text : String,
+    /// The number of bytes (0 or 3) of the UTF-8 BOM that was stripped from the input.
+    pub bom_length : usize,
+    /// Warnings about the decoding, such as invalid UTF-8 sequences.
+    pub messages : Vec<SourceMessage>,
+}
+
+/// Decode the given bytes as UTF-8, stripping a leading BOM if present.
+/// Invalid sequences are replaced with U+FFFD, and a WARNING message is reported for each of them,
+/// with the offset of the offending byte in the original input.
+pub fn decode_source(bytes : &[u8]) -> DecodedSource {
+    let mut bom_length = 0;
+    let mut bytes = bytes;
+    if bytes.starts_with(UTF8_BOM) {
+        bom_length = UTF8_BOM.len();
+        bytes = &bytes[bom_length..];
+    }
+
+    let mut text = String::with_capacity(bytes.len());
+    // The text offset and the original input offset of each invalid sequence 
+    let mut invalid_sequences = vec![];
+
+    let mut remaining = bytes;
+    loop {
+        match str::from_utf8(remaining) {
+            Ok(valid) => {
+                text.push_str(valid);
+                break;
+            }
+            Err(error) => {
+                let valid_up_to = error.valid_up_to();
+                text.push_str(str::from_utf8(&remaining[..valid_up_to]).unwrap());
+
+                let input_offset = bom_length + (bytes.len() - remaining.len()) + valid_up_to;
+                invalid_sequences.push((text.len(), input_offset));
+                text.push('\u{FFFD}');
+
+                let invalid_len = match error.error_len() {
+                    Some(len) => len,
+                    None => remaining.len() - valid_up_to, // Truncated sequence at end of input
+                };
+                remaining = &remaining[valid_up_to + invalid_len ..];
+            }
+        }
+    }
+
+    let mut messages = vec![];
+    if !invalid_sequences.is_empty() {
+        let line_index = LineIndex::new(&text);
+        
+        for (text_offset, input_offset) in invalid_sequences {
+            let position = line_index.position_at(text_offset);
+            let message = format!("invalid UTF-8 byte at offset {}, replaced with U+FFFD", input_offset);
+            messages.push(SourceMessage {
+                severity : Severity::WARNING,
+                sourcerange : Some(SourceRange {
+                    start_pos : position,
+                    end_pos : LineColumnPosition { line : position.line, col : position.col + CharPos(1) },
+                }),
+                message : message,
+            });
+        }
+    }
+
+    DecodedSource { text : text, bom_length : bom_length, messages : messages }
+}
+
+
+#[test]
+fn test_decode_source() {
+
+    fn check_decode(bytes : &[u8], expected_text : &str, expected_msgs : &[(usize, usize, &str)]) {
+        let decoded = decode_source(bytes);
+        assert_eq!(decoded.text, expected_text);
+        assert_eq!(decoded.messages.len(), expected_msgs.len());
+        for (msg, &(line, col, text)) in decoded.messages.iter().zip(expected_msgs) {
+            let sr = msg.sourcerange.unwrap();
+            assert_eq!((sr.start_pos.line, sr.start_pos.col.0), (line, col));
+            assert_eq!((sr.end_pos.line, sr.end_pos.col.0), (line, col + 1));
+            assert_eq!(msg.message, text);
+        }
+    }
+
+    check_decode(b"", "", &[]);
+    check_decode(b"fn foo() {}", "fn foo() {}", &[]);
+    check_decode(b"\xEF\xBB\xBFfn foo() {}", "fn foo() {}", &[]);
+    assert_eq!(decode_source(b"\xEF\xBB\xBFfn").bom_length, 3);
+
+    check_decode(b"ab\xFFcd", "ab\u{FFFD}cd",
+        &[(1, 2, "invalid UTF-8 byte at offset 2, replaced with U+FFFD")]);
+    // Offsets are reported against the original input, positions against the decoded text:
+    check_decode(b"\xEF\xBB\xBF\xC3\xA9\nx\xFF\xFE", "\u{E9}\nx\u{FFFD}\u{FFFD}", &[
+        (2, 1, "invalid UTF-8 byte at offset 7, replaced with U+FFFD"),
+        (2, 2, "invalid UTF-8 byte at offset 8, replaced with U+FFFD"),
+    ]);
+    // Truncated multi-byte sequence at end of input
+    check_decode(b"a\xE2\x82", "a\u{FFFD}", &[(1, 1, "invalid UTF-8 byte at offset 1, replaced with U+FFFD")]);
+}
@@ -0,0 +1,114 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Lexing of a source into its full token stream, including whitespace and comments
+//! (which the parser discards).
+//!
+
+use syntex_syntax::parse::{ self, ParseSess };
+use syntex_syntax::parse::lexer::{ Reader, TokenAndSpan };
+use syntex_syntax::parse::token;
+use syntex_syntax::codemap::{ CodeMap, Pos };
+use syntex_errors::{ Handler, DiagnosticBuilder };
+use syntex_errors::emitter;
+
+use std::panic::{ self, AssertUnwindSafe };
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// An emitter that discards all messages.
+pub struct IgnoreMessagesEmitter;
+
+impl emitter::Emitter for IgnoreMessagesEmitter {
+    fn emit(&mut self, _db: &DiagnosticBuilder) {
+    }
+}
+
+/// Lex given source into all of its tokens, including whitespace and comment tokens (but not the EOF token).
+/// The source is added as a new file to given codemap, so token spans are relative to that file.
+///
+/// Lexer messages are discarded, since the parser reports them already.
+/// If the lexer fails fatally, the tokens lexed up to that point are returned.
+pub fn lex_all_tokens(codemap : Rc<CodeMap>, source : &str) -> Vec<TokenAndSpan> {
+    let handler = Handler::with_emitter(false, false, Box::new(IgnoreMessagesEmitter));
+    let sess = ParseSess::with_span_handler(handler, codemap);
+    let filemap = sess.codemap().new_filemap("_file_tokens_".to_string(), None, source.to_string());
+
+    let mut tokens = vec![];
+    {
+        let tokens = &mut tokens;
+        let sess = &sess;
+
+        // StringReader::new panics if the very first token is a fatal error
+        panic::catch_unwind(AssertUnwindSafe(move || {
+            let mut reader = parse::lexer::StringReader::new(&sess.span_diagnostic, filemap);
+
+            loop {
+                match reader.try_next_token() {
+                    Ok(TokenAndSpan { tok : token::Eof, .. }) => break,
+                    Ok(token) => tokens.push(token),
+                    Err(_) => {
+                        reader.emit_fatal_errors();
+                        break;
+                    }
+                }
+            }
+        })).ok();
+    }
+    tokens
+}
+
+/// The byte offset of given position, relative to the start of the file containing it.
+pub fn file_offset(codemap : &CodeMap, pos : ::syntex_syntax::codemap::BytePos) -> usize {
+    codemap.lookup_byte_offset(pos).pos.to_usize()
+}
+
+/// Map the end offset of each `}` token to the start offset of its matching `{`.
+pub fn matching_brace_starts(codemap : &CodeMap, tokens : &[TokenAndSpan]) -> HashMap<usize, usize> {
+    let mut brace_starts = HashMap::new();
+    let mut open_braces = vec![];
+
+    for token in tokens {
+        match token.tok {
+            token::OpenDelim(token::Brace) => {
+                open_braces.push(file_offset(codemap, token.sp.lo));
+            }
+            token::CloseDelim(token::Brace) => {
+                if let Some(start) = open_braces.pop() {
+                    brace_starts.insert(file_offset(codemap, token.sp.hi), start);
+                }
+            }
+            _ => { }
+        }
+    }
+    brace_starts
+}
+
+
+#[test]
+fn test_lex_all_tokens() {
+
+    fn lex_snippets(source : &str) -> Vec<String> {
+        let codemap = Rc::new(CodeMap::new());
+        lex_all_tokens(codemap.clone(), source).iter()
+            .map(|token| codemap.span_to_snippet(token.sp).unwrap())
+            .collect()
+    }
+
+    assert_eq!(lex_snippets(""), Vec::<String>::new());
+    assert_eq!(lex_snippets("fn /* c */ x // d\n"), vec!["fn", " ", "/* c */", " ", "x", " ", "// d", "\n"]);
+    // Lexing stops at a fatal error:
+    assert_eq!(lex_snippets("a /* unterminated"), vec!["a"]);
+}
@@ -12,19 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use ::syntex_syntax::codemap:: { Span, Loc, CodeMap, CharPos};
+use ::syntex_syntax::codemap:: { Span, Loc, CodeMap, CharPos, Pos};
 
+use std;
 
-#[derive(Debug, Clone, Copy)]
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct LineColumnPosition {
     /// The (1-based) line number
     pub line: usize,
-    /// The (0-based) column offset
+    /// The (0-based) column offset, in chars. 
+    /// (Ranges converted with `SourceRange::with_column_encoding` hold other units instead)
     pub col: CharPos,
     
 }
 
-#[derive(Debug, Clone, Copy)]
+/// A range of source positions. Ranges are ordered by start position, then by end position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SourceRange {
     pub start_pos : LineColumnPosition,
     pub end_pos : LineColumnPosition,
@@ -38,6 +42,18 @@ impl SourceRange {
         SourceRange::from_loc(startLoc, endLoc)
     }
     
+    /// Create a SourceRange for given span, with lines and columns computed by given line index.
+    /// Unlike `new`, this recognizes a lone `\r` as a line terminator too.
+    pub fn from_span(codemap : &CodeMap, line_index : &LineIndex, span : Span) -> SourceRange {
+        let start = codemap.lookup_byte_offset(span.lo).pos.to_usize();
+        let end = codemap.lookup_byte_offset(span.hi).pos.to_usize();
+        
+        SourceRange {
+            start_pos : line_index.position_at(start),
+            end_pos : line_index.position_at(end),
+        }
+    }
+    
     pub fn from_loc(startLoc : Loc, endLoc : Loc) -> SourceRange {
         SourceRange{ 
             start_pos : LineColumnPosition{ line: startLoc.line, col : startLoc.col }, 
@@ -47,6 +63,47 @@ impl SourceRange {
     
 }
 
+impl SourceRange {
+    
+    /// Whether given position is inside this range. The end position is included, 
+    /// so that a cursor placed right after the end of an element is still considered inside it.
+    pub fn contains(&self, pos : &LineColumnPosition) -> bool {
+        self.start_pos <= *pos && *pos <= self.end_pos
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.start_pos >= self.end_pos
+    }
+    
+    /// Whether given range is entirely inside this range.
+    pub fn contains_range(&self, other : &SourceRange) -> bool {
+        self.start_pos <= other.start_pos && other.end_pos <= self.end_pos
+    }
+    
+    /// Whether this range and given range have at least one position in common. 
+    /// (As with `contains`, end positions are included, so adjacent ranges intersect)
+    pub fn intersects(&self, other : &SourceRange) -> bool {
+        self.start_pos <= other.end_pos && other.start_pos <= self.end_pos
+    }
+    
+    /// The smallest range containing both this range and given range.
+    pub fn union(&self, other : &SourceRange) -> SourceRange {
+        SourceRange {
+            start_pos : std::cmp::min(self.start_pos, other.start_pos),
+            end_pos : std::cmp::max(self.end_pos, other.end_pos),
+        }
+    }
+    
+    /// Return this range with columns converted from chars to the units of given encoding.
+    pub fn with_column_encoding(&self, line_index : &LineIndex, encoding : ColumnEncoding) -> SourceRange {
+        SourceRange {
+            start_pos : line_index.with_column_encoding(&self.start_pos, encoding),
+            end_pos : line_index.with_column_encoding(&self.end_pos, encoding),
+        }
+    }
+    
+}
+
 pub fn source_range(start_line : usize, start_col : usize, end_line : usize, end_col : usize) -> SourceRange {
     SourceRange { 
         start_pos : LineColumnPosition { line : start_line, col : CharPos(start_col) },
@@ -55,10 +112,413 @@ pub fn source_range(start_line : usize, start_col : usize, end_line : usize, end
 }
 
 
+/* ----------------- Line index ----------------- */
+
+/// The line terminator convention used by a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEndingStyle {
+    /// The source has no line terminators.
+    None,
+    LF,
+    CRLF,
+    CR,
+    /// More than one kind of line terminator is used.
+    Mixed,
+}
+
+impl LineEndingStyle {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            LineEndingStyle::None => "NONE",
+            LineEndingStyle::LF => "LF",
+            LineEndingStyle::CRLF => "CRLF",
+            LineEndingStyle::CR => "CR",
+            LineEndingStyle::Mixed => "MIXED",
+        }
+    }
+}
+
+/// An index of the line start offsets of a source text, used to convert between byte offsets and 
+/// line/column positions. It retains a copy of the source, and needs no CodeMap.
+/// 
+/// `\r\n`, `\n`, and a lone `\r` are all recognized as line terminators (as in the Language Server
+/// Protocol), so a line's columns never include any part of its terminator. 
+/// Note this differs from CodeMap, which only breaks lines at `\n`.
+pub struct LineIndex {
+    source : String,
+    /// The byte offset at which each line starts. The first line starts at 0.
+    line_starts : Vec<usize>,
+    line_endings : LineEndingStyle,
+}
+
+impl LineIndex {
+    
+    pub fn new(source : &str) -> LineIndex {
+        let mut line_starts = vec![0];
+        let mut line_endings = LineEndingStyle::None;
+        
+        let bytes = source.as_bytes();
+        let mut ix = 0;
+        while ix < bytes.len() {
+            let ending = match bytes[ix] {
+                b'\r' if ix + 1 < bytes.len() && bytes[ix + 1] == b'\n' => {
+                    ix += 1;
+                    LineEndingStyle::CRLF
+                }
+                b'\r' => LineEndingStyle::CR,
+                b'\n' => LineEndingStyle::LF,
+                _ => { 
+                    ix += 1;
+                    continue;
+                }
+            };
+            ix += 1;
+            line_starts.push(ix);
+            
+            line_endings = match line_endings {
+                LineEndingStyle::None => ending,
+                previous if previous == ending => previous,
+                _ => LineEndingStyle::Mixed,
+            };
+        }
+        
+        LineIndex { source : String::from(source), line_starts : line_starts, line_endings : line_endings }
+    }
+    
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+    
+    pub fn line_endings(&self) -> LineEndingStyle {
+        self.line_endings
+    }
+    
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+    
+    /// The position of given byte offset. 
+    /// An offset inside a line terminator is positioned at the end of that line's content.
+    pub fn position_at(&self, offset : usize) -> LineColumnPosition {
+        let offset = std::cmp::min(offset, self.source.len());
+        
+        let line_ix = match self.line_starts.binary_search(&offset) {
+            Ok(line_ix) => line_ix,
+            Err(next_line_ix) => next_line_ix - 1,
+        };
+        let line_text = self.line_text(line_ix);
+        let line_offset = std::cmp::min(offset - self.line_starts[line_ix], line_text.len());
+        
+        let col = line_text.char_indices().take_while(|&(ix, _)| ix < line_offset).count();
+        LineColumnPosition { line : line_ix + 1, col : CharPos(col) }
+    }
+    
+    /// The byte offset of given position, or None if the position is not in the source. 
+    /// A column past the end of the line content is not in the source either.
+    pub fn offset_at(&self, pos : &LineColumnPosition) -> Option<usize> {
+        if pos.line == 0 || pos.line > self.line_count() {
+            return None;
+        }
+        let line_text = self.line_text(pos.line - 1);
+        
+        let col_offset = if pos.col.0 == line_text.chars().count() {
+            Some(line_text.len())
+        } else {
+            line_text.char_indices().nth(pos.col.0).map(|(ix, _)| ix)
+        };
+        col_offset.map(|col_offset| self.line_starts[pos.line - 1] + col_offset)
+    }
+    
+    /// The range between given byte offsets.
+    pub fn range_at(&self, start_offset : usize, end_offset : usize) -> SourceRange {
+        SourceRange { start_pos : self.position_at(start_offset), end_pos : self.position_at(end_offset) }
+    }
+    
+    /// The start and end byte offsets of given range, or None if the range is not in the source.
+    pub fn range_offsets(&self, range : &SourceRange) -> Option<(usize, usize)> {
+        match (self.offset_at(&range.start_pos), self.offset_at(&range.end_pos)) {
+            (Some(start), Some(end)) => Some((start, end)),
+            _ => None,
+        }
+    }
+    
+    /// The source text of given range, or None if the range is not in the source.
+    pub fn range_text(&self, range : &SourceRange) -> Option<&str> {
+        match self.range_offsets(range) {
+            Some((start, end)) if start <= end => Some(&self.source[start..end]),
+            _ => None,
+        }
+    }
+    
+    /// The text of the line with given (0-based) index, excluding the line terminator.
+    pub fn line_text(&self, line_ix : usize) -> &str {
+        let start = self.line_starts[line_ix];
+        let end = match self.line_starts.get(line_ix + 1) {
+            Some(&next_start) => next_start,
+            None => self.source.len(),
+        };
+        self.source[start..end].trim_right_matches(|ch| ch == '\n' || ch == '\r')
+    }
+    
+}
+
+/// The unit in which line columns are measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnEncoding {
+    /// Unicode scalar values (Rust `char`s). This is the default.
+    Chars,
+    /// UTF-16 code units, as used by the Language Server Protocol, Java and JavaScript editors.
+    Utf16,
+    /// UTF-8 bytes.
+    Bytes,
+}
+
+impl ColumnEncoding {
+    pub fn to_string(&self) -> &'static str {
+        match *self {
+            ColumnEncoding::Chars => "chars",
+            ColumnEncoding::Utf16 => "utf16",
+            ColumnEncoding::Bytes => "bytes",
+        }
+    }
+    
+    pub fn from_string(string : &str) -> Option<ColumnEncoding> {
+        match string {
+            "chars" => Some(ColumnEncoding::Chars),
+            "utf16" => Some(ColumnEncoding::Utf16),
+            "bytes" => Some(ColumnEncoding::Bytes),
+            _ => None,
+        }
+    }
+    
+    fn char_length(&self, ch : char) -> usize {
+        match *self {
+            ColumnEncoding::Chars => 1,
+            ColumnEncoding::Utf16 => ch.len_utf16(),
+            ColumnEncoding::Bytes => ch.len_utf8(),
+        }
+    }
+}
+
+impl LineIndex {
+    
+    /// Convert the char column of given position to the units of given encoding.
+    pub fn encode_column(&self, pos : &LineColumnPosition, encoding : ColumnEncoding) -> usize {
+        if encoding == ColumnEncoding::Chars || pos.line == 0 || pos.line > self.line_count() {
+            return pos.col.0;
+        }
+        
+        let line_text = self.line_text(pos.line - 1);
+        let in_line : usize = line_text.chars().take(pos.col.0).map(|ch| encoding.char_length(ch)).sum();
+        // Columns past the end of the line content are counted as 1 unit each
+        let past_line = pos.col.0.saturating_sub(line_text.chars().count());
+        in_line + past_line
+    }
+    
+    /// Convert a column in the units of given encoding to a char column. 
+    /// A column in the middle of a character is rounded down to the start of that character.
+    pub fn decode_column(&self, line : usize, col : usize, encoding : ColumnEncoding) -> CharPos {
+        if encoding == ColumnEncoding::Chars || line == 0 || line > self.line_count() {
+            return CharPos(col);
+        }
+        
+        let mut units = 0;
+        let mut chars = 0;
+        for ch in self.line_text(line - 1).chars() {
+            let ch_units = encoding.char_length(ch);
+            if units + ch_units > col {
+                return CharPos(chars);
+            }
+            units += ch_units;
+            chars += 1;
+        }
+        CharPos(chars + (col - units))
+    }
+    
+    pub fn with_column_encoding(&self, pos : &LineColumnPosition, encoding : ColumnEncoding) 
+        -> LineColumnPosition 
+    {
+        LineColumnPosition { line : pos.line, col : CharPos(self.encode_column(pos, encoding)) }
+    }
+    
+}
+
+#[test]
+fn test_SourceRange_operations() {
+    let range = source_range(2, 4, 5, 1);
+    
+    assert!(range.contains(&source_range(2, 4, 2, 4).start_pos));
+    assert!(range.contains(&source_range(3, 0, 3, 0).start_pos));
+    assert!(range.contains(&source_range(5, 1, 5, 1).start_pos));
+    assert!(!range.contains(&source_range(2, 3, 2, 3).start_pos));
+    assert!(!range.contains(&source_range(5, 2, 5, 2).start_pos));
+    
+    assert!(range.contains_range(&range));
+    assert!(range.contains_range(&source_range(2, 10, 4, 0)));
+    assert!(!range.contains_range(&source_range(1, 10, 4, 0)));
+    assert!(!range.contains_range(&source_range(2, 10, 6, 0)));
+    
+    assert!(range.intersects(&source_range(1, 0, 2, 4)));
+    assert!(range.intersects(&source_range(3, 0, 8, 0)));
+    assert!(range.intersects(&source_range(1, 0, 8, 0)));
+    assert!(!range.intersects(&source_range(1, 0, 2, 3)));
+    assert!(!range.intersects(&source_range(5, 2, 6, 0)));
+    
+    assert_eq!(range.union(&source_range(1, 0, 3, 0)), source_range(1, 0, 5, 1));
+    assert_eq!(range.union(&source_range(3, 0, 6, 0)), source_range(2, 4, 6, 0));
+    
+    assert!(source_range(1, 4, 1, 4).is_empty());
+    assert!(!range.is_empty());
+    
+    let mut ranges = vec![source_range(3, 0, 4, 0), source_range(1, 5, 9, 0), source_range(1, 5, 2, 0)];
+    ranges.sort();
+    assert_eq!(ranges, vec![source_range(1, 5, 2, 0), source_range(1, 5, 9, 0), source_range(3, 0, 4, 0)]);
+}
+
+#[test]
+fn test_LineIndex_offsets() {
+    let line_index = LineIndex::new("ab\r\n\u{E9}cd\rx");
+    
+    assert_eq!(line_index.offset_at(&source_range(1, 0, 1, 0).start_pos), Some(0));
+    assert_eq!(line_index.offset_at(&source_range(1, 2, 1, 2).start_pos), Some(2));
+    assert_eq!(line_index.offset_at(&source_range(1, 3, 1, 3).start_pos), None);
+    assert_eq!(line_index.offset_at(&source_range(2, 1, 2, 1).start_pos), Some(6));
+    assert_eq!(line_index.offset_at(&source_range(2, 3, 2, 3).start_pos), Some(8));
+    assert_eq!(line_index.offset_at(&source_range(3, 1, 3, 1).start_pos), Some(10));
+    assert_eq!(line_index.offset_at(&source_range(4, 0, 4, 0).start_pos), None);
+    
+    for offset in vec![0, 1, 2, 4, 6, 7, 8, 9, 10] {
+        assert_eq!(line_index.offset_at(&line_index.position_at(offset)), Some(offset));
+    }
+    
+    let range = line_index.range_at(6, 10);
+    assert_eq!(range, source_range(2, 1, 3, 1));
+    assert_eq!(line_index.range_offsets(&range), Some((6, 10)));
+    assert_eq!(line_index.range_text(&range), Some("cd\rx"));
+}
+
+#[test]
+fn test_ColumnEncoding() {
+    let line_index = LineIndex::new("a\u{E9}\u{1F600}b\r\nx");
+    
+    fn pos(line : usize, col : usize) -> LineColumnPosition {
+        LineColumnPosition { line : line, col : CharPos(col) }
+    }
+    
+    assert_eq!(line_index.encode_column(&pos(1, 4), ColumnEncoding::Chars), 4);
+    assert_eq!(line_index.encode_column(&pos(1, 2), ColumnEncoding::Utf16), 2);
+    assert_eq!(line_index.encode_column(&pos(1, 3), ColumnEncoding::Utf16), 4);
+    assert_eq!(line_index.encode_column(&pos(1, 4), ColumnEncoding::Utf16), 5);
+    assert_eq!(line_index.encode_column(&pos(1, 3), ColumnEncoding::Bytes), 7);
+    assert_eq!(line_index.encode_column(&pos(1, 4), ColumnEncoding::Bytes), 8);
+    assert_eq!(line_index.encode_column(&pos(2, 1), ColumnEncoding::Bytes), 1);
+    
+    assert_eq!(line_index.decode_column(1, 4, ColumnEncoding::Utf16), CharPos(3));
+    assert_eq!(line_index.decode_column(1, 3, ColumnEncoding::Utf16), CharPos(2)); // Middle of surrogate pair
+    assert_eq!(line_index.decode_column(1, 8, ColumnEncoding::Bytes), CharPos(4));
+    assert_eq!(line_index.decode_column(2, 1, ColumnEncoding::Bytes), CharPos(1));
+    
+    assert_eq!(ColumnEncoding::from_string("utf16"), Some(ColumnEncoding::Utf16));
+    assert_eq!(ColumnEncoding::from_string("utf8"), None);
+}
+
+/// Detect the line terminator convention used by given source.
+pub fn detect_line_endings(source : &str) -> LineEndingStyle {
+    LineIndex::new(source).line_endings()
+}
+
+#[test]
+fn test_LineIndex() {
+    
+    fn check_position(source : &str, offset : usize, line : usize, col : usize) {
+        let pos = LineIndex::new(source).position_at(offset);
+        assert_eq!((pos.line, pos.col.0), (line, col));
+    }
+    
+    assert_eq!(detect_line_endings(""), LineEndingStyle::None);
+    assert_eq!(detect_line_endings("abc"), LineEndingStyle::None);
+    assert_eq!(detect_line_endings("a\nb\n"), LineEndingStyle::LF);
+    assert_eq!(detect_line_endings("a\r\nb\r\n"), LineEndingStyle::CRLF);
+    assert_eq!(detect_line_endings("a\rb\r"), LineEndingStyle::CR);
+    assert_eq!(detect_line_endings("a\r\nb\n"), LineEndingStyle::Mixed);
+    assert_eq!(detect_line_endings("a\rb\r\n"), LineEndingStyle::Mixed);
+    
+    check_position("ab\ncd", 4, 2, 1);
+    check_position("ab\r\ncd", 2, 1, 2);
+    check_position("ab\r\ncd", 3, 1, 2); // Inside the terminator
+    check_position("ab\r\ncd", 4, 2, 0);
+    check_position("ab\r\ncd", 6, 2, 2);
+    check_position("ab\rcd", 3, 2, 0);
+    check_position("ab\r\rcd", 4, 3, 0);
+    check_position("a\r\nb\rc\nd", 7, 4, 0);
+    check_position("\u{E9}\r\n\u{E9}x", 6, 2, 1); 
+    check_position("ab", 10, 1, 2); 
+    
+    assert_eq!(LineIndex::new("ab\r\ncd\r").line_count(), 3);
+    assert_eq!(LineIndex::new("ab\r\ncd\r").line_text(0), "ab");
+}
+
+
+/// A position supplied by a client: either a (0-based) line and column, or a byte offset into the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourcePosition {
+    /// A line and a column in the selected column encoding.
+    LineColumn(usize, usize),
+    /// A UTF-8 byte offset, whatever the selected column encoding.
+    Offset(usize),
+}
+
+impl SourcePosition {
+    
+    /// Parse a position in the format `line:column` or `@offset`, as in the output format. 
+    pub fn parse(string : &str) -> Option<SourcePosition> {
+        if string.starts_with('@') {
+            return string[1..].parse().ok().map(SourcePosition::Offset);
+        }
+        
+        let mut parts = string.splitn(2, ':');
+        let line = parts.next().and_then(|line| line.parse().ok());
+        let col = parts.next().and_then(|col| col.parse().ok());
+        match (line, col) {
+            (Some(line), Some(col)) => Some(SourcePosition::LineColumn(line, col)),
+            _ => None,
+        }
+    }
+    
+    /// Resolve this position against the given source, with the column in given encoding (an offset is always 
+    /// in bytes).
+    pub fn resolve(&self, line_index : &LineIndex, encoding : ColumnEncoding) -> LineColumnPosition {
+        match *self {
+            SourcePosition::LineColumn(line, col) => {
+                LineColumnPosition { line : line + 1, col : line_index.decode_column(line + 1, col, encoding) }
+            }
+            SourcePosition::Offset(offset) => line_index.position_at(offset),
+        }
+    }
+    
+}
+
+#[test]
+fn test_SourcePosition() {
+    assert_eq!(SourcePosition::parse("5:10"), Some(SourcePosition::LineColumn(5, 10)));
+    assert_eq!(SourcePosition::parse("@250"), Some(SourcePosition::Offset(250)));
+    assert_eq!(SourcePosition::parse("5"), None);
+    assert_eq!(SourcePosition::parse("5:x"), None);
+    assert_eq!(SourcePosition::parse("@"), None);
+    
+    let line_index = LineIndex::new("ab\r\n\u{1F600}c");
+    let pos = SourcePosition::LineColumn(1, 2).resolve(&line_index, ColumnEncoding::Utf16);
+    assert_eq!((pos.line, pos.col), (2, CharPos(1)));
+    let pos = SourcePosition::Offset(8).resolve(&line_index, ColumnEncoding::Utf16);
+    assert_eq!((pos.line, pos.col), (2, CharPos(1)));
+}
+
+
 /* -----------------  ----------------- */
 
 //use ::util::core::*;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Severity {
     INFO,
     WARNING,
@@ -76,6 +536,7 @@ impl Severity {
 }
 
 
+#[derive(Debug, Clone)]
 pub struct SourceMessage {
     pub severity : Severity,
     pub sourcerange : Option<SourceRange>,
@@ -84,6 +545,7 @@ pub struct SourceMessage {
 
 /* ----------------- Model ----------------- */
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StructureElementKind {
     Var,
     Function,
@@ -119,13 +581,52 @@ impl StructureElementKind {
             StructureElementKind::TypeAlias => "TypeAlias",
         }
     }
+    
+    pub fn from_string(string : &str) -> Option<StructureElementKind> {
+        match string {
+            "Var" => Some(StructureElementKind::Var),
+            "Function" => Some(StructureElementKind::Function),
+            "Struct" => Some(StructureElementKind::Struct),
+            "Union" => Some(StructureElementKind::Union),
+            "Impl" => Some(StructureElementKind::Impl),
+            "Trait" => Some(StructureElementKind::Trait),
+            "Enum" => Some(StructureElementKind::Enum),
+            "EnumVariant" => Some(StructureElementKind::EnumVariant),
+            "ExternCrate" => Some(StructureElementKind::ExternCrate),
+            "Mod" => Some(StructureElementKind::Mod),
+            "Use" => Some(StructureElementKind::Use),
+            "Macro" => Some(StructureElementKind::MacroDef),
+            "TypeAlias" => Some(StructureElementKind::TypeAlias),
+            _ => None,
+        }
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct StructureElement {
     pub name: String,
     pub kind: StructureElementKind,
     pub sourcerange: SourceRange,
+    /// The range to select when navigating to the element, usually the element's name.
+    pub selection_range: SourceRange,
+    /// The declaration part of the element, up to its opening brace (or the whole element if it has no body).
+    pub header_range: SourceRange,
+    /// The braced body of the element, if any, including the braces.
+    pub body_range: Option<SourceRange>,
     
     pub type_desc: String,
+    /// The path of the element from the root module of the source, such as `bar::Foo::new`. 
+    /// The items of an inherent impl are in the path of the type, and other unnamed elements have a segment 
+    /// such as `<impl Display for Foo>` or `<extern "C">`.
+    pub qualified_path: String,
+    /// An ID of the element that doesn't depend on its position: the kind and the qualified path, where 
+    /// segments shared by previous siblings of the same kind are disambiguated with an index, 
+    /// such as `Impl bar::Foo#1`.
+    pub id: String,
+    /// A hash of the source text of the element, to detect modified elements.
+    pub content_hash: u64,
+    /// The file with the children of an out-of-line module, if it was loaded. The ranges of the children
+    /// are in that file.
+    pub source_file: Option<String>,
     pub children: Vec<StructureElement>,
 }
\ No newline at end of file
@@ -25,19 +25,31 @@ use source_model::*;
 use syntex_syntax::visit::*;
 use syntex_syntax::ast::*;
 use syntex_syntax::codemap:: { Span, CodeMap };
+use syntex_syntax::print::pprust;
+use source_lexer::file_offset;
+
+use std::collections::HashMap;
 
 pub struct StructureVisitor<'ps> {
     pub codemap : & 'ps CodeMap,
+    pub line_index : & 'ps LineIndex,
     pub parentIsStruct : bool,
     pub parentIsUnion : bool,
     pub elements : Vec<StructureElement>,
+    /// The qualified path segments of the elements enclosing the current one.
+    pub path : Vec<String>,
+    /// The ID path segments of the elements enclosing the current one: their path segments, disambiguated.
+    pub id_path : Vec<String>,
+    /// Maps the end offset of each `}` to the start offset of its matching `{`, used to find element bodies.
+    pub brace_starts : HashMap<usize, usize>,
 }
 
 impl<'ps> StructureVisitor<'ps> {
     
-    pub fn new(codemap : &'ps CodeMap) -> StructureVisitor<'ps> {
+    pub fn new(codemap : &'ps CodeMap, line_index : &'ps LineIndex) -> StructureVisitor<'ps> {
         StructureVisitor { 
-            codemap : codemap, parentIsStruct : false, parentIsUnion : false, elements : vec![]
+            codemap : codemap, line_index : line_index, parentIsStruct : false, parentIsUnion : false, elements : vec![],
+            path : vec![], id_path : vec![], brace_starts : HashMap::new(),
         }
     }
     
@@ -45,26 +57,70 @@ impl<'ps> StructureVisitor<'ps> {
         &mut self, 
         ident: &str, 
         kind: StructureElementKind, 
-        sourcerange: SourceRange,
+        span: Span,
+        name_span: Option<Span>,
         type_desc: String,  
+        path_segment: Option<String>,
         walkFn: FN
     ) -> Void
         where FN : Fn(&mut Self) 
     {
-
+        // The segment of the element in qualified paths is its name, unless given
+        let path_segment = path_segment.unwrap_or_else(|| String::from(ident));
+        let mut qualified_path = self.path.clone();
+        qualified_path.push(path_segment.clone());
+        let qualified_path = qualified_path.join("::");
+        
+        // The items of an extern block (the only Mod element without name) are in the enclosing module
+        let is_extern_block = kind == StructureElementKind::Mod && ident.is_empty();
+        
+        // Previous siblings with the same kind and path (such as two `impl Foo`) are disambiguated by index
+        let same_path_count = self.elements.iter()
+            .filter(|sibling| sibling.kind == kind && sibling.qualified_path == qualified_path)
+            .count();
+        let id_segment = if same_path_count == 0 {
+            path_segment.clone()
+        } else {
+            format!("{}#{}", path_segment, same_path_count)
+        };
+        let mut id_path = self.id_path.clone();
+        id_path.push(id_segment.clone());
+        let id = format!("{} {}", kind.to_string(), id_path.join("::"));
+        
         let mut siblings = vec![];
         std::mem::swap(&mut self.elements, &mut siblings);
         
+        if !is_extern_block {
+            self.path.push(path_segment);
+        }
+        self.id_path.push(id_segment);
         walkFn(self); // self.elements now has children
+        self.id_path.pop();
+        if !is_extern_block {
+            self.path.pop();
+        }
         
         std::mem::swap(&mut self.elements, &mut siblings);
         let children = siblings;
         
+        let (header_range, body_range) = self.header_and_body_ranges(kind, span);
+        let selection_range = match name_span {
+            Some(name_span) => self.source_range(name_span),
+            None => self.name_range(ident, &header_range),
+        };
+        
         let element = StructureElement{ 
             name: String::from(ident), 
             kind: kind, 
-            sourcerange: sourcerange ,
+            sourcerange: self.source_range(span),
+            selection_range: selection_range,
+            header_range: header_range,
+            body_range: body_range,
             type_desc : type_desc,
+            qualified_path : qualified_path,
+            id : id,
+            content_hash : self.content_hash(span),
+            source_file : None,
             children : children 
         };
         
@@ -76,15 +132,17 @@ impl<'ps> StructureVisitor<'ps> {
         &mut self, 
         ident: &str, 
         kind : StructureElementKind, 
-        sourceRange: SourceRange, 
+        span: Span, 
+        name_span: Option<Span>,
         type_desc: String,
+        path_segment: Option<String>,
         walkFn : FN)
         where FN : Fn(&mut Self) 
     {
         use std::io::Write;
         
         match 
-            self.write_element_do(ident, kind, sourceRange, type_desc, walkFn)
+            self.write_element_do(ident, kind, span, name_span, type_desc, path_segment, walkFn)
         {
             Ok(ok) => { ok } 
             Err(error) => { 
@@ -104,6 +162,20 @@ impl<'ps> StructureVisitor<'ps> {
         self.write_element(ident, kind, span, "".to_string(), walkFn);
     }
     
+    /// Write the element of an item, with given segment in qualified paths if it is not the item name.
+    pub fn write_item_element<FN>(
+        &mut self, 
+        item: &Item, 
+        kind : StructureElementKind, 
+        type_desc: String, 
+        path_segment: Option<String>, 
+        walkFn : FN
+    )
+        where FN : Fn(&mut Self)
+    {
+        self.write_element_handled(&*item.ident.name.as_str(), kind, item.span, None, type_desc, path_segment, walkFn)
+    }
+    
     pub fn write_element<FN>(
         &mut self, 
         ident: Ident, 
@@ -113,14 +185,65 @@ impl<'ps> StructureVisitor<'ps> {
     )
         where FN : Fn(&mut Self)
     {
-        self.write_element_handled(&*ident.name.as_str(), kind, SourceRange::new(self.codemap, span), 
-            type_desc, walkFn)
+        self.write_element_handled(&*ident.name.as_str(), kind, span, None, type_desc, None, walkFn)
+    }
+    
+    pub fn source_range(&self, span : Span) -> SourceRange {
+        SourceRange::from_span(self.codemap, self.line_index, span)
+    }
+    
+    /// The hash of the source text of given span.
+    pub fn content_hash(&self, span : Span) -> u64 {
+        let start = file_offset(self.codemap, span.lo);
+        let end = file_offset(self.codemap, span.hi);
+        fnv1a_hash(&self.line_index.source()[start..end])
+    }
+    
+    /// Split the element with given span into its header and its braced body (if it has one). 
+    /// The header excludes the whitespace before the opening brace.
+    pub fn header_and_body_ranges(&self, kind : StructureElementKind, span : Span) 
+        -> (SourceRange, Option<SourceRange>) 
+    {
+        let start = file_offset(self.codemap, span.lo);
+        let end = file_offset(self.codemap, span.hi);
+        
+        let may_have_body = match kind {
+            StructureElementKind::Var | StructureElementKind::TypeAlias | 
+            StructureElementKind::Use | StructureElementKind::ExternCrate => false,
+            _ => true,
+        };
+        
+        if may_have_body {
+            if let Some(&body_start) = self.brace_starts.get(&end) {
+                if body_start >= start {
+                    let header = &self.line_index.source()[start..body_start];
+                    let header_end = start + header.trim_right().len();
+                    
+                    return (self.line_index.range_at(start, header_end), 
+                        Some(self.line_index.range_at(body_start, end)));
+                }
+            }
+        }
+        (self.line_index.range_at(start, end), None)
+    }
+    
+    /// The range of the first occurrence of given name in given element header.
+    /// Defaults to the header range if the name is not found (for example, the name of an `impl`).
+    pub fn name_range(&self, name : &str, header_range : &SourceRange) -> SourceRange {
+        if let Some((header_start, header_end)) = self.line_index.range_offsets(header_range) {
+            let header = &self.line_index.source()[header_start..header_end];
+            
+            if let Some(ix) = find_identifier(header, name) {
+                let name_start = header_start + ix;
+                return self.line_index.range_at(name_start, name_start + name.len());
+            }
+        }
+        *header_range
     }
     
     /* -----------------  ----------------- */
     
     fn write_ItemUse(&mut self, vp : &ViewPath, span: Span) {
-        use syntex_syntax::print::pprust;
         use syntex_syntax::ast;
         use std::ops::Index;
         
@@ -167,7 +290,7 @@ impl<'ps> StructureVisitor<'ps> {
             }
         }
 
-        self.write_element_handled(&useSpec, kind, SourceRange::new(self.codemap, span), "".to_string(), 
+        self.write_element_handled(&useSpec, kind, span, Some(vp.span), "".to_string(), None,
             &|_ : &mut Self| { })
     }
     
@@ -244,6 +367,8 @@ impl<'v> Visitor for StructureVisitor<'v> {
         
         let kind;
         let mut type_desc = "".to_string();
+        // The segment of unnamed items in qualified paths
+        let mut path_segment = None;
         
         let noop_walkFn = &|_self : &mut Self| { };
         
@@ -279,8 +404,9 @@ impl<'v> Visitor for StructureVisitor<'v> {
             ItemKind::Mod(ref _module) => {
                 kind = StructureElementKind::Mod;
             }
-            ItemKind::ForeignMod(ref _foreign_module) => {
+            ItemKind::ForeignMod(ref foreign_module) => {
                 kind = StructureElementKind::Mod;
+                path_segment = Some(format!("<extern \"{}\">", foreign_module.abi.name()));
             }
             ItemKind::Ty(ref _typ, ref _type_parameters) => {
                 kind = StructureElementKind::TypeAlias;
@@ -288,11 +414,18 @@ impl<'v> Visitor for StructureVisitor<'v> {
             ItemKind::Enum(ref _enum_definition, ref _type_parameters) => {
                 kind = StructureElementKind::Enum;
             }
-            ItemKind::DefaultImpl(_, ref _trait_ref) => {
+            ItemKind::DefaultImpl(_, ref trait_ref) => {
                 kind = StructureElementKind::Impl;
+                path_segment = Some(format!("<impl {} for ..>", pprust::path_to_string(&trait_ref.path)));
             }
-            ItemKind::Impl(_, _, ref _type_parameters, ref _opt_trait_reference, ref _typ, ref _impl_items) => {
-                 kind = StructureElementKind::Impl;
+            ItemKind::Impl(_, _, ref _type_parameters, ref opt_trait_reference, ref typ, ref _impl_items) => {
+                kind = StructureElementKind::Impl;
+                // The items of an inherent impl are in the path of the type, as in `bar::Foo::new`
+                path_segment = Some(match *opt_trait_reference {
+                    Some(ref trait_ref) => format!("<impl {} for {}>", pprust::path_to_string(&trait_ref.path),
+                        pprust::ty_to_string(typ)),
+                    None => pprust::ty_to_string(typ),
+                });
             }
             ItemKind::Struct(ref _struct_definition, ref _generics) => {
                 // Go straight in
@@ -314,7 +447,7 @@ impl<'v> Visitor for StructureVisitor<'v> {
             }
         }
         
-        self.write_element(item.ident, kind, item.span, type_desc, walkFn);
+        self.write_item_element(item, kind, type_desc, path_segment, walkFn);
     }
     
     fn visit_enum_def(&mut self, enum_def: &EnumDef, generics: &Generics, nodeid: NodeId, _span: Span) {
@@ -521,6 +654,41 @@ impl<'v> Visitor for StructureVisitor<'v> {
 }
 
 
+/// Find the first occurrence of given name in text, as a whole identifier.
+pub fn find_identifier(text : &str, name : &str) -> Option<usize> {
+    fn is_ident_char(ch : char) -> bool {
+        ch.is_alphanumeric() || ch == '_'
+    }
+    
+    if name.is_empty() {
+        return None;
+    }
+    for (ix, _) in text.match_indices(name) {
+        let starts_word = text[..ix].chars().next_back().map_or(true, |ch| !is_ident_char(ch));
+        let ends_word = text[ix + name.len()..].chars().next().map_or(true, |ch| !is_ident_char(ch));
+        if starts_word && ends_word {
+            return Some(ix);
+        }
+    }
+    None
+}
+
+/// The 64-bit FNV-1a hash of given text. (Unlike the std hashers, this is stable across Rust versions)
+fn fnv1a_hash(text : &str) -> u64 {
+    let mut hash : u64 = 0xcbf29ce484222325;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[test]
+fn test_find_identifier() {
+    assert_eq!(find_identifier("fn foo_x<foo>", "foo"), Some(9));
+    assert_eq!(find_identifier("pub fn xfoo()", "foo"), None);
+    assert_eq!(find_identifier("struct A", ""), None);
+}
 
 #[test]
 fn tests_describe_structure() {
@@ -550,90 +718,90 @@ fn tests_describe_structure() {
         check_equal(result, expected);
     }
     
-    test_describe_structure("extern crate xx;", r#"ExternCrate { "xx" { 0:0 0:16 } {} "" {} }"#);
+    test_describe_structure("extern crate xx;", r#"ExternCrate { "xx" { 0:0 0:16 } { 0:13 0:15 } "" {} }"#);
     
-    test_describe_structure("const xx : u32 = 1;", r#"Var { "xx" { 0:0 0:19 } {} "u32" {} }"#);
+    test_describe_structure("const xx : u32 = 1;", r#"Var { "xx" { 0:0 0:19 } { 0:6 0:8 } "u32" {} }"#);
     
     
-    test_describe_structure("mod myMod   ;  ", r#"Mod { "myMod" { 0:0 0:13 } {} "" {} }"#);
-    test_describe_structure("mod myMod { }", r#"Mod { "myMod" { 0:0 0:13 } {} "" {} }"#);
+    test_describe_structure("mod myMod   ;  ", r#"Mod { "myMod" { 0:0 0:13 } { 0:4 0:9 } "" {} }"#);
+    test_describe_structure("mod myMod { }", r#"Mod { "myMod" { 0:0 0:13 } { 0:4 0:9 } "" { HEADER_RANGE { 0:0 0:9 } BODY_RANGE { 0:10 0:13 } } }"#);
     test_describe_structure("mod myMod { static xx : u32 = 2; }", 
-r#"Mod { "myMod" { 0:0 0:34 } {} "" {}
-  Var { "xx" { 0:12 0:32 } {} "u32" {} }
+r#"Mod { "myMod" { 0:0 0:34 } { 0:4 0:9 } "" { HEADER_RANGE { 0:0 0:9 } BODY_RANGE { 0:10 0:34 } }
+  Var { "xx" { 0:12 0:32 } { 0:19 0:21 } "u32" { QUALIFIED_PATH "myMod::xx" } }
 }"#
     );
     
-    test_describe_structure("fn xx() { }", r#"Function { "xx" { 0:0 0:11 } {} "()" {} }"#);
+    test_describe_structure("fn xx() { }", r#"Function { "xx" { 0:0 0:11 } { 0:3 0:5 } "()" { HEADER_RANGE { 0:0 0:7 } BODY_RANGE { 0:8 0:11 } } }"#);
     test_describe_structure("fn xx(a : &str) -> u32 { }", 
-        r#"Function { "xx" { 0:0 0:26 } {} "(&str) -> u32" {} }"#);
+        r#"Function { "xx" { 0:0 0:26 } { 0:3 0:5 } "(&str) -> u32" { HEADER_RANGE { 0:0 0:22 } BODY_RANGE { 0:23 0:26 } } }"#);
     test_describe_structure("fn xx(blah : Vec<u32>, x : &'v str) -> u32 { }", 
-        r#"Function { "xx" { 0:0 0:46 } {} "(Vec<u32>, &'v str) -> u32" {} }"#);
+        r#"Function { "xx" { 0:0 0:46 } { 0:3 0:5 } "(Vec<u32>, &'v str) -> u32" { HEADER_RANGE { 0:0 0:42 } BODY_RANGE { 0:43 0:46 } } }"#);
     // Test "deceiving" case
     test_describe_structure("fn xx(my_self : &str) -> u32 { }", 
-        r#"Function { "xx" { 0:0 0:32 } {} "(&str) -> u32" {} }"#);
+        r#"Function { "xx" { 0:0 0:32 } { 0:3 0:5 } "(&str) -> u32" { HEADER_RANGE { 0:0 0:28 } BODY_RANGE { 0:29 0:32 } } }"#);
     
-    test_describe_structure("type MyType = &u32<asd>;", r#"TypeAlias { "MyType" { 0:0 0:24 } {} "" {} }"#);
+    test_describe_structure("type MyType = &u32<asd>;", r#"TypeAlias { "MyType" { 0:0 0:24 } { 0:5 0:11 } "" {} }"#);
     
     test_describe_structure("enum MyEnum { Alpha, Beta, } ", 
-r#"Enum { "MyEnum" { 0:0 0:28 } {} "" {}
-  EnumVariant { "Alpha" { 0:14 0:19 } {} "" {} }
-  EnumVariant { "Beta" { 0:21 0:25 } {} "" {} }
+r#"Enum { "MyEnum" { 0:0 0:28 } { 0:5 0:11 } "" { HEADER_RANGE { 0:0 0:11 } BODY_RANGE { 0:12 0:28 } }
+  EnumVariant { "Alpha" { 0:14 0:19 } { 0:14 0:19 } "" { QUALIFIED_PATH "MyEnum::Alpha" } }
+  EnumVariant { "Beta" { 0:21 0:25 } { 0:21 0:25 } "" { QUALIFIED_PATH "MyEnum::Beta" } }
 }"#);
     test_describe_structure("enum MyEnum<T, U> { Alpha(T), Beta(U), } ", 
-r#"Enum { "MyEnum" { 0:0 0:40 } {} "" {}
-  EnumVariant { "Alpha" { 0:20 0:28 } {} "" {} }
-  EnumVariant { "Beta" { 0:30 0:37 } {} "" {} }
+r#"Enum { "MyEnum" { 0:0 0:40 } { 0:5 0:11 } "" { HEADER_RANGE { 0:0 0:17 } BODY_RANGE { 0:18 0:40 } }
+  EnumVariant { "Alpha" { 0:20 0:28 } { 0:20 0:25 } "" { QUALIFIED_PATH "MyEnum::Alpha" } }
+  EnumVariant { "Beta" { 0:30 0:37 } { 0:30 0:34 } "" { QUALIFIED_PATH "MyEnum::Beta" } }
 }"#);
     
     
     test_describe_structure("struct MyStruct ( u32, blah<sdf> ); ", 
-r#"Struct { "MyStruct" { 0:0 0:35 } {} "" {} }"#);
+r#"Struct { "MyStruct" { 0:0 0:35 } { 0:7 0:15 } "" {} }"#);
     test_describe_structure("struct MyStruct { foo : u32, } ", 
-r#"Struct { "MyStruct" { 0:0 0:30 } {} "" {}
-  Var { "foo" { 0:18 0:27 } {} "" {} }
+r#"Struct { "MyStruct" { 0:0 0:30 } { 0:7 0:15 } "" { HEADER_RANGE { 0:0 0:15 } BODY_RANGE { 0:16 0:30 } }
+  Var { "foo" { 0:18 0:27 } { 0:18 0:21 } "" { QUALIFIED_PATH "MyStruct::foo" } }
 }"#);
     test_describe_structure("union MyUnion { foo : u32, } ", 
-r#"Union { "MyUnion" { 0:0 0:28 } {} "" {}
-  Var { "foo" { 0:16 0:25 } {} "" {} }
+r#"Union { "MyUnion" { 0:0 0:28 } { 0:6 0:13 } "" { HEADER_RANGE { 0:0 0:13 } BODY_RANGE { 0:14 0:28 } }
+  Var { "foo" { 0:16 0:25 } { 0:16 0:19 } "" { QUALIFIED_PATH "MyUnion::foo" } }
 }"#);
     
-    test_describe_structure("trait MyTrait { } ", r#"Trait { "MyTrait" { 0:0 0:17 } {} "" {} }"#);
+    test_describe_structure("trait MyTrait { } ", r#"Trait { "MyTrait" { 0:0 0:17 } { 0:6 0:13 } "" { HEADER_RANGE { 0:0 0:13 } BODY_RANGE { 0:14 0:17 } } }"#);
     test_describe_structure("trait MyTrait : Foo { fn xxx(); } ", 
-r#"Trait { "MyTrait" { 0:0 0:33 } {} "" {}
-  Function { "xxx" { 0:22 0:31 } {} "()" {} }
+r#"Trait { "MyTrait" { 0:0 0:33 } { 0:6 0:13 } "" { HEADER_RANGE { 0:0 0:19 } BODY_RANGE { 0:20 0:33 } }
+  Function { "xxx" { 0:22 0:31 } { 0:25 0:28 } "()" { QUALIFIED_PATH "MyTrait::xxx" } }
 }"#);
     test_describe_structure("trait MyTrait : Foo { type N: fmt::Display; fn xxx(&self); const foo :u32 = 3; } ", 
-r#"Trait { "MyTrait" { 0:0 0:80 } {} "" {}
-  TypeAlias { "N" { 0:22 0:43 } {} "" {} }
-  Function { "xxx" { 0:44 0:58 } {} "(&self)" {} }
-  Var { "foo" { 0:59 0:78 } {} "" {} }
+r#"Trait { "MyTrait" { 0:0 0:80 } { 0:6 0:13 } "" { HEADER_RANGE { 0:0 0:19 } BODY_RANGE { 0:20 0:80 } }
+  TypeAlias { "N" { 0:22 0:43 } { 0:27 0:28 } "" { QUALIFIED_PATH "MyTrait::N" } }
+  Function { "xxx" { 0:44 0:58 } { 0:47 0:50 } "(&self)" { QUALIFIED_PATH "MyTrait::xxx" } }
+  Var { "foo" { 0:59 0:78 } { 0:65 0:68 } "" { QUALIFIED_PATH "MyTrait::foo" } }
 }"#);
     
     /* FIXME: review
-    test_describe_structure("impl MyType { } ", r#"Impl { "MyType" { 0:0 0:15 } {} "" {} }"#);
-    test_describe_structure("impl MyTrait for MyType { } ", r#"Impl { "MyType.MyTrait" { 0:0 0:27 } {} "" {} }"#);
+    test_describe_structure("impl MyType { } ", r#"Impl { "MyType" { 0:0 0:15 } { 0:5 0:11 } "" { HEADER_RANGE { 0:0 0:11 } BODY_RANGE { 0:12 0:15 } } }"#);
+    test_describe_structure("impl MyTrait for MyType { } ", r#"Impl { "MyType.MyTrait" { 0:0 0:27 } { 0:0 0:23 } "" { HEADER_RANGE { 0:0 0:23 } BODY_RANGE { 0:24 0:27 } } }"#);
     test_describe_structure("impl  MyTrait       { type N= fmt::Display; fn xx(){} const foo :u32 = 3; } ", 
-r#"Impl { "MyTrait" { 0:0 0:75 } {} "" {}
-  TypeAlias { "N" { 0:22 0:43 } {} "" {} }
-  Function { "xx" { 0:44 0:53 } {} "()" {} }
-  Var { "foo" { 0:54 0:73 } {} "" {} }
+r#"Impl { "MyTrait" { 0:0 0:75 } { 0:6 0:13 } "" { HEADER_RANGE { 0:0 0:13 } BODY_RANGE { 0:20 0:75 } }
+  TypeAlias { "N" { 0:22 0:43 } { 0:27 0:28 } "" {} }
+  Function { "xx" { 0:44 0:53 } { 0:47 0:49 } "()" { HEADER_RANGE { 0:44 0:51 } BODY_RANGE { 0:51 0:53 } } }
+  Var { "foo" { 0:54 0:73 } { 0:60 0:63 } "" {} }
 }"#);
     */
     
     
-    test_describe_structure("use blah;", r#"Use { "blah" { 0:0 0:9 } {} "" {} }"#);
-    test_describe_structure("use blah as foo;", r#"Use { "blah as foo" { 0:0 0:16 } {} "" {} }"#);
+    test_describe_structure("use blah;", r#"Use { "blah" { 0:0 0:9 } { 0:4 0:8 } "" {} }"#);
+    test_describe_structure("use blah as foo;", r#"Use { "blah as foo" { 0:0 0:16 } { 0:4 0:15 } "" {} }"#);
     // TODO: this is not printing the global path prefix, seems to be a limitation from libsyntax?
-    test_describe_structure("use ::blah::foo as myfoo;", r#"Use { "::blah::foo as myfoo" { 0:0 0:25 } {} "" {} }"#);
-    test_describe_structure("use ::blah::foo::*;", r#"Use { "::blah::foo::*" { 0:0 0:19 } {} "" {} }"#);
+    test_describe_structure("use ::blah::foo as myfoo;", r#"Use { "::blah::foo as myfoo" { 0:0 0:25 } { 0:4 0:24 } "" {} }"#);
+    test_describe_structure("use ::blah::foo::*;", r#"Use { "::blah::foo::*" { 0:0 0:19 } { 0:4 0:18 } "" {} }"#);
     test_describe_structure("use blah::foo:: { One as OtherOne, self as Two };", 
-        r#"Use { "blah::foo::{ One as OtherOne, self as Two, }" { 0:0 0:49 } {} "" {} }"#);
+        r#"Use { "blah::foo::{ One as OtherOne, self as Two, }" { 0:0 0:49 } { 0:4 0:48 } "" {} }"#);
     
     
     test_describe_structure("my_macro!(asf); ", "");
     
     // test: visit_mac! visit method 
-    test_describe_structure("fn foo() { my_macro!(asf); }", r#"Function { "foo" { 0:0 0:28 } {} "()" {} }"#);
+    test_describe_structure("fn foo() { my_macro!(asf); }", r#"Function { "foo" { 0:0 0:28 } { 0:3 0:6 } "()" { HEADER_RANGE { 0:0 0:8 } BODY_RANGE { 0:9 0:28 } } }"#);
     
     test_describe_structure("macro_rules! foo { (x => $e:expr) => (); }", r#""#);
     // TODO: macro definitions, unfortunately can't get that info easily from syntax_syntex
@@ -642,19 +810,86 @@ r#"Impl { "MyTrait" { 0:0 0:75 } {} "" {}
     
     // Test pub extern
     test_describe_structure("pub extern crate my_crate;", 
-        r#"ExternCrate { "my_crate" { 0:0 0:26 } {} "" {} }"#
+        r#"ExternCrate { "my_crate" { 0:0 0:26 } { 0:17 0:25 } "" {} }"#
     );
     
     test_describe_structure("extern { fn ext(p : u32); }", 
-r#"Mod { "" { 0:0 0:27 } {} "" {}
-  Function { "ext" { 0:9 0:25 } {} "" {} }
+r#"Mod { "" { 0:0 0:27 } { 0:0 0:6 } "" { HEADER_RANGE { 0:0 0:6 } BODY_RANGE { 0:7 0:27 } QUALIFIED_PATH "<extern \"C\">" }
+  Function { "ext" { 0:9 0:25 } { 0:12 0:15 } "" {} }
 }"#);
     test_describe_structure("extern { fn ext(p : u32); \n static extVar: u8; }", 
-r#"Mod { "" { 0:0 1:21 } {} "" {}
-  Function { "ext" { 0:9 0:25 } {} "" {} }
-  Var { "extVar" { 1:1 1:19 } {} "" {} }
+r#"Mod { "" { 0:0 1:21 } { 0:0 0:6 } "" { HEADER_RANGE { 0:0 0:6 } BODY_RANGE { 0:7 1:21 } QUALIFIED_PATH "<extern \"C\">" }
+  Function { "ext" { 0:9 0:25 } { 0:12 0:15 } "" {} }
+  Var { "extVar" { 1:1 1:19 } { 1:8 1:14 } "" {} }
 }"#);
     
+    // Test CRLF and lone CR line endings
+    test_describe_structure("fn xx() {\r\n}\r\nfn yy() { }", 
+r#"Function { "xx" { 0:0 1:1 } { 0:3 0:5 } "()" { HEADER_RANGE { 0:0 0:7 } BODY_RANGE { 0:8 1:1 } } }Function { "yy" { 2:0 2:11 } { 2:3 2:5 } "()" { HEADER_RANGE { 2:0 2:7 } BODY_RANGE { 2:8 2:11 } } }"#);
+    test_describe_structure("fn xx() {\r}\rfn yy() { }", 
+r#"Function { "xx" { 0:0 1:1 } { 0:3 0:5 } "()" { HEADER_RANGE { 0:0 0:7 } BODY_RANGE { 0:8 1:1 } } }Function { "yy" { 2:0 2:11 } { 2:3 2:5 } "()" { HEADER_RANGE { 2:0 2:7 } BODY_RANGE { 2:8 2:11 } } }"#);
+    
     // Test with a lexer error, 
 //    test_describe_structure("const xx : u32 = '", r#"Var { "xx" { 1 0 1 19 } {} {} {} }"#);    
+}
+
+#[test]
+fn test_qualified_paths() {
+    use parse_describe::parse_crate_with_messages;
+    
+    fn qualified_paths(elements : &[StructureElement], paths : &mut Vec<String>) {
+        for element in elements {
+            paths.push(element.qualified_path.clone());
+            qualified_paths(&element.children, paths);
+        }
+    }
+    
+    let source = "mod bar { struct Foo; impl Foo { fn new() {} } impl fmt::Display for Foo { fn fmt(&self) {} } }\n\
+        impl<T> Trait for Vec<T> { type Out = T; }\n\
+        extern { fn ext(); }\n\
+        fn outer() { fn inner() {} }";
+    let (_, elements) = parse_crate_with_messages(source);
+    let mut paths = vec![];
+    qualified_paths(&elements, &mut paths);
+    
+    assert_eq!(paths, vec![
+        "bar", "bar::Foo", "bar::Foo", "bar::Foo::new", 
+        "bar::<impl fmt::Display for Foo>", "bar::<impl fmt::Display for Foo>::fmt",
+        "<impl Trait for Vec<T>>", "<impl Trait for Vec<T>>::Out",
+        "<extern \"C\">", "ext",
+        "outer", "outer::inner",
+    ]);
+}
+
+#[test]
+fn test_element_ids() {
+    use parse_describe::parse_crate_with_messages;
+    
+    fn element_ids(elements : &[StructureElement], ids : &mut Vec<String>) {
+        for element in elements {
+            ids.push(element.id.clone());
+            element_ids(&element.children, ids);
+        }
+    }
+    
+    let source = "struct Foo;\nimpl Foo { fn a() {} }\nimpl Foo { fn a() {} }\nfn Foo() {}";
+    let (_, elements) = parse_crate_with_messages(source);
+    let mut ids = vec![];
+    element_ids(&elements, &mut ids);
+    assert_eq!(ids, vec![
+        "Struct Foo", "Impl Foo", "Function Foo::a", "Impl Foo#1", "Function Foo#1::a",
+        "Function Foo",
+    ]);
+    // Same text, same hash
+    assert_eq!(elements[1].children[0].content_hash, elements[2].children[0].content_hash);
+    assert!(elements[1].children[0].content_hash != elements[3].content_hash);
+    
+    // IDs and hashes don't depend on the position of the element
+    let (_, moved_elements) = parse_crate_with_messages(&format!("use std;\n\n{}", source));
+    assert_eq!(moved_elements[4].id, elements[3].id);
+    assert_eq!(moved_elements[4].content_hash, elements[3].content_hash);
+    
+    let (_, modified_elements) = parse_crate_with_messages(&source.replace("fn Foo() {}", "fn Foo() { 1; }"));
+    assert_eq!(modified_elements[3].id, elements[3].id);
+    assert!(modified_elements[3].content_hash != elements[3].content_hash);
 }
\ No newline at end of file
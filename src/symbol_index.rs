@@ -0,0 +1,500 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! An index of the structure elements of all the Rust files in a directory, for "go to symbol in workspace".
+//!
+
+use util::core::*;
+use source_model::*;
+use module_loader::read_source_file;
+use parse_describe::{ analyse_source, structure_elements, source_line_index };
+
+use std::collections::BTreeMap;
+use std::fs::{ self, File };
+use std::io::{ self, Read, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+/// The number of threads the files are indexed with.
+const INDEX_THREADS : usize = 4;
+
+const INDEX_FILE_HEADER : &'static str = "RUST_SYMBOL_INDEX 1";
+
+/// An indexed structure element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolEntry {
+    pub name : String,
+    pub kind : StructureElementKind,
+    /// The path of the module the element is in, such as `foo::bar`, derived from the file path and the
+    /// enclosing `mod` elements. Empty for the crate root.
+    pub module_path : String,
+    pub sourcerange : SourceRange,
+    pub selection_range : SourceRange,
+}
+
+/// The symbols of an indexed file.
+#[derive(Debug, Clone)]
+struct IndexedFile {
+    /// The modification time of the file when it was indexed, in nanoseconds since the Unix epoch.
+    modified : u64,
+    symbols : Vec<SymbolEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolQueryKind {
+    /// Names starting with the query.
+    Prefix,
+    /// Names containing the query.
+    Substring,
+    /// Names containing the characters of the query, in order.
+    Fuzzy,
+}
+
+impl SymbolQueryKind {
+    pub fn from_string(string : &str) -> Option<SymbolQueryKind> {
+        match string {
+            "prefix" => Some(SymbolQueryKind::Prefix),
+            "substring" => Some(SymbolQueryKind::Substring),
+            "fuzzy" => Some(SymbolQueryKind::Fuzzy),
+            _ => None,
+        }
+    }
+}
+
+/// A symbol matching a query, and the file it is in.
+#[derive(Debug, Clone)]
+pub struct SymbolMatch {
+    /// The file path, relative to the index root directory.
+    pub file : String,
+    pub symbol : SymbolEntry,
+}
+
+/// The symbols of the `.rs` files in a directory and its subdirectories. Files are identified by their path
+/// relative to the root directory, with `/` separators.
+#[derive(Debug, Clone)]
+pub struct SymbolIndex {
+    pub root : PathBuf,
+    files : BTreeMap<String, IndexedFile>,
+}
+
+impl SymbolIndex {
+
+    /// Index all the `.rs` files in given directory, in parallel.
+    pub fn build(root : &Path) -> io::Result<SymbolIndex> {
+        let mut index = SymbolIndex { root : root.to_path_buf(), files : BTreeMap::new() };
+        try!(index.refresh());
+        Ok(index)
+    }
+
+    /// Index the files that were added or modified since they were indexed, and remove the deleted ones.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let mut files = vec![];
+        try!(find_source_files(&self.root, "", &mut files));
+
+        let current_files : Vec<String> = files.iter().map(|&(ref file, _)| file.clone()).collect();
+        let deleted_files : Vec<String> = self.files.keys()
+            .filter(|file| !current_files.contains(*file))
+            .cloned().collect();
+        for file in deleted_files {
+            self.files.remove(&file);
+        }
+
+        let modified_files = files.into_iter()
+            .filter(|&(ref file, modified)| self.files.get(file).map_or(true, |indexed| indexed.modified != modified))
+            .map(|(file, _)| file)
+            .collect();
+        self.files.extend(index_files(&self.root, modified_files));
+        Ok(())
+    }
+
+    /// Index given file again, or remove it if it no longer exists.
+    pub fn update_file(&mut self, file : &str) {
+        match index_file(&self.root, file) {
+            Some(indexed) => { self.files.insert(file.to_string(), indexed); }
+            None => { self.files.remove(file); }
+        }
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The symbols of given file, if indexed.
+    pub fn file_symbols(&self, file : &str) -> Option<&[SymbolEntry]> {
+        self.files.get(file).map(|indexed| &indexed.symbols as &[SymbolEntry])
+    }
+
+    /// The symbols whose name matches given query, ignoring case. Fuzzy matches are sorted from best to worst,
+    /// the others by name length, then name.
+    pub fn query(&self, query : &str, kind : SymbolQueryKind) -> Vec<SymbolMatch> {
+        let query = query.to_lowercase();
+        let mut matches = vec![];
+
+        for (file, indexed) in &self.files {
+            for symbol in &indexed.symbols {
+                let name = symbol.name.to_lowercase();
+                let score = match kind {
+                    SymbolQueryKind::Prefix => if name.starts_with(&query) { Some(0) } else { None },
+                    SymbolQueryKind::Substring => if name.contains(&query) { Some(0) } else { None },
+                    SymbolQueryKind::Fuzzy => fuzzy_score(&symbol.name, &query),
+                };
+                if let Some(score) = score {
+                    matches.push((score, SymbolMatch { file : file.clone(), symbol : symbol.clone() }));
+                }
+            }
+        }
+
+        matches.sort_by(|&(score_a, ref match_a), &(score_b, ref match_b)| {
+            (-score_a, match_a.symbol.name.len(), &match_a.symbol.name)
+                .cmp(&(-score_b, match_b.symbol.name.len(), &match_b.symbol.name))
+        });
+        matches.into_iter().map(|(_, symbol_match)| symbol_match).collect()
+    }
+
+    /// Convert the ranges of given matches from chars to given column encoding, with the line index of the file
+    /// of each match. The files are read again: the ranges of a file that can't be read are not converted.
+    pub fn apply_column_encoding(&self, matches : &mut [SymbolMatch], encoding : ColumnEncoding) {
+        if encoding == ColumnEncoding::Chars {
+            return;
+        }
+
+        let mut line_indexes : BTreeMap<String, Option<LineIndex>> = BTreeMap::new();
+        for symbol_match in matches.iter_mut() {
+            let root = &self.root;
+            let line_index = line_indexes.entry(symbol_match.file.clone()).or_insert_with(|| {
+                read_source_file(&root.join(&symbol_match.file)).ok().map(|source| source_line_index(&source.text))
+            });
+            if let Some(ref line_index) = *line_index {
+                let symbol = &mut symbol_match.symbol;
+                symbol.sourcerange = symbol.sourcerange.with_column_encoding(line_index, encoding);
+                symbol.selection_range = symbol.selection_range.with_column_encoding(line_index, encoding);
+            }
+        }
+    }
+
+    /// Save this index to given file.
+    pub fn save(&self, path : &Path) -> io::Result<()> {
+        let mut text = String::new();
+        text.push_str(INDEX_FILE_HEADER);
+        text.push_str("\n");
+        text.push_str(&format!("ROOT\t{}\n", escape_field(&self.root.to_string_lossy())));
+
+        for (file, indexed) in &self.files {
+            text.push_str(&format!("FILE\t{}\t{}\n", indexed.modified, escape_field(file)));
+            for symbol in &indexed.symbols {
+                text.push_str(&format!("{}\t{}\t{}\t{}\t{}\n", symbol.kind.to_string(),
+                    range_to_field(&symbol.sourcerange), range_to_field(&symbol.selection_range),
+                    escape_field(&symbol.module_path), escape_field(&symbol.name)));
+            }
+        }
+
+        try!(File::create(path)).write_all(text.as_bytes())
+    }
+
+    /// Load an index saved with `save`.
+    pub fn load(path : &Path) -> GResult<SymbolIndex> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+
+        let mut lines = text.lines();
+        if lines.next() != Some(INDEX_FILE_HEADER) {
+            return Err("Not a symbol index file.".into());
+        }
+        let root = match lines.next().map(|line| line.split('\t').collect::<Vec<_>>()) {
+            Some(ref fields) if fields.len() == 2 && fields[0] == "ROOT" => PathBuf::from(unescape_field(fields[1])),
+            _ => return Err("Missing symbol index root.".into()),
+        };
+
+        let mut index = SymbolIndex { root : root, files : BTreeMap::new() };
+        let mut file = None;
+
+        for line in lines {
+            let fields : Vec<&str> = line.split('\t').collect();
+            if fields.len() == 3 && fields[0] == "FILE" {
+                let modified = try!(fields[1].parse::<u64>().map_err(|_| "Invalid symbol index file time."));
+                let name = unescape_field(fields[2]);
+                index.files.insert(name.clone(), IndexedFile { modified : modified, symbols : vec![] });
+                file = Some(name);
+                continue;
+            }
+
+            let symbol = match (fields.len(), &file) {
+                (5, &Some(_)) => {
+                    let kind = try!(StructureElementKind::from_string(fields[0]).ok_or("Invalid symbol kind."));
+                    SymbolEntry {
+                        name : unescape_field(fields[4]),
+                        kind : kind,
+                        module_path : unescape_field(fields[3]),
+                        sourcerange : try!(range_from_field(fields[1])),
+                        selection_range : try!(range_from_field(fields[2])),
+                    }
+                }
+                _ => return Err(format!("Invalid symbol index line: {}", line).into()),
+            };
+            if let Some(ref file) = file {
+                index.files.get_mut(file).unwrap().symbols.push(symbol);
+            }
+        }
+
+        Ok(index)
+    }
+
+}
+
+/// Add the `.rs` files in given directory (a path relative to given root) to `files`, recursively,
+/// with their modification time. Hidden directories and `target` directories are skipped.
+fn find_source_files(root : &Path, dir : &str, files : &mut Vec<(String, u64)>) -> io::Result<()> {
+    let mut entries = vec![];
+    for entry in try!(fs::read_dir(root.join(dir))) {
+        let entry = try!(entry);
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let file = if dir.is_empty() { name.clone() } else { format!("{}/{}", dir, name) };
+        entries.push((name, file, try!(entry.metadata())));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (name, file, metadata) in entries {
+        if metadata.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                try!(find_source_files(root, &file, files));
+            }
+        } else if name.ends_with(".rs") {
+            files.push((file, modification_time(&metadata)));
+        }
+    }
+    Ok(())
+}
+
+fn modification_time(metadata : &fs::Metadata) -> u64 {
+    metadata.modified().ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64)
+}
+
+/// Index given files, in parallel.
+fn index_files(root : &Path, files : Vec<String>) -> Vec<(String, IndexedFile)> {
+    let files = Arc::new(Mutex::new(files.into_iter()));
+
+    let workers : Vec<_> = (0..INDEX_THREADS).map(|_| {
+        let files = files.clone();
+        let root = root.to_path_buf();
+        thread::spawn(move || {
+            let mut indexed_files = vec![];
+            loop {
+                let file = match files.lock().unwrap().next() {
+                    Some(file) => file,
+                    None => break,
+                };
+                if let Some(indexed) = index_file(&root, &file) {
+                    indexed_files.push((file, indexed));
+                }
+            }
+            indexed_files
+        })
+    }).collect();
+
+    let mut indexed_files = vec![];
+    for worker in workers {
+        indexed_files.extend(worker.join().unwrap_or(vec![]));
+    }
+    indexed_files
+}
+
+/// Index given file, or None if it can't be read.
+fn index_file(root : &Path, file : &str) -> Option<IndexedFile> {
+    let path = root.join(file);
+    let modified = match fs::metadata(&path) {
+        Ok(ref metadata) if metadata.is_file() => modification_time(metadata),
+        _ => return None,
+    };
+
+    let source = match read_source_file(&path) {
+        Ok(source) => source.text,
+        Err(_) => return None,
+    };
+    let (_, elements) = analyse_source(&source, |parsed| structure_elements(parsed));
+
+    let mut symbols = vec![];
+    add_symbols(&elements.unwrap_or(vec![]), &file_module_path(file), &mut symbols);
+    Some(IndexedFile { modified : modified, symbols : symbols })
+}
+
+/// The module path of given file, assuming `src` is the crate root directory: `src/foo/bar.rs` and
+/// `src/foo/bar/mod.rs` are `foo::bar`, and `src/lib.rs` is the crate root.
+fn file_module_path(file : &str) -> Vec<String> {
+    let mut segments : Vec<&str> = file.split('/').collect();
+    if segments.len() > 1 && segments[0] == "src" {
+        segments.remove(0);
+    }
+    let file_name = segments.pop().unwrap_or("");
+    let file_stem = file_name.trim_right_matches(".rs");
+
+    let mut module_path : Vec<String> = segments.into_iter().map(String::from).collect();
+    if file_stem != "mod" && !(module_path.is_empty() && (file_stem == "lib" || file_stem == "main")) {
+        module_path.push(file_stem.to_string());
+    }
+    module_path
+}
+
+fn add_symbols(elements : &[StructureElement], module_path : &[String], symbols : &mut Vec<SymbolEntry>) {
+    for element in elements {
+        symbols.push(SymbolEntry {
+            name : element.name.clone(),
+            kind : element.kind,
+            module_path : module_path.join("::"),
+            sourcerange : element.sourcerange,
+            selection_range : element.selection_range,
+        });
+
+        if element.kind == StructureElementKind::Mod {
+            let mut child_module_path = module_path.to_vec();
+            child_module_path.push(element.name.clone());
+            add_symbols(&element.children, &child_module_path, symbols);
+        } else {
+            add_symbols(&element.children, module_path, symbols);
+        }
+    }
+}
+
+/// The score of given name for a fuzzy query (already in lowercase), or None if it doesn't match.
+/// Consecutive matches and matches at the start of a word score higher.
+fn fuzzy_score(name : &str, query : &str) -> Option<i32> {
+    let mut query_chars = query.chars().peekable();
+    let mut score = 0;
+    let mut previous_matched = false;
+    let mut previous_char : Option<char> = None;
+
+    for ch in name.chars() {
+        let matches = match query_chars.peek() {
+            Some(&query_char) => ch.to_lowercase().eq(query_char.to_lowercase()),
+            None => break,
+        };
+        if matches {
+            query_chars.next();
+            score += 1;
+            if previous_matched {
+                score += 5;
+            }
+            let word_start = match previous_char {
+                None => true,
+                Some(previous) => previous == '_' || (previous.is_lowercase() && ch.is_uppercase()),
+            };
+            if word_start {
+                score += 3;
+            }
+        }
+        previous_matched = matches;
+        previous_char = Some(ch);
+    }
+
+    if query_chars.peek().is_none() { Some(score) } else { None }
+}
+
+fn range_to_field(range : &SourceRange) -> String {
+    format!("{}:{}-{}:{}", range.start_pos.line, range.start_pos.col.0, range.end_pos.line, range.end_pos.col.0)
+}
+
+fn range_from_field(field : &str) -> GResult<SourceRange> {
+    let numbers : Vec<usize> = field.split(|ch| ch == ':' || ch == '-').filter_map(|number| number.parse().ok())
+        .collect();
+    if numbers.len() != 4 {
+        return Err(format!("Invalid symbol index range `{}`.", field).into());
+    }
+    Ok(source_range(numbers[0], numbers[1], numbers[2], numbers[3]))
+}
+
+fn escape_field(field : &str) -> String {
+    field.replace("\\", "\\\\").replace("\t", "\\t").replace("\n", "\\n").replace("\r", "\\r")
+}
+
+fn unescape_field(field : &str) -> String {
+    let mut result = String::new();
+    let mut chars = field.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => { }
+        }
+    }
+    result
+}
+
+
+#[test]
+fn test_symbol_index() {
+    use test_util::TempDir;
+
+    let dir = TempDir::new("rainicorn_symbol_index_tests");
+    dir.write_file("src/lib.rs", "mod foo;\nfn parse_source() {}\nmod inner { struct ParseError; }");
+    dir.write_file("src/foo/mod.rs", "fn parse() {}\nfn compare_parts() {}");
+    dir.write_file("target/ignored.rs", "fn parse_ignored() {}");
+
+    let mut index = SymbolIndex::build(&dir.path).unwrap();
+    assert_eq!(index.file_count(), 2);
+
+    fn names(matches : Vec<SymbolMatch>) -> Vec<String> {
+        matches.into_iter().map(|m| format!("{}:{}:{}", m.file, m.symbol.module_path, m.symbol.name)).collect()
+    }
+    assert_eq!(names(index.query("PARSE", SymbolQueryKind::Prefix)),
+        vec!["src/foo/mod.rs:foo:parse", "src/lib.rs:inner:ParseError", "src/lib.rs::parse_source"]);
+    assert_eq!(names(index.query("error", SymbolQueryKind::Substring)), vec!["src/lib.rs:inner:ParseError"]);
+    assert_eq!(names(index.query("pe", SymbolQueryKind::Fuzzy)),
+        vec!["src/foo/mod.rs:foo:parse", "src/lib.rs:inner:ParseError", "src/lib.rs::parse_source",
+            "src/foo/mod.rs:foo:compare_parts"]);
+    assert_eq!(index.query("xyz", SymbolQueryKind::Fuzzy).len(), 0);
+
+    // The ranges are converted with the line index of their own file
+    dir.write_file("src/foo/wide.rs", "/* \u{1F600} */ fn wide() {}");
+    index.update_file("src/foo/wide.rs");
+    let mut matches = index.query("PARSE_", SymbolQueryKind::Prefix);
+    matches.extend(index.query("wide", SymbolQueryKind::Prefix));
+    index.apply_column_encoding(&mut matches, ColumnEncoding::Utf16);
+    let ranges : Vec<SourceRange> = matches.iter().map(|m| m.symbol.selection_range).collect();
+    assert_eq!(ranges, vec![source_range(2, 3, 2, 15), source_range(1, 12, 1, 16)]);
+    fs::remove_file(dir.join("src/foo/wide.rs")).unwrap();
+    index.update_file("src/foo/wide.rs");
+
+    let symbols = index.file_symbols("src/foo/mod.rs").unwrap().to_vec();
+    assert_eq!(symbols[0].kind, StructureElementKind::Function);
+    assert_eq!(symbols[0].selection_range, source_range(1, 3, 1, 8));
+
+    let index_path = dir.join("symbols.index");
+    index.save(&index_path).unwrap();
+    let loaded = SymbolIndex::load(&index_path).unwrap();
+    assert_eq!(loaded.root, dir.path);
+    assert_eq!(loaded.file_symbols("src/foo/mod.rs").unwrap(), &symbols as &[SymbolEntry]);
+    assert_eq!(names(loaded.query("parse_s", SymbolQueryKind::Prefix)), vec!["src/lib.rs::parse_source"]);
+
+    dir.write_file("src/foo/mod.rs", "fn renamed() {}");
+    index.update_file("src/foo/mod.rs");
+    assert_eq!(names(index.query("renamed", SymbolQueryKind::Prefix)), vec!["src/foo/mod.rs:foo:renamed"]);
+
+    fs::remove_file(dir.join("src/foo/mod.rs")).unwrap();
+    index.refresh().unwrap();
+    assert_eq!(index.file_count(), 1);
+
+    assert_eq!(file_module_path("src/a/b.rs"), vec!["a", "b"]);
+    assert_eq!(file_module_path("src/main.rs"), Vec::<String>::new());
+    assert_eq!(unescape_field(&escape_field("a\tb\\n")), "a\tb\\n");
+}
@@ -0,0 +1,488 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! A generic dump of the full syntax tree, for debugging and introspection.
+//!
+
+use source_model::*;
+use parse_describe::ParsedSource;
+
+use syntex_syntax::visit::*;
+use syntex_syntax::ast::*;
+use syntex_syntax::codemap::{ Span, CodeMap };
+use syntex_syntax::print::pprust;
+
+/// A node of the syntax tree.
+#[derive(Debug, Clone)]
+pub struct SyntaxNode {
+    /// The node category and its variant, for example `Item.Fn` or `Expr.Call`.
+    pub kind : String,
+    pub sourcerange : SourceRange,
+    /// The key fields of the node, such as names, operators or literal values.
+    pub fields : Vec<(&'static str, String)>,
+    pub children : Vec<SyntaxNode>,
+}
+
+/// Build the syntax tree of given parsed source, with a `Crate` root node, or a `Fragment` root node 
+/// spanning the whole source if it was parsed as a fragment. Returns `None` if the source could not be parsed.
+pub fn syntax_tree(parsed : &ParsedSource) -> Option<SyntaxNode> {
+    let mut builder = SyntaxTreeBuilder { 
+        codemap : &parsed.codemap, line_index : &parsed.line_index, stack : vec![], root : None 
+    };
+    if let Some(ref krate) = parsed.krate {
+        builder.node("Crate".to_string(), krate.span, vec![], |builder| walk_crate(builder, krate));
+    } else if let Some(ref fragment) = parsed.fragment {
+        let sourcerange = parsed.line_index.range_at(0, parsed.source.len());
+        let fields = vec![("target", fragment.target().to_string().to_string())];
+        builder.node_at("Fragment".to_string(), sourcerange, fields, |builder| fragment.walk(builder));
+    }
+
+    builder.root
+}
+
+struct SyntaxTreeBuilder<'a> {
+    codemap : &'a CodeMap,
+    line_index : &'a LineIndex,
+    /// The nodes being built, from the root to the current node.
+    stack : Vec<SyntaxNode>,
+    root : Option<SyntaxNode>,
+}
+
+impl<'a> SyntaxTreeBuilder<'a> {
+
+    /// Add a node as a child of the current node. The children of the new node are added by `walk`.
+    fn node<FN>(&mut self, kind : String, span : Span, fields : Vec<(&'static str, String)>, walk : FN)
+        where FN : FnOnce(&mut Self)
+    {
+        let sourcerange = SourceRange::from_span(self.codemap, self.line_index, span);
+        self.node_at(kind, sourcerange, fields, walk)
+    }
+
+    fn node_at<FN>(&mut self, kind : String, sourcerange : SourceRange, fields : Vec<(&'static str, String)>, 
+        walk : FN)
+        where FN : FnOnce(&mut Self)
+    {
+        self.stack.push(SyntaxNode { kind : kind, sourcerange : sourcerange, fields : fields, children : vec![] });
+
+        walk(self);
+
+        let node = self.stack.pop().unwrap();
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.root = Some(node),
+        }
+    }
+
+    fn snippet(&self, span : Span) -> String {
+        self.codemap.span_to_snippet(span).unwrap_or(String::new())
+    }
+}
+
+fn name_field(ident : Ident) -> (&'static str, String) {
+    ("name", ident.name.as_str().to_string())
+}
+
+fn add_vis_field(fields : &mut Vec<(&'static str, String)>, vis : &Visibility) {
+    if let Some(vis) = visibility_to_string(vis) {
+        fields.push(("vis", vis));
+    }
+}
+
+/// The source text of given visibility, such as `pub(crate)`, or None if inherited (private).
+pub fn visibility_to_string(vis : &Visibility) -> Option<String> {
+    match *vis {
+        Visibility::Public => Some("pub".to_string()),
+        Visibility::Crate(_) => Some("pub(crate)".to_string()),
+        Visibility::Restricted { ref path, .. } => Some(format!("pub({})", pprust::path_to_string(path))),
+        Visibility::Inherited => None,
+    }
+}
+
+fn label_fields(label : &Option<SpannedIdent>) -> Vec<(&'static str, String)> {
+    match *label {
+        Some(ref label) => vec![("label", label.node.name.as_str().to_string())],
+        None => vec![],
+    }
+}
+
+fn item_kind_name(kind : &ItemKind) -> &'static str {
+    match *kind {
+        ItemKind::ExternCrate(..) => "Item.ExternCrate",
+        ItemKind::Use(..) => "Item.Use",
+        ItemKind::Static(..) => "Item.Static",
+        ItemKind::Const(..) => "Item.Const",
+        ItemKind::Fn(..) => "Item.Fn",
+        ItemKind::Mod(..) => "Item.Mod",
+        ItemKind::ForeignMod(..) => "Item.ForeignMod",
+        ItemKind::Ty(..) => "Item.Ty",
+        ItemKind::Enum(..) => "Item.Enum",
+        ItemKind::Struct(..) => "Item.Struct",
+        ItemKind::Union(..) => "Item.Union",
+        ItemKind::Trait(..) => "Item.Trait",
+        ItemKind::DefaultImpl(..) => "Item.DefaultImpl",
+        ItemKind::Impl(..) => "Item.Impl",
+        ItemKind::Mac(..) => "Item.Mac",
+    }
+}
+
+fn expr_kind_name(kind : &ExprKind) -> &'static str {
+    match *kind {
+        ExprKind::Box(..) => "Expr.Box",
+        ExprKind::InPlace(..) => "Expr.InPlace",
+        ExprKind::Vec(..) => "Expr.Vec",
+        ExprKind::Call(..) => "Expr.Call",
+        ExprKind::MethodCall(..) => "Expr.MethodCall",
+        ExprKind::Tup(..) => "Expr.Tup",
+        ExprKind::Binary(..) => "Expr.Binary",
+        ExprKind::Unary(..) => "Expr.Unary",
+        ExprKind::Lit(..) => "Expr.Lit",
+        ExprKind::Cast(..) => "Expr.Cast",
+        ExprKind::Type(..) => "Expr.Type",
+        ExprKind::If(..) => "Expr.If",
+        ExprKind::IfLet(..) => "Expr.IfLet",
+        ExprKind::While(..) => "Expr.While",
+        ExprKind::WhileLet(..) => "Expr.WhileLet",
+        ExprKind::ForLoop(..) => "Expr.ForLoop",
+        ExprKind::Loop(..) => "Expr.Loop",
+        ExprKind::Match(..) => "Expr.Match",
+        ExprKind::Closure(..) => "Expr.Closure",
+        ExprKind::Block(..) => "Expr.Block",
+        ExprKind::Assign(..) => "Expr.Assign",
+        ExprKind::AssignOp(..) => "Expr.AssignOp",
+        ExprKind::Field(..) => "Expr.Field",
+        ExprKind::TupField(..) => "Expr.TupField",
+        ExprKind::Index(..) => "Expr.Index",
+        ExprKind::Range(..) => "Expr.Range",
+        ExprKind::Path(..) => "Expr.Path",
+        ExprKind::AddrOf(..) => "Expr.AddrOf",
+        ExprKind::Break(..) => "Expr.Break",
+        ExprKind::Continue(..) => "Expr.Continue",
+        ExprKind::Ret(..) => "Expr.Ret",
+        ExprKind::InlineAsm(..) => "Expr.InlineAsm",
+        ExprKind::Mac(..) => "Expr.Mac",
+        ExprKind::Struct(..) => "Expr.Struct",
+        ExprKind::Repeat(..) => "Expr.Repeat",
+        ExprKind::Paren(..) => "Expr.Paren",
+        ExprKind::Try(..) => "Expr.Try",
+    }
+}
+
+fn ty_kind_name(kind : &TyKind) -> &'static str {
+    match *kind {
+        TyKind::Slice(..) => "Ty.Slice",
+        TyKind::Array(..) => "Ty.Array",
+        TyKind::Ptr(..) => "Ty.Ptr",
+        TyKind::Rptr(..) => "Ty.Rptr",
+        TyKind::BareFn(..) => "Ty.BareFn",
+        TyKind::Never => "Ty.Never",
+        TyKind::Tup(..) => "Ty.Tup",
+        TyKind::Path(..) => "Ty.Path",
+        TyKind::ObjectSum(..) => "Ty.ObjectSum",
+        TyKind::PolyTraitRef(..) => "Ty.PolyTraitRef",
+        TyKind::ImplTrait(..) => "Ty.ImplTrait",
+        TyKind::Paren(..) => "Ty.Paren",
+        TyKind::Typeof(..) => "Ty.Typeof",
+        TyKind::Infer => "Ty.Infer",
+        TyKind::ImplicitSelf => "Ty.ImplicitSelf",
+        TyKind::Mac(..) => "Ty.Mac",
+    }
+}
+
+fn pat_kind_name(kind : &PatKind) -> &'static str {
+    match *kind {
+        PatKind::Wild => "Pat.Wild",
+        PatKind::Ident(..) => "Pat.Ident",
+        PatKind::Struct(..) => "Pat.Struct",
+        PatKind::TupleStruct(..) => "Pat.TupleStruct",
+        PatKind::Path(..) => "Pat.Path",
+        PatKind::Tuple(..) => "Pat.Tuple",
+        PatKind::Box(..) => "Pat.Box",
+        PatKind::Ref(..) => "Pat.Ref",
+        PatKind::Lit(..) => "Pat.Lit",
+        PatKind::Range(..) => "Pat.Range",
+        PatKind::Slice(..) => "Pat.Slice",
+        PatKind::Mac(..) => "Pat.Mac",
+    }
+}
+
+fn stmt_kind_name(kind : &StmtKind) -> &'static str {
+    match *kind {
+        StmtKind::Local(..) => "Stmt.Local",
+        StmtKind::Item(..) => "Stmt.Item",
+        StmtKind::Expr(..) => "Stmt.Expr",
+        StmtKind::Semi(..) => "Stmt.Semi",
+        StmtKind::Mac(..) => "Stmt.Mac",
+    }
+}
+
+impl<'a> Visitor for SyntaxTreeBuilder<'a> {
+
+    fn visit_item(&mut self, item : &Item) {
+        let mut fields = vec![];
+        if !item.ident.name.as_str().is_empty() {
+            fields.push(name_field(item.ident));
+        }
+        add_vis_field(&mut fields, &item.vis);
+
+        self.node(item_kind_name(&item.node).to_string(), item.span, fields, |this| walk_item(this, item));
+    }
+
+    fn visit_foreign_item(&mut self, item : &ForeignItem) {
+        let kind = match item.node {
+            ForeignItemKind::Fn(..) => "ForeignItem.Fn",
+            ForeignItemKind::Static(..) => "ForeignItem.Static",
+        };
+        let mut fields = vec![name_field(item.ident)];
+        add_vis_field(&mut fields, &item.vis);
+
+        self.node(kind.to_string(), item.span, fields, |this| walk_foreign_item(this, item));
+    }
+
+    fn visit_trait_item(&mut self, ti : &TraitItem) {
+        let kind = match ti.node {
+            TraitItemKind::Const(..) => "TraitItem.Const",
+            TraitItemKind::Method(..) => "TraitItem.Method",
+            TraitItemKind::Type(..) => "TraitItem.Type",
+            TraitItemKind::Macro(..) => "TraitItem.Macro",
+        };
+        self.node(kind.to_string(), ti.span, vec![name_field(ti.ident)], |this| walk_trait_item(this, ti));
+    }
+
+    fn visit_impl_item(&mut self, ii : &ImplItem) {
+        let kind = match ii.node {
+            ImplItemKind::Const(..) => "ImplItem.Const",
+            ImplItemKind::Method(..) => "ImplItem.Method",
+            ImplItemKind::Type(..) => "ImplItem.Type",
+            ImplItemKind::Macro(..) => "ImplItem.Macro",
+        };
+        let mut fields = vec![name_field(ii.ident)];
+        add_vis_field(&mut fields, &ii.vis);
+
+        self.node(kind.to_string(), ii.span, fields, |this| walk_impl_item(this, ii));
+    }
+
+    fn visit_variant(&mut self, v : &Variant, g : &Generics, item_id : NodeId) {
+        self.node("Variant".to_string(), v.span, vec![name_field(v.node.name)],
+            |this| walk_variant(this, v, g, item_id));
+    }
+
+    fn visit_struct_field(&mut self, sf : &StructField) {
+        let mut fields = vec![];
+        if let Some(ident) = sf.ident {
+            fields.push(name_field(ident));
+        }
+        add_vis_field(&mut fields, &sf.vis);
+
+        self.node("StructField".to_string(), sf.span, fields, |this| walk_struct_field(this, sf));
+    }
+
+    fn visit_generics(&mut self, generics : &Generics) {
+        for param in generics.ty_params.iter() {
+            self.node("TyParam".to_string(), param.span, vec![name_field(param.ident)], |this| {
+                for bound in param.bounds.iter() {
+                    this.visit_ty_param_bound(bound);
+                }
+                if let Some(ref default) = param.default {
+                    this.visit_ty(default);
+                }
+            });
+        }
+        for lifetime_def in &generics.lifetimes {
+            self.visit_lifetime_def(lifetime_def);
+        }
+        for predicate in &generics.where_clause.predicates {
+            let span = match *predicate {
+                WherePredicate::BoundPredicate(ref predicate) => predicate.span,
+                WherePredicate::RegionPredicate(ref predicate) => predicate.span,
+                WherePredicate::EqPredicate(ref predicate) => predicate.span,
+            };
+            self.node("WherePredicate".to_string(), span, vec![], |this| {
+                match *predicate {
+                    WherePredicate::BoundPredicate(ref predicate) => {
+                        this.visit_ty(&predicate.bounded_ty);
+                        for bound in predicate.bounds.iter() {
+                            this.visit_ty_param_bound(bound);
+                        }
+                        for lifetime_def in &predicate.bound_lifetimes {
+                            this.visit_lifetime_def(lifetime_def);
+                        }
+                    }
+                    WherePredicate::RegionPredicate(ref predicate) => {
+                        this.visit_lifetime(&predicate.lifetime);
+                        for bound in &predicate.bounds {
+                            this.visit_lifetime(bound);
+                        }
+                    }
+                    WherePredicate::EqPredicate(ref predicate) => {
+                        this.visit_path(&predicate.path, predicate.id);
+                        this.visit_ty(&predicate.ty);
+                    }
+                }
+            });
+        }
+    }
+
+    fn visit_lifetime(&mut self, lifetime : &Lifetime) {
+        self.node("Lifetime".to_string(), lifetime.span, vec![("name", lifetime.name.as_str().to_string())],
+            |_| { });
+    }
+
+    fn visit_block(&mut self, block : &Block) {
+        self.node("Block".to_string(), block.span, vec![], |this| walk_block(this, block));
+    }
+
+    fn visit_stmt(&mut self, stmt : &Stmt) {
+        self.node(stmt_kind_name(&stmt.node).to_string(), stmt.span, vec![], |this| walk_stmt(this, stmt));
+    }
+
+    fn visit_arm(&mut self, arm : &Arm) {
+        let span = Span { lo : arm.pats[0].span.lo, .. arm.body.span };
+        self.node("Arm".to_string(), span, vec![], |this| walk_arm(this, arm));
+    }
+
+    fn visit_pat(&mut self, pat : &Pat) {
+        let mut fields = vec![];
+        match pat.node {
+            PatKind::Ident(binding_mode, ref ident, _) => {
+                fields.push(("name", ident.node.name.as_str().to_string()));
+                let (binding, mutability) = match binding_mode {
+                    BindingMode::ByRef(mutability) => ("ref", mutability),
+                    BindingMode::ByValue(mutability) => ("value", mutability),
+                };
+                fields.push(("binding", binding.to_string()));
+                if mutability == Mutability::Mutable {
+                    fields.push(("mutable", "true".to_string()));
+                }
+            }
+            PatKind::Lit(ref expr) => {
+                fields.push(("value", self.snippet(expr.span)));
+            }
+            _ => { }
+        }
+        self.node(pat_kind_name(&pat.node).to_string(), pat.span, fields, |this| walk_pat(this, pat));
+    }
+
+    fn visit_expr(&mut self, expr : &Expr) {
+        let fields = match expr.node {
+            ExprKind::Lit(ref lit) => vec![("value", self.snippet(lit.span))],
+            ExprKind::Binary(op, _, _) | ExprKind::AssignOp(op, _, _) => vec![("op", op.node.to_string().to_string())],
+            ExprKind::Unary(op, _) => vec![("op", UnOp::to_string(op).to_string())],
+            ExprKind::MethodCall(ref ident, _, _) => vec![("method", ident.node.name.as_str().to_string())],
+            ExprKind::Field(_, ref ident) => vec![("field", ident.node.name.as_str().to_string())],
+            ExprKind::TupField(_, ref index) => vec![("field", index.node.to_string())],
+            ExprKind::While(_, _, ref label) | ExprKind::WhileLet(_, _, _, ref label) |
+            ExprKind::ForLoop(_, _, _, ref label) | ExprKind::Loop(_, ref label) |
+            ExprKind::Break(ref label) | ExprKind::Continue(ref label) => label_fields(label),
+            _ => vec![],
+        };
+        self.node(expr_kind_name(&expr.node).to_string(), expr.span, fields, |this| walk_expr(this, expr));
+    }
+
+    fn visit_ty(&mut self, ty : &Ty) {
+        self.node(ty_kind_name(&ty.node).to_string(), ty.span, vec![], |this| walk_ty(this, ty));
+    }
+
+    fn visit_path(&mut self, path : &Path, _id : NodeId) {
+        self.node("Path".to_string(), path.span, vec![("path", pprust::path_to_string(path))],
+            |this| walk_path(this, path));
+    }
+
+    fn visit_mac(&mut self, mac : &Mac) {
+        // The macro arguments are not parsed
+        self.node("Mac".to_string(), mac.span, vec![("path", pprust::path_to_string(&mac.node.path))], |_| { });
+    }
+
+    fn visit_attribute(&mut self, attr : &Attribute) {
+        let text = self.snippet(attr.span);
+        self.node("Attribute".to_string(), attr.span, vec![("text", text)], |_| { });
+    }
+}
+
+
+#[test]
+fn test_syntax_tree() {
+    use parse_describe::{ analyse_source, analyse_source_as };
+    use fragment::ParseTarget;
+
+    fn describe_tree(node : &SyntaxNode, level : usize, out : &mut String) {
+        for _ in 0..level {
+            out.push_str("  ");
+        }
+        out.push_str(&node.kind);
+        for &(name, ref value) in &node.fields {
+            out.push_str(&format!(" {}={}", name, value));
+        }
+        out.push_str("\n");
+        for child in &node.children {
+            describe_tree(child, level + 1, out);
+        }
+    }
+
+    fn test_tree(source : &str, expected : &str) {
+        let (_, tree) = analyse_source(source, |parsed| syntax_tree(parsed));
+        let mut result = String::new();
+        describe_tree(&tree.unwrap().unwrap(), 0, &mut result);
+        assert_eq!(result.trim(), expected.trim());
+    }
+
+    test_tree("pub fn foo(x : &u8) -> u8 { let mut y = *x + 1; y }", r#"
+Crate
+  Item.Fn name=foo vis=pub
+    Pat.Ident name=x binding=value
+    Ty.Rptr
+      Ty.Path
+        Path path=u8
+    Ty.Path
+      Path path=u8
+    Block
+      Stmt.Local
+        Pat.Ident name=y binding=value mutable=true
+        Expr.Binary op=+
+          Expr.Unary op=*
+            Expr.Path
+              Path path=x
+          Expr.Lit value=1
+      Stmt.Expr
+        Expr.Path
+          Path path=y
+"#);
+
+    test_tree("#[derive(Debug)]\nstruct S<T> where T : Copy { a : T }", r#"
+Crate
+  Item.Struct name=S
+    TyParam name=T
+    WherePredicate
+      Ty.Path
+        Path path=T
+      Path path=Copy
+    StructField name=a
+      Ty.Path
+        Path path=T
+    Attribute text=#[derive(Debug)]
+"#);
+
+    let (_, tree) = analyse_source("fn", |parsed| syntax_tree(parsed));
+    assert!(tree.unwrap().is_none());
+
+    let (_, tree) = analyse_source_as("a.b()", ParseTarget::Expression, |parsed| syntax_tree(parsed));
+    let mut result = String::new();
+    describe_tree(&tree.unwrap().unwrap(), 0, &mut result);
+    assert_eq!(result.trim(), r#"
+Fragment target=expr
+  Expr.MethodCall method=b
+    Expr.Path
+      Path path=a
+"#.trim());
+}
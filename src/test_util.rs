@@ -0,0 +1,68 @@
+// Copyright 2016 Bruno Medeiros
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//!
+//! Helpers for the tests that read files from the filesystem.
+//!
+
+use std::env;
+use std::fs::{ self, File };
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicUsize, ATOMIC_USIZE_INIT, Ordering };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+static TEMP_DIR_COUNT : AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A new empty directory in the system temporary directory, removed with its contents when dropped.
+pub struct TempDir {
+    pub path : PathBuf,
+}
+
+impl TempDir {
+    
+    /// Create a directory with a unique name starting with given prefix.
+    pub fn new(prefix : &str) -> TempDir {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+        loop {
+            let count = TEMP_DIR_COUNT.fetch_add(1, Ordering::SeqCst);
+            let path = env::temp_dir().join(format!("{}_{}_{}", prefix, nanos, count));
+            match fs::create_dir(&path) {
+                Ok(()) => return TempDir { path : path },
+                Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => panic!("Could not create `{}`: {}", path.display(), error),
+            }
+        }
+    }
+    
+    pub fn join<P : AsRef<Path>>(&self, path : P) -> PathBuf {
+        self.path.join(path)
+    }
+    
+    /// Write a file with given path relative to this directory, creating its parent directories.
+    pub fn write_file(&self, path : &str, contents : &str) {
+        let path = self.path.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+    }
+    
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}